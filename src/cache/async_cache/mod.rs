@@ -0,0 +1,209 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements an async facade over the [`ValueCache`] for
+//! applications based on [tokio](https://tokio.rs). It is available only when
+//! the feature `async` is enabled.
+use super::{SimpleCache, ValueCache};
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::future::Future;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, PoisonError};
+use tokio::sync::OnceCell;
+
+#[cfg(test)]
+mod tests;
+
+/// Result of a load shared among all tasks waiting for it.
+type SharedLoadResult<V> = Result<Arc<V>, Arc<dyn Any + Send + Sync>>;
+
+/// A load in progress. It is initialized once the loader finishes.
+type AsyncLoad<V> = Arc<OnceCell<SharedLoadResult<V>>>;
+
+//=============================================================================
+// AsyncCache
+//-----------------------------------------------------------------------------
+/// This struct implements an async facade over a [`ValueCache`]. It adds
+/// loading methods whose loaders are futures, so tasks that miss a key being
+/// loaded by another task wait for it without blocking the executor thread.
+///
+/// The loader runs at most once per key among concurrent tasks, and its
+/// errors are shared with all tasks waiting for the same load without being
+/// cached. If the task running the loader is cancelled, one of the waiting
+/// tasks runs its own loader instead.
+///
+/// The underlying cache never holds its locks across an `.await`, so its
+/// methods can be called directly from async code by [`Self::cache()`].
+pub struct AsyncCache<
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: ValueCache<K, V> + Sync = SimpleCache<K, V>,
+> {
+    cache: C,
+    loads: Mutex<HashMap<K, AsyncLoad<V>>>,
+    _phantom: PhantomData<V>,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync, C: ValueCache<K, V> + Sync>
+    AsyncCache<K, V, C>
+{
+    /// Creates a new `AsyncCache`.
+    ///
+    /// Arguments:
+    /// - `cache`: The cache that will hold the entries;
+    pub fn new(cache: C) -> Self {
+        Self {
+            cache,
+            loads: Mutex::new(HashMap::new()),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the underlying cache.
+    pub fn cache(&self) -> &C {
+        &self.cache
+    }
+
+    /// Gets the value from the cache or loads and inserts it if it is missing.
+    /// See [`Self::get_or_try_insert_with()`] for details.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `loader`: The function that creates the future that loads the value;
+    ///
+    /// Returns the cached or loaded value.
+    pub async fn get_or_insert_with<F, Fut>(&self, key: K, loader: F) -> Arc<V>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = V>,
+    {
+        let ret = self
+            .get_or_try_insert_with(key, || async { Ok::<V, Infallible>(loader().await) })
+            .await;
+        match ret {
+            Ok(value) => value,
+            Err(error) => match *error {},
+        }
+    }
+
+    /// Gets the value from the cache or loads and inserts it if it is missing.
+    ///
+    /// The loader runs at most once per key among concurrent tasks. Tasks that
+    /// miss a key while it is being loaded wait for the in-flight load and
+    /// receive its result. Errors returned by the loader are shared with all
+    /// waiters of the same load but are not cached, so the next miss will run
+    /// the loader again.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `loader`: The function that creates the future that loads the value;
+    ///
+    /// Returns:
+    /// - `Ok(v)`: The cached or loaded value. `v` is a new [`Arc`] that points
+    ///   to it;
+    /// - `Err(e)`: The error returned by the loader;
+    pub async fn get_or_try_insert_with<E, F, Fut>(
+        &self,
+        key: K,
+        loader: F,
+    ) -> Result<Arc<V>, Arc<E>>
+    where
+        E: Send + Sync + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<V, E>>,
+    {
+        if let Some(value) = self.cache.get(&key) {
+            return Ok(value);
+        }
+        let mut loader = Some(loader);
+        loop {
+            let load = {
+                let mut loads = self.loads.lock().unwrap_or_else(PoisonError::into_inner);
+                match loads.get(&key) {
+                    Some(load) => Arc::clone(load),
+                    None => {
+                        if let Some(value) = self.cache.peek(&key) {
+                            return Ok(value);
+                        }
+                        let load = Arc::new(OnceCell::new());
+                        loads.insert(key.clone(), Arc::clone(&load));
+                        load
+                    }
+                }
+            };
+            let ret = load
+                .get_or_init(|| async {
+                    // The loader is only taken by the task that initializes
+                    // the load, which always gets its own error type back.
+                    let loader = loader.take().unwrap();
+                    let ret = match loader().await {
+                        Ok(value) => {
+                            let value = Arc::new(value);
+                            self.cache.insert(key.clone(), &value);
+                            Ok(value)
+                        }
+                        Err(error) => Err(Arc::new(error) as Arc<dyn Any + Send + Sync>),
+                    };
+                    self.finish_load(&key, &load);
+                    ret
+                })
+                .await;
+            match ret {
+                Ok(value) => return Ok(Arc::clone(value)),
+                Err(error) => {
+                    // Waiters that expect another error type run their own
+                    // loader instead.
+                    if let Ok(error) = Arc::clone(error).downcast::<E>() {
+                        return Err(error);
+                    }
+                    self.finish_load(&key, &load);
+                }
+            }
+        }
+    }
+
+    /// Removes a finished load from the loads in progress.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `load`: The finished load;
+    fn finish_load(&self, key: &K, load: &AsyncLoad<V>) {
+        let mut loads = self.loads.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(current) = loads.get(key) {
+            if Arc::ptr_eq(current, load) {
+                loads.remove(key);
+            }
+        }
+    }
+}
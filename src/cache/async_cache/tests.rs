@@ -0,0 +1,163 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use std::cell::Cell;
+use std::time::Duration;
+
+/// Yields to the runtime a few times, letting other tasks run while a loader
+/// is still in progress.
+async fn yield_a_few_times() {
+    for _ in 0..10 {
+        tokio::task::yield_now().await;
+    }
+}
+
+//=============================================================================
+// AsyncCache
+//-----------------------------------------------------------------------------
+#[tokio::test(flavor = "current_thread")]
+async fn test_asynccache_get_or_insert_with() {
+    let c: AsyncCache<u64, u64> = AsyncCache::new(SimpleCache::new(10));
+
+    assert_eq!(*c.get_or_insert_with(1, || async { 10 }).await, 10);
+    assert_eq!(*c.cache().get(&1).unwrap(), 10);
+    let ret = c
+        .get_or_insert_with(1, || async { panic!("Should not be called.") })
+        .await;
+    assert_eq!(*ret, 10);
+    assert!(c.loads.lock().unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_asynccache_get_or_insert_with_concurrent() {
+    let c: AsyncCache<u64, u64> = AsyncCache::new(SimpleCache::new(10));
+    let calls = Cell::new(0);
+    let load = || async {
+        calls.set(calls.get() + 1);
+        yield_a_few_times().await;
+        10
+    };
+
+    // All tasks share the same thread, so waiting for the load must not block
+    let (a, b, c2) = tokio::join!(
+        c.get_or_insert_with(1, load),
+        c.get_or_insert_with(1, load),
+        c.get_or_insert_with(1, load)
+    );
+    assert_eq!((*a, *b, *c2), (10, 10, 10));
+    assert_eq!(calls.get(), 1);
+    assert_eq!(c.cache().len(), 1);
+    assert!(c.loads.lock().unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_asynccache_get_or_try_insert_with_error() {
+    let c: AsyncCache<u64, u64> = AsyncCache::new(SimpleCache::new(10));
+    let calls = Cell::new(0);
+    let load = || async {
+        calls.set(calls.get() + 1);
+        yield_a_few_times().await;
+        Err::<u64, String>(String::from("failed"))
+    };
+
+    let (a, b) = tokio::join!(
+        c.get_or_try_insert_with(1, load),
+        c.get_or_try_insert_with(1, load)
+    );
+    assert_eq!(a.unwrap_err().as_str(), "failed");
+    assert_eq!(b.unwrap_err().as_str(), "failed");
+    assert_eq!(calls.get(), 1);
+    assert!(!c.cache().contains_key(&1));
+    assert!(c.loads.lock().unwrap().is_empty());
+
+    // Errors are not cached
+    let ret = c
+        .get_or_try_insert_with(1, || async { Ok::<u64, String>(20) })
+        .await;
+    assert_eq!(*ret.unwrap(), 20);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_asynccache_get_or_try_insert_with_other_error_type() {
+    let c: AsyncCache<u64, u64> = AsyncCache::new(SimpleCache::new(10));
+
+    let (a, b) = tokio::join!(
+        c.get_or_try_insert_with(1, || async {
+            yield_a_few_times().await;
+            Err::<u64, String>(String::from("failed"))
+        }),
+        c.get_or_try_insert_with(1, || async { Err::<u64, u32>(5) })
+    );
+    assert_eq!(a.unwrap_err().as_str(), "failed");
+    assert_eq!(*b.unwrap_err(), 5);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_asynccache_get_or_insert_with_cancelled() {
+    let c: AsyncCache<u64, u64> = AsyncCache::new(SimpleCache::new(10));
+
+    // The first task is cancelled while loading
+    let ret = tokio::time::timeout(
+        Duration::from_millis(10),
+        c.get_or_insert_with(1, || async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            10
+        }),
+    )
+    .await;
+    assert!(ret.is_err());
+    assert!(!c.cache().contains_key(&1));
+
+    // The next task runs its own loader
+    assert_eq!(*c.get_or_insert_with(1, || async { 20 }).await, 20);
+    assert!(c.loads.lock().unwrap().is_empty());
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn test_asynccache_get_or_insert_with_cancelled_waiter() {
+    let c: AsyncCache<u64, u64> = AsyncCache::new(SimpleCache::new(10));
+
+    // The leader is cancelled while another task is waiting for it
+    let (a, b) = tokio::join!(
+        tokio::time::timeout(
+            Duration::from_millis(10),
+            c.get_or_insert_with(1, || async {
+                tokio::time::sleep(Duration::from_secs(60)).await;
+                10
+            }),
+        ),
+        c.get_or_insert_with(1, || async { 20 })
+    );
+    assert!(a.is_err());
+    assert_eq!(*b, 20);
+    assert_eq!(*c.cache().get(&1).unwrap(), 20);
+}
@@ -0,0 +1,276 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements a doubly linked list that stores its nodes inside a
+//! vector. Nodes are addressed by their index in the vector, allowing the
+//! cache engines to keep the position of each entry inside a [`HashMap`] and
+//! reorder them in constant time.
+//!
+//! [`HashMap`]: std::collections::HashMap
+
+#[cfg(test)]
+mod tests;
+
+/// Index used to mark the absence of a node.
+const NIL: usize = usize::MAX;
+
+//=============================================================================
+// IndexedListNode
+//-----------------------------------------------------------------------------
+/// A node of the [`IndexedList`]. Free nodes have no value and are chained
+/// using the field `next`.
+struct IndexedListNode<T> {
+    value: Option<T>,
+    prev: usize,
+    next: usize,
+}
+
+//=============================================================================
+// IndexedList
+//-----------------------------------------------------------------------------
+/// This struct implements a doubly linked list whose nodes are stored in a
+/// vector. The index of a node remains valid until it is removed from the list,
+/// even if other nodes are inserted or removed.
+///
+/// The front of the list is the most recent entry while the back is the oldest.
+pub(crate) struct IndexedList<T> {
+    nodes: Vec<IndexedListNode<T>>,
+    head: usize,
+    tail: usize,
+    free: usize,
+    len: usize,
+}
+
+impl<T> IndexedList<T> {
+    /// Creates a new empty `IndexedList`.
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            free: NIL,
+            len: 0,
+        }
+    }
+
+    /// Returns the number of entries in the list.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the index of the oldest entry.
+    pub fn back(&self) -> Option<usize> {
+        if self.tail == NIL {
+            None
+        } else {
+            Some(self.tail)
+        }
+    }
+
+    /// Returns a reference to the value of a node.
+    ///
+    /// Arguments:
+    /// - `index`: The index of the node;
+    ///
+    /// It panics if the index does not point to a valid node.
+    pub fn get(&self, index: usize) -> &T {
+        self.nodes[index].value.as_ref().unwrap()
+    }
+
+    /// Returns a mutable reference to the value of a node.
+    ///
+    /// Arguments:
+    /// - `index`: The index of the node;
+    ///
+    /// It panics if the index does not point to a valid node.
+    pub fn get_mut(&mut self, index: usize) -> &mut T {
+        self.nodes[index].value.as_mut().unwrap()
+    }
+
+    /// Adds a new value to the front of the list.
+    ///
+    /// Arguments:
+    /// - `value`: The value to be added;
+    ///
+    /// Returns the index of the new node.
+    pub fn push_front(&mut self, value: T) -> usize {
+        let index = if self.free == NIL {
+            self.nodes.push(IndexedListNode {
+                value: Some(value),
+                prev: NIL,
+                next: NIL,
+            });
+            self.nodes.len() - 1
+        } else {
+            let index = self.free;
+            self.free = self.nodes[index].next;
+            self.nodes[index].value = Some(value);
+            index
+        };
+        self.link_front(index);
+        self.len += 1;
+        index
+    }
+
+    /// Removes a node from the list.
+    ///
+    /// Arguments:
+    /// - `index`: The index of the node;
+    ///
+    /// Returns the value of the removed node. It panics if the index does not
+    /// point to a valid node.
+    pub fn remove(&mut self, index: usize) -> T {
+        let value = self.nodes[index].value.take().unwrap();
+        self.unlink(index);
+        self.nodes[index].next = self.free;
+        self.free = index;
+        self.len -= 1;
+        value
+    }
+
+    /// Removes the oldest entry of the list.
+    ///
+    /// Returns the value of the removed node or `None` if the list is empty.
+    pub fn pop_back(&mut self) -> Option<T> {
+        self.back().map(|index| self.remove(index))
+    }
+
+    /// Moves the node to the front of the list.
+    ///
+    /// Arguments:
+    /// - `index`: The index of the node;
+    pub fn move_to_front(&mut self, index: usize) {
+        if self.head != index {
+            self.unlink(index);
+            self.link_front(index);
+        }
+    }
+
+    /// Removes all entries that match the given predicate. The list is visited
+    /// from the oldest to the most recent entry.
+    ///
+    /// Arguments:
+    /// - `predicate`: The predicate that selects the entries to be removed;
+    ///
+    /// Returns the removed values in the order they were visited.
+    pub fn remove_if<F: FnMut(&T) -> bool>(&mut self, mut predicate: F) -> Vec<T> {
+        let mut removed = Vec::new();
+        let mut index = self.tail;
+        while index != NIL {
+            let prev = self.nodes[index].prev;
+            if predicate(self.get(index)) {
+                removed.push(self.remove(index));
+            }
+            index = prev;
+        }
+        removed
+    }
+
+    /// Returns an iterator that visits the entries from the oldest to the most
+    /// recent.
+    pub fn iter(&self) -> IndexedListIter<'_, T> {
+        IndexedListIter {
+            list: self,
+            index: self.tail,
+        }
+    }
+
+    /// Removes all entries from the list.
+    ///
+    /// Returns the removed values from the oldest to the most recent.
+    pub fn drain(&mut self) -> Vec<T> {
+        let ret = self.remove_if(|_| true);
+        self.clear();
+        ret
+    }
+
+    /// Removes all entries from the list.
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.head = NIL;
+        self.tail = NIL;
+        self.free = NIL;
+        self.len = 0;
+    }
+
+    /// Inserts a detached node at the front of the list.
+    fn link_front(&mut self, index: usize) {
+        self.nodes[index].prev = NIL;
+        self.nodes[index].next = self.head;
+        if self.head == NIL {
+            self.tail = index;
+        } else {
+            self.nodes[self.head].prev = index;
+        }
+        self.head = index;
+    }
+
+    /// Detaches a node from the list.
+    fn unlink(&mut self, index: usize) {
+        let prev = self.nodes[index].prev;
+        let next = self.nodes[index].next;
+        if prev == NIL {
+            self.head = next;
+        } else {
+            self.nodes[prev].next = next;
+        }
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            self.nodes[next].prev = prev;
+        }
+        self.nodes[index].prev = NIL;
+        self.nodes[index].next = NIL;
+    }
+}
+
+//=============================================================================
+// IndexedListIter
+//-----------------------------------------------------------------------------
+/// Iterator returned by [`IndexedList::iter()`]. It visits the entries from the
+/// oldest to the most recent.
+pub(crate) struct IndexedListIter<'a, T> {
+    list: &'a IndexedList<T>,
+    index: usize,
+}
+
+impl<'a, T> Iterator for IndexedListIter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index == NIL {
+            return None;
+        }
+        let node = &self.list.nodes[self.index];
+        self.index = node.prev;
+        node.value.as_ref()
+    }
+}
@@ -0,0 +1,168 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+
+fn drain_from_back(list: &mut IndexedList<u64>) -> Vec<u64> {
+    let mut ret = Vec::new();
+    while let Some(v) = list.pop_back() {
+        ret.push(v);
+    }
+    ret
+}
+
+//=============================================================================
+// IndexedList
+//-----------------------------------------------------------------------------
+#[test]
+fn test_indexedlist_impl_new() {
+    let l: IndexedList<u64> = IndexedList::new();
+    assert_eq!(l.len(), 0);
+    assert!(l.back().is_none());
+    assert_eq!(l.head, NIL);
+    assert_eq!(l.tail, NIL);
+    assert_eq!(l.free, NIL);
+}
+
+#[test]
+fn test_indexedlist_impl_push_front() {
+    let mut l: IndexedList<u64> = IndexedList::new();
+
+    for v in 0..10 {
+        let index = l.push_front(v);
+        assert_eq!(*l.get(index), v);
+        assert_eq!(l.len(), (v + 1) as usize);
+        assert_eq!(l.head, index);
+    }
+    assert_eq!(*l.get(l.back().unwrap()), 0);
+    assert_eq!(drain_from_back(&mut l), (0..10).collect::<Vec<u64>>());
+    assert_eq!(l.len(), 0);
+}
+
+#[test]
+fn test_indexedlist_impl_get_mut() {
+    let mut l: IndexedList<u64> = IndexedList::new();
+
+    let index = l.push_front(1);
+    *l.get_mut(index) = 2;
+    assert_eq!(*l.get(index), 2);
+}
+
+#[test]
+fn test_indexedlist_impl_remove() {
+    let mut l: IndexedList<u64> = IndexedList::new();
+
+    let indexes: Vec<usize> = (0..5).map(|v| l.push_front(v)).collect();
+    // Middle, head and tail
+    assert_eq!(l.remove(indexes[2]), 2);
+    assert_eq!(l.remove(indexes[4]), 4);
+    assert_eq!(l.remove(indexes[0]), 0);
+    assert_eq!(l.len(), 2);
+
+    // Removed nodes are reused
+    let index = l.push_front(10);
+    assert!(index == indexes[0] || index == indexes[2] || index == indexes[4]);
+    assert_eq!(l.nodes.len(), 5);
+
+    assert_eq!(drain_from_back(&mut l), vec![1, 3, 10]);
+}
+
+#[test]
+fn test_indexedlist_impl_move_to_front() {
+    let mut l: IndexedList<u64> = IndexedList::new();
+
+    let indexes: Vec<usize> = (0..5).map(|v| l.push_front(v)).collect();
+    l.move_to_front(indexes[0]);
+    l.move_to_front(indexes[2]);
+    l.move_to_front(indexes[2]);
+    l.move_to_front(indexes[4]);
+    assert_eq!(drain_from_back(&mut l), vec![1, 3, 0, 2, 4]);
+}
+
+#[test]
+fn test_indexedlist_impl_remove_if() {
+    let mut l: IndexedList<u64> = IndexedList::new();
+
+    for v in 0..10 {
+        l.push_front(v);
+    }
+    assert_eq!(l.remove_if(|v| v % 3 == 0), vec![0, 3, 6, 9]);
+    assert_eq!(l.len(), 6);
+    assert!(l.remove_if(|_| false).is_empty());
+    assert_eq!(drain_from_back(&mut l), vec![1, 2, 4, 5, 7, 8]);
+}
+
+#[test]
+fn test_indexedlist_impl_iter() {
+    let mut l: IndexedList<u64> = IndexedList::new();
+    assert_eq!(l.iter().count(), 0);
+
+    let mut indexes = Vec::new();
+    for v in 0..10 {
+        indexes.push(l.push_front(v));
+    }
+    l.move_to_front(indexes[0]);
+    l.remove(indexes[5]);
+    assert_eq!(
+        l.iter().copied().collect::<Vec<u64>>(),
+        vec![1, 2, 3, 4, 6, 7, 8, 9, 0]
+    );
+}
+
+#[test]
+fn test_indexedlist_impl_drain() {
+    let mut l: IndexedList<u64> = IndexedList::new();
+
+    for v in 0..10 {
+        l.push_front(v);
+    }
+    assert_eq!(l.drain(), (0..10).collect::<Vec<u64>>());
+    assert_eq!(l.len(), 0);
+    assert_eq!(l.nodes.len(), 0);
+    assert!(l.drain().is_empty());
+}
+
+#[test]
+fn test_indexedlist_impl_clear() {
+    let mut l: IndexedList<u64> = IndexedList::new();
+
+    for v in 0..10 {
+        l.push_front(v);
+    }
+    l.clear();
+    assert_eq!(l.len(), 0);
+    assert!(l.back().is_none());
+    assert!(l.pop_back().is_none());
+
+    let index = l.push_front(1);
+    assert_eq!(index, 0);
+    assert_eq!(drain_from_back(&mut l), vec![1]);
+}
@@ -0,0 +1,252 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements the single-flight loading used by the caches of the
+//! [`crate::cache`] module. It guarantees that concurrent misses of the same
+//! key run the loader only once while the other callers wait for its result.
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+#[cfg(test)]
+mod tests;
+
+/// Type of the errors shared among the callers waiting for the same load.
+type SharedLoadError = Arc<dyn Any + Send + Sync>;
+
+//=============================================================================
+// LoadState
+//-----------------------------------------------------------------------------
+/// State of an in-flight load.
+enum LoadState<V> {
+    /// The loader is still running.
+    Pending,
+    /// The loader finished successfully.
+    Loaded(Arc<V>),
+    /// The loader returned an error.
+    Failed(SharedLoadError),
+    /// The loader panicked before producing a result.
+    Abandoned,
+}
+
+//=============================================================================
+// InFlightLoad
+//-----------------------------------------------------------------------------
+/// An in-flight load shared by all callers that missed the same key.
+struct InFlightLoad<V> {
+    state: Mutex<LoadState<V>>,
+    done: Condvar,
+}
+
+impl<V> InFlightLoad<V> {
+    /// Creates a new pending `InFlightLoad`.
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(LoadState::Pending),
+            done: Condvar::new(),
+        }
+    }
+
+    /// Publishes the final state of the load and wakes up all waiters.
+    ///
+    /// Arguments:
+    /// - `state`: The final state;
+    fn complete(&self, state: LoadState<V>) {
+        let mut s = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        *s = state;
+        self.done.notify_all();
+    }
+
+    /// Waits until the load finishes.
+    ///
+    /// Returns:
+    /// - `Some(Ok(v))`: The loaded value;
+    /// - `Some(Err(e))`: The error returned by the loader;
+    /// - `None`: If the load was abandoned;
+    fn wait(&self) -> Option<Result<Arc<V>, SharedLoadError>> {
+        let mut s = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            match &*s {
+                LoadState::Pending => s = self.done.wait(s).unwrap_or_else(PoisonError::into_inner),
+                LoadState::Loaded(v) => return Some(Ok(Arc::clone(v))),
+                LoadState::Failed(e) => return Some(Err(Arc::clone(e))),
+                LoadState::Abandoned => return None,
+            }
+        }
+    }
+}
+
+//=============================================================================
+// SingleFlight
+//-----------------------------------------------------------------------------
+/// This struct tracks the loads in progress for each key. The first caller
+/// that misses a key becomes the leader and runs the loader, while all other
+/// callers for the same key wait for the leader to finish.
+///
+/// Errors are shared with all waiters of the same load but are never cached,
+/// so the next miss of the key will run the loader again.
+pub(crate) struct SingleFlight<K, V> {
+    loads: Mutex<HashMap<K, Arc<InFlightLoad<V>>>>,
+}
+
+impl<K: Eq + Hash + Clone, V> SingleFlight<K, V> {
+    /// Creates a new `SingleFlight` without loads in progress.
+    pub fn new() -> Self {
+        Self {
+            loads: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the number of loads in progress.
+    #[cfg(test)]
+    pub fn in_flight(&self) -> usize {
+        self.loads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len()
+    }
+
+    /// Loads the value associated with the key, running the loader at most
+    /// once among all concurrent callers.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `lookup`: Looks the key up in the cache. It is used to detect values
+    ///   inserted by a load that finished before this call started;
+    /// - `loader`: The loader;
+    /// - `store`: Stores the loaded value in the cache;
+    ///
+    /// Returns:
+    /// - `Ok(v)`: The loaded value;
+    /// - `Err(e)`: The error returned by the loader;
+    pub fn load<E, L, F, S>(&self, key: K, lookup: L, loader: F, store: S) -> Result<Arc<V>, Arc<E>>
+    where
+        E: Send + Sync + 'static,
+        L: Fn(&K) -> Option<Arc<V>>,
+        F: FnOnce() -> Result<V, E>,
+        S: FnOnce(K, &Arc<V>),
+    {
+        loop {
+            let (load, leader) = {
+                let mut loads = self.loads.lock().unwrap_or_else(PoisonError::into_inner);
+                match loads.get(&key) {
+                    Some(load) => (Arc::clone(load), false),
+                    None => {
+                        if let Some(value) = lookup(&key) {
+                            return Ok(value);
+                        }
+                        let load = Arc::new(InFlightLoad::new());
+                        loads.insert(key.clone(), Arc::clone(&load));
+                        (load, true)
+                    }
+                }
+            };
+            if leader {
+                return self.lead(key, &load, loader, store);
+            }
+            match load.wait() {
+                Some(Ok(value)) => return Ok(value),
+                Some(Err(error)) => {
+                    // Waiters that expect another error type run their own
+                    // loader instead.
+                    if let Ok(error) = error.downcast::<E>() {
+                        return Err(error);
+                    }
+                }
+                None => (),
+            }
+        }
+    }
+
+    /// Runs the loader as the leader of the load.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `load`: The load registered for the key;
+    /// - `loader`: The loader;
+    /// - `store`: Stores the loaded value in the cache;
+    fn lead<E, F, S>(
+        &self,
+        key: K,
+        load: &Arc<InFlightLoad<V>>,
+        loader: F,
+        store: S,
+    ) -> Result<Arc<V>, Arc<E>>
+    where
+        E: Send + Sync + 'static,
+        F: FnOnce() -> Result<V, E>,
+        S: FnOnce(K, &Arc<V>),
+    {
+        let mut guard = LeaderGuard {
+            flight: self,
+            key,
+            load,
+            state: LoadState::Abandoned,
+        };
+        match loader() {
+            Ok(value) => {
+                let value = Arc::new(value);
+                store(guard.key.clone(), &value);
+                guard.state = LoadState::Loaded(Arc::clone(&value));
+                Ok(value)
+            }
+            Err(error) => {
+                let error = Arc::new(error);
+                guard.state = LoadState::Failed(Arc::clone(&error) as SharedLoadError);
+                Err(error)
+            }
+        }
+    }
+}
+
+//=============================================================================
+// LeaderGuard
+//-----------------------------------------------------------------------------
+/// Finishes the load of the leader when dropped, even if the loader panics.
+struct LeaderGuard<'a, K: Eq + Hash + Clone, V> {
+    flight: &'a SingleFlight<K, V>,
+    key: K,
+    load: &'a Arc<InFlightLoad<V>>,
+    state: LoadState<V>,
+}
+
+impl<'a, K: Eq + Hash + Clone, V> Drop for LeaderGuard<'a, K, V> {
+    fn drop(&mut self) {
+        self.flight
+            .loads
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .remove(&self.key);
+        let state = std::mem::replace(&mut self.state, LoadState::Abandoned);
+        self.load.complete(state);
+    }
+}
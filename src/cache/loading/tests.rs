@@ -0,0 +1,202 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Barrier;
+use std::thread;
+use std::time::Duration;
+
+/// Number of threads used by the concurrent tests.
+const THREADS: usize = 8;
+
+/// Runs [`SingleFlight::load()`] concurrently from [`THREADS`] threads using
+/// `slot` as the cache. The loader takes a while to complete in order to let
+/// all threads join the same load.
+///
+/// Arguments:
+/// - `flight`: The single flight;
+/// - `slot`: The cache;
+/// - `calls`: Counts the number of times the loader was called;
+/// - `result`: The result of the loader;
+///
+/// Returns the results of all threads.
+fn load_concurrently(
+    flight: &Arc<SingleFlight<u64, u64>>,
+    slot: &Arc<Mutex<Option<Arc<u64>>>>,
+    calls: &Arc<AtomicUsize>,
+    result: Result<u64, String>,
+) -> Vec<Result<Arc<u64>, Arc<String>>> {
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let mut threads = Vec::new();
+    for _ in 0..THREADS {
+        let flight = Arc::clone(flight);
+        let slot = Arc::clone(slot);
+        let calls = Arc::clone(calls);
+        let barrier = Arc::clone(&barrier);
+        let result = result.clone();
+        threads.push(thread::spawn(move || {
+            barrier.wait();
+            flight.load(
+                1,
+                |_| slot.lock().unwrap().clone(),
+                || {
+                    calls.fetch_add(1, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(200));
+                    result
+                },
+                |_, value| *slot.lock().unwrap() = Some(Arc::clone(value)),
+            )
+        }));
+    }
+    threads.into_iter().map(|t| t.join().unwrap()).collect()
+}
+
+//=============================================================================
+// SingleFlight
+//-----------------------------------------------------------------------------
+#[test]
+fn test_singleflight_load() {
+    let flight: SingleFlight<u64, u64> = SingleFlight::new();
+    let slot: Mutex<Option<Arc<u64>>> = Mutex::new(None);
+
+    let ret = flight.load::<String, _, _, _>(
+        1,
+        |_| slot.lock().unwrap().clone(),
+        || Ok(10),
+        |_, value| *slot.lock().unwrap() = Some(Arc::clone(value)),
+    );
+    assert_eq!(*ret.unwrap(), 10);
+    assert_eq!(**slot.lock().unwrap().as_ref().unwrap(), 10);
+    assert_eq!(flight.in_flight(), 0);
+
+    // The value found by lookup is used
+    let ret = flight.load::<String, _, _, _>(
+        1,
+        |_| slot.lock().unwrap().clone(),
+        || panic!("Should not be called."),
+        |_, _| panic!("Should not be called."),
+    );
+    assert_eq!(*ret.unwrap(), 10);
+}
+
+#[test]
+fn test_singleflight_load_concurrent() {
+    let flight = Arc::new(SingleFlight::new());
+    let slot = Arc::new(Mutex::new(None));
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let results = load_concurrently(&flight, &slot, &calls, Ok(10));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    for ret in results {
+        assert_eq!(*ret.unwrap(), 10);
+    }
+    assert_eq!(flight.in_flight(), 0);
+}
+
+#[test]
+fn test_singleflight_load_concurrent_error() {
+    let flight = Arc::new(SingleFlight::new());
+    let slot = Arc::new(Mutex::new(None));
+    let calls = Arc::new(AtomicUsize::new(0));
+
+    let results = load_concurrently(&flight, &slot, &calls, Err("failed".into()));
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    for ret in results {
+        assert_eq!(ret.unwrap_err().as_str(), "failed");
+    }
+    assert!(slot.lock().unwrap().is_none());
+    assert_eq!(flight.in_flight(), 0);
+
+    // Errors are not cached
+    let results = load_concurrently(&flight, &slot, &calls, Ok(10));
+    assert_eq!(calls.load(Ordering::SeqCst), 2);
+    for ret in results {
+        assert_eq!(*ret.unwrap(), 10);
+    }
+}
+
+#[test]
+fn test_singleflight_load_leader_panic() {
+    let flight: Arc<SingleFlight<u64, u64>> = Arc::new(SingleFlight::new());
+    let started = Arc::new(Barrier::new(2));
+
+    let leader = {
+        let flight = Arc::clone(&flight);
+        let started = Arc::clone(&started);
+        thread::spawn(move || {
+            let _ = flight.load::<String, _, _, _>(
+                1,
+                |_| None,
+                || {
+                    started.wait();
+                    thread::sleep(Duration::from_millis(200));
+                    panic!("Loader panic.")
+                },
+                |_, _| (),
+            );
+        })
+    };
+    started.wait();
+    // The waiter takes over the abandoned load
+    let ret = flight.load::<String, _, _, _>(1, |_| None, || Ok(20), |_, _| ());
+    assert!(leader.join().is_err());
+    assert_eq!(*ret.unwrap(), 20);
+    assert_eq!(flight.in_flight(), 0);
+}
+
+#[test]
+fn test_singleflight_load_other_error_type() {
+    let flight: Arc<SingleFlight<u64, u64>> = Arc::new(SingleFlight::new());
+    let started = Arc::new(Barrier::new(2));
+
+    let leader = {
+        let flight = Arc::clone(&flight);
+        let started = Arc::clone(&started);
+        thread::spawn(move || {
+            flight.load::<String, _, _, _>(
+                1,
+                |_| None,
+                || {
+                    started.wait();
+                    thread::sleep(Duration::from_millis(200));
+                    Err("failed".into())
+                },
+                |_, _| (),
+            )
+        })
+    };
+    started.wait();
+    // The error of the leader cannot be returned as an u32
+    let ret = flight.load::<u32, _, _, _>(1, |_| None, || Err(5), |_, _| ());
+    assert_eq!(leader.join().unwrap().unwrap_err().as_str(), "failed");
+    assert_eq!(*ret.unwrap_err(), 5);
+}
@@ -0,0 +1,217 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements a cache engine that keeps the exact least recently
+//! used order of its entries with constant time lookups, insertions and
+//! evictions.
+use super::list::IndexedList;
+use super::{CacheEngine, CacheEviction, EvictionReason, SimpleCache};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests;
+
+//=============================================================================
+// LRUCacheEngine
+//-----------------------------------------------------------------------------
+/// This struct implements a [`CacheEngine`] that drops the least recently used
+/// entry when it reaches its maximum capacity.
+///
+/// It is the default engine of [`SimpleCache`]. Unlike the
+/// [`SimpleCacheEngine`](super::SimpleCacheEngine), the entries are kept in a
+/// linked list ordered by their last use, so finding the oldest entry does not
+/// require a scan of the whole cache.
+///
+/// This struct is not thread safe and must have its concurrency protected by
+/// an external [`std::sync::RwLock`] or other synchronization primitive.
+pub struct LRUCacheEngine<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> {
+    map: HashMap<K, usize>,
+    list: IndexedList<(K, Arc<V>)>,
+    max_size: usize,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> LRUCacheEngine<K, V> {
+    /// Creates a new `LRUCacheEngine` with a given capacity.
+    ///
+    /// Arguments:
+    /// - `max_size`: Maximum number of items in the cache;
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            map: HashMap::new(),
+            list: IndexedList::new(),
+            max_size,
+        }
+    }
+
+    /// Returns the maximum number of items in the cache.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// This method removes the least recently used entry.
+    ///
+    /// Returns the key and the value of the removed entry if any.
+    fn remove_oldest(&mut self) -> Option<(K, Arc<V>)> {
+        let (key, value) = self.list.pop_back()?;
+        self.map.remove(&key);
+        Some((key, value))
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> CacheEngine<K, V>
+    for LRUCacheEngine<K, V>
+{
+    fn get<Q>(&mut self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        self.list.move_to_front(index);
+        Some(Arc::clone(&self.list.get(index).1))
+    }
+
+    fn insert_with_evictions(
+        &mut self,
+        key: K,
+        value: &Arc<V>,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
+        if let Some(&index) = self.map.get(&key) {
+            let old = std::mem::replace(&mut self.list.get_mut(index).1, Arc::clone(value));
+            self.list.move_to_front(index);
+            evictions.push(CacheEviction::new(key, old, EvictionReason::Replaced));
+        } else {
+            let index = self.list.push_front((key.clone(), Arc::clone(value)));
+            self.map.insert(key, index);
+            if self.map.len() > self.max_size {
+                if let Some((k, v)) = self.remove_oldest() {
+                    evictions.push(CacheEviction::new(k, v, EvictionReason::Capacity));
+                }
+            }
+        }
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<CacheEviction<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let index = self.map.remove(key)?;
+        let (key, value) = self.list.remove(index);
+        Some(CacheEviction::new(key, value, EvictionReason::Removed))
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    fn peek<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        Some(Arc::clone(&self.list.get(index).1))
+    }
+
+    fn entries(&self) -> Vec<(K, Arc<V>)> {
+        self.list
+            .iter()
+            .map(|(key, value)| (key.clone(), Arc::clone(value)))
+            .collect()
+    }
+
+    fn retain_with_evictions<F>(
+        &mut self,
+        mut predicate: F,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) where
+        F: FnMut(&K, &V) -> bool,
+    {
+        for (key, value) in self.list.remove_if(|(key, value)| !predicate(key, value)) {
+            self.map.remove(&key);
+            evictions.push(CacheEviction::new(key, value, EvictionReason::Removed));
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.max_size
+    }
+
+    fn set_capacity_with_evictions(
+        &mut self,
+        capacity: usize,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
+        self.max_size = capacity;
+        while self.map.len() > self.max_size {
+            match self.remove_oldest() {
+                Some((k, v)) => evictions.push(CacheEviction::new(k, v, EvictionReason::Capacity)),
+                None => break,
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.list.clear();
+    }
+
+    fn clear_with_evictions(&mut self, evictions: &mut Vec<CacheEviction<K, V>>) {
+        for (key, value) in self.list.drain() {
+            evictions.push(CacheEviction::new(key, value, EvictionReason::Cleared));
+        }
+        self.map.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.list.len() == 0
+    }
+}
+
+//=============================================================================
+// LRUCache
+//-----------------------------------------------------------------------------
+/// A thread safe [`SimpleCache`] that uses the [`LRUCacheEngine`]. It is the
+/// same type created by [`SimpleCache::new()`].
+pub type LRUCache<K, V> = SimpleCache<K, V, LRUCacheEngine<K, V>>;
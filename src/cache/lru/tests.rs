@@ -0,0 +1,353 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use crate::cache::ValueCache;
+
+//=============================================================================
+// LRUCacheEngine
+//-----------------------------------------------------------------------------
+#[test]
+fn test_lrucacheengine_impl_new() {
+    let e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(10);
+    assert_eq!(e.map.len(), 0);
+    assert_eq!(e.list.len(), 0);
+    assert_eq!(e.max_size(), 10);
+}
+
+#[test]
+fn test_lrucacheengine_impl_remove_oldest() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(10);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(key));
+    }
+    for key in 0..10 {
+        assert_eq!(e.len(), (10 - key) as usize);
+        e.remove_oldest();
+        assert_eq!(e.len(), (10 - key - 1) as usize);
+        assert!(!e.map.contains_key(&key));
+    }
+    e.remove_oldest();
+    assert!(e.is_empty());
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_get() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(10);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(key + 100));
+    }
+    for key in 0..10 {
+        assert_eq!(*e.get(&key).unwrap(), key + 100);
+        // The next key becomes the oldest one
+        assert_eq!(e.list.get(e.list.back().unwrap()).0, (key + 1) % 10);
+    }
+    assert!(e.get(&10).is_none());
+
+    // Use the keys in reverse order, making 9 the oldest entry
+    for key in (0..10).rev() {
+        e.get(&key).unwrap();
+    }
+    assert_eq!(e.list.get(e.list.back().unwrap()).0, 9);
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_insert() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(10);
+
+    // Adding 10 entries
+    for key in 0..10 {
+        assert_eq!(e.len(), key as usize);
+        let value = Arc::new(key + 100);
+        e.insert(key, &value);
+        assert_eq!(e.len(), (key + 1) as usize);
+        assert_eq!(*e.get(&key).unwrap(), *value);
+    }
+
+    // Replacing entries
+    for key in 0..10 {
+        let value = Arc::new(key + 1000);
+        e.insert(key, &value);
+        assert_eq!(e.len(), 10);
+        assert_eq!(e.list.get(e.list.back().unwrap()).0, (key + 1) % 10);
+        assert_eq!(*e.get(&key).unwrap(), *value);
+    }
+
+    // Adding 10 new entries
+    for key in 10..20 {
+        let value = Arc::new(key + 1000);
+        e.insert(key, &value);
+        assert_eq!(e.len(), 10);
+        assert_eq!(*e.get(&key).unwrap(), *value);
+
+        // The older key will always be the one with the smallest key
+        assert!(e.get(&(key - 10)).is_none());
+    }
+    assert_eq!(e.map.len(), e.list.len());
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_insert_recency() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(3);
+
+    e.insert(1, &Arc::new(1));
+    e.insert(2, &Arc::new(2));
+    e.insert(3, &Arc::new(3));
+    // 1 becomes the most recent
+    e.get(&1).unwrap();
+    e.insert(4, &Arc::new(4));
+    assert!(e.get(&2).is_none());
+    // 3 is now the oldest
+    e.insert(5, &Arc::new(5));
+    assert!(e.get(&3).is_none());
+    assert!(e.get(&1).is_some());
+    assert!(e.get(&4).is_some());
+    assert!(e.get(&5).is_some());
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_insert_with_evictions() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(2);
+
+    let mut evictions = Vec::new();
+    e.insert_with_evictions(1, &Arc::new(1), &mut evictions);
+    e.insert_with_evictions(2, &Arc::new(2), &mut evictions);
+    assert!(evictions.is_empty());
+
+    e.insert_with_evictions(1, &Arc::new(10), &mut evictions);
+    assert_eq!(evictions.len(), 1);
+    assert_eq!(evictions[0].key, 1);
+    assert_eq!(*evictions[0].value, 1);
+    assert_eq!(evictions[0].reason, EvictionReason::Replaced);
+
+    evictions.clear();
+    e.insert_with_evictions(3, &Arc::new(3), &mut evictions);
+    assert_eq!(evictions.len(), 1);
+    assert_eq!(evictions[0].key, 2);
+    assert_eq!(*evictions[0].value, 2);
+    assert_eq!(evictions[0].reason, EvictionReason::Capacity);
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_remove() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(10);
+
+    for key in 0..5 {
+        e.insert(key, &Arc::new(key + 100));
+    }
+    let removed = e.remove(&2).unwrap();
+    assert_eq!(removed.key, 2);
+    assert_eq!(*removed.value, 102);
+    assert_eq!(removed.reason, EvictionReason::Removed);
+    assert!(e.remove(&2).is_none());
+    assert_eq!(e.len(), 4);
+    assert_eq!(e.map.len(), 4);
+
+    let removed = e.remove(&0).unwrap();
+    assert_eq!(removed.key, 0);
+    assert_eq!(e.list.get(e.list.back().unwrap()).0, 1);
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_peek_contains_key() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(3);
+
+    for key in 1..4 {
+        e.insert(key, &Arc::new(key * 10));
+    }
+    // peek() and contains_key() do not change the recency
+    assert_eq!(*e.peek(&1).unwrap(), 10);
+    assert!(e.contains_key(&1));
+    assert_eq!(e.list.get(e.list.back().unwrap()).0, 1);
+    assert!(e.peek(&4).is_none());
+    assert!(!e.contains_key(&4));
+
+    // get() does
+    e.get(&1).unwrap();
+    assert_eq!(e.list.get(e.list.back().unwrap()).0, 2);
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_string_keys() {
+    let mut e: LRUCacheEngine<String, u64> = LRUCacheEngine::new(2);
+
+    e.insert(String::from("a"), &Arc::new(1));
+    e.insert(String::from("b"), &Arc::new(2));
+    assert_eq!(*e.get("a").unwrap(), 1);
+    assert_eq!(*e.peek("b").unwrap(), 2);
+    let mut evictions = Vec::new();
+    e.insert_with_evictions(String::from("c"), &Arc::new(3), &mut evictions);
+    assert_eq!(evictions.len(), 1);
+    assert_eq!(evictions[0].key, "b");
+    assert!(e.contains_key("a"));
+    assert!(!e.contains_key("b"));
+    assert_eq!(e.remove("c").unwrap().key, "c");
+    assert_eq!(e.len(), 1);
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_entries() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(10);
+    assert!(e.entries().is_empty());
+
+    for key in 0..4 {
+        e.insert(key, &Arc::new(key + 10));
+    }
+    e.get(&1).unwrap();
+    let entries: Vec<(u64, u64)> = e.entries().into_iter().map(|(k, v)| (k, *v)).collect();
+    assert_eq!(entries, vec![(0, 10), (2, 12), (3, 13), (1, 11)]);
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_retain_with_evictions() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(10);
+
+    for key in 0..6 {
+        e.insert(key, &Arc::new(key + 10));
+    }
+    let mut evictions = Vec::new();
+    e.retain_with_evictions(|k, _| k % 2 == 1, &mut evictions);
+    // From the oldest to the most recent
+    let removed: Vec<(u64, EvictionReason)> = evictions.iter().map(|e| (e.key, e.reason)).collect();
+    assert_eq!(
+        removed,
+        vec![
+            (0, EvictionReason::Removed),
+            (2, EvictionReason::Removed),
+            (4, EvictionReason::Removed)
+        ]
+    );
+    assert_eq!(e.map.len(), 3);
+    assert_eq!(e.keys(), vec![1, 3, 5]);
+
+    // The remaining entries keep their order
+    e.insert(6, &Arc::new(16));
+    e.retain(|_, v| *v != 13);
+    assert_eq!(e.keys(), vec![1, 5, 6]);
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_set_capacity_with_evictions() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(10);
+
+    for key in 0..6 {
+        e.insert(key, &Arc::new(key));
+    }
+    e.get(&1).unwrap();
+    let mut evictions = Vec::new();
+    e.set_capacity_with_evictions(2, &mut evictions);
+    assert_eq!(e.capacity(), 2);
+    assert_eq!(e.max_size(), 2);
+    let removed: Vec<(u64, EvictionReason)> = evictions.iter().map(|e| (e.key, e.reason)).collect();
+    assert_eq!(
+        removed,
+        vec![
+            (0, EvictionReason::Capacity),
+            (2, EvictionReason::Capacity),
+            (3, EvictionReason::Capacity),
+            (4, EvictionReason::Capacity)
+        ]
+    );
+    assert_eq!(e.keys(), vec![5, 1]);
+    assert_eq!(e.map.len(), 2);
+
+    e.set_capacity(0);
+    assert!(e.is_empty());
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_clear_with_evictions() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(10);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(key + 100));
+    }
+    let mut evictions = Vec::new();
+    e.clear_with_evictions(&mut evictions);
+    assert!(e.is_empty());
+    assert_eq!(e.map.len(), 0);
+    // From the oldest to the most recent
+    assert_eq!(evictions.len(), 10);
+    for (key, eviction) in evictions.iter().enumerate() {
+        assert_eq!(eviction.key, key as u64);
+        assert_eq!(eviction.reason, EvictionReason::Cleared);
+    }
+}
+
+#[test]
+fn test_lrucacheengine_cacheengine_clear() {
+    let mut e: LRUCacheEngine<u64, u64> = LRUCacheEngine::new(10);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(key + 100));
+    }
+    assert!(!e.is_empty());
+    e.clear();
+    assert!(e.is_empty());
+    assert_eq!(e.map.len(), 0);
+    assert!(e.get(&1).is_none());
+}
+
+//=============================================================================
+// LRUCache
+//-----------------------------------------------------------------------------
+#[test]
+fn test_lrucache_valuecache() {
+    let c: Arc<LRUCache<u64, u64>> = Arc::new(SimpleCache::with_engine(LRUCacheEngine::new(10)));
+
+    let t1c = Arc::clone(&c);
+    let t1 = std::thread::spawn(move || {
+        for key in 0..8 {
+            t1c.insert(key, &Arc::new(key + 1000));
+        }
+    });
+    let t2c = Arc::clone(&c);
+    let t2 = std::thread::spawn(move || {
+        for key in 2..10 {
+            t2c.insert(key, &Arc::new(key + 10000));
+        }
+    });
+    t1.join().unwrap();
+    t2.join().unwrap();
+
+    assert_eq!(c.len(), 10);
+    for key in 0..10 {
+        c.get(&key).unwrap();
+    }
+    c.insert(10, &Arc::new(10));
+    assert_eq!(c.len(), 10);
+    assert!(c.get(&0).is_none());
+
+    c.clear();
+    assert!(c.is_empty());
+}
@@ -31,12 +31,79 @@
  */
 //! This module implements a very simple associative cache that stores read-only
 //! entries associated to a key.
+//!
+//! The policy used to prune old entries is implemented by a [`CacheEngine`]. The
+//! default engine is the one used by [`SimpleCache::new()`] but other engines can
+//! be found in the submodules of this module.
+use loading::SingleFlight;
+use lru::LRUCacheEngine;
+use stats::{CacheStats, CacheStatsCounters};
+use std::borrow::Borrow;
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::sync::{Arc, RwLock};
+use std::io;
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard};
 
+#[cfg(feature = "async")]
+pub mod async_cache;
+mod list;
+mod loading;
+pub mod lru;
+pub mod negative;
+pub mod sharded;
+pub mod snapshot;
+pub mod stats;
 #[cfg(test)]
 mod tests;
+pub mod ttl;
+pub mod two_queue;
+pub mod weighted;
+
+//=============================================================================
+// EvictionReason
+//-----------------------------------------------------------------------------
+/// Reasons that lead an entry to be removed from a cache.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum EvictionReason {
+    /// The entry was removed to make room for other entries.
+    Capacity,
+    /// The entry was replaced by a new value with the same key.
+    Replaced,
+    /// The entry was removed because it expired.
+    Expired,
+    /// The entry was removed because the cache was cleared.
+    Cleared,
+    /// The entry was explicitly removed from the cache.
+    Removed,
+}
+
+//=============================================================================
+// CacheEviction
+//-----------------------------------------------------------------------------
+/// Describes an entry removed from a [`CacheEngine`] as a side effect of an
+/// operation.
+pub struct CacheEviction<K, V> {
+    /// The key of the entry.
+    pub key: K,
+    /// The value of the entry.
+    pub value: Arc<V>,
+    /// The reason of the removal.
+    pub reason: EvictionReason,
+}
+
+impl<K, V> CacheEviction<K, V> {
+    /// Creates a new `CacheEviction`.
+    ///
+    /// Arguments:
+    /// - `key`: The key of the entry;
+    /// - `value`: The value of the entry;
+    /// - `reason`: The reason of the removal;
+    pub fn new(key: K, value: Arc<V>, reason: EvictionReason) -> Self {
+        Self { key, value, reason }
+    }
+}
 
 //=============================================================================
 // ValueCache
@@ -47,8 +114,13 @@ mod tests;
 /// It is up to the implementator of this trait to define how old values are
 /// prunned from the cache.
 ///
+/// Lookups take a `&K`. The methods with the `_borrowed` suffix, such as
+/// [`ValueCache::get_borrowed()`], accept any borrowed form of the key just
+/// like [`HashMap::get()`], so a cache with [`String`] keys can be queried
+/// with a `&str`. Those methods are not available on a `dyn ValueCache`.
+///
 /// All methods of this trait are required to be thread safe.
-pub trait ValueCache<K: Eq + Hash + Copy + Sync, V: Send + Sync>: Send {
+pub trait ValueCache<K: Eq + Hash + Clone + Sync, V: Send + Sync>: Send {
     /// Gets the value from the cache if it exists.
     ///
     /// Arguments:
@@ -59,6 +131,28 @@ pub trait ValueCache<K: Eq + Hash + Copy + Sync, V: Send + Sync>: Send {
     /// - `None`: IF the entry is not in the cache;
     fn get(&self, key: &K) -> Option<Arc<V>>;
 
+    /// Gets the value from the cache if it exists using any borrowed form of
+    /// the key.
+    ///
+    /// The default implementation converts the borrowed key into a `K` and
+    /// calls [`Self::get()`]. Caches able to look the borrowed key up
+    /// directly should override it.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    ///
+    /// Returns:
+    /// - `Some(v)`: The cached value. `v` is a new [`Arc`] that points to it.
+    /// - `None`: IF the entry is not in the cache;
+    fn get_borrowed<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        Self: Sized,
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get(&K::from(key))
+    }
+
     /// Inserts the value into the cache. Reinserting a new value with the
     /// same key will replace the existing value.
     ///
@@ -67,6 +161,237 @@ pub trait ValueCache<K: Eq + Hash + Copy + Sync, V: Send + Sync>: Send {
     /// - `value`: A reference to an [`Arc`] that points to the value;
     fn insert(&self, key: K, value: &Arc<V>);
 
+    /// Gets the value from the cache or loads and inserts it if it is missing.
+    ///
+    /// The loader runs at most once per key among concurrent callers. Callers
+    /// that miss a key while it is being loaded wait for the in-flight load and
+    /// receive its result. Errors returned by the loader are shared with all
+    /// waiters of the same load but are not cached, so the next miss will run
+    /// the loader again.
+    ///
+    /// The default implementation calls [`Self::get()`] and, on a miss, runs
+    /// the loader and calls [`Self::insert()`]. It does not coordinate
+    /// concurrent callers, so the loader may run once per caller.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `loader`: The function that loads the value;
+    ///
+    /// Returns:
+    /// - `Ok(v)`: The cached or loaded value. `v` is a new [`Arc`] that points
+    ///   to it;
+    /// - `Err(e)`: The error returned by the loader;
+    fn get_or_try_insert_with<E, F>(&self, key: K, loader: F) -> Result<Arc<V>, Arc<E>>
+    where
+        Self: Sized,
+        E: Send + Sync + 'static,
+        F: FnOnce() -> Result<V, E>,
+    {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+        let value = Arc::new(loader().map_err(Arc::new)?);
+        self.insert(key, &value);
+        Ok(value)
+    }
+
+    /// Removes the entry from the cache.
+    ///
+    /// The default implementation cannot remove a single entry, so it clears
+    /// the whole cache if the entry is in it. It is still correct for a cache
+    /// but all other entries are lost, thus implementors should override it.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be removed;
+    ///
+    /// Returns:
+    /// - `Some(v)`: The value of the removed entry;
+    /// - `None`: IF the entry is not in the cache;
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let value = self.get(key)?;
+        self.clear();
+        Some(value)
+    }
+
+    /// Removes the entry from the cache using any borrowed form of the key.
+    ///
+    /// The default implementation converts the borrowed key into a `K` and
+    /// calls [`Self::remove()`].
+    ///
+    /// Arguments:
+    /// - `key`: The key to be removed;
+    ///
+    /// Returns:
+    /// - `Some(v)`: The value of the removed entry;
+    /// - `None`: IF the entry is not in the cache;
+    fn remove_borrowed<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        Self: Sized,
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.remove(&K::from(key))
+    }
+
+    /// Verifies if the cache contains the entry without changing its
+    /// recency.
+    ///
+    /// The default implementation relies on [`Self::peek()`].
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    fn contains_key(&self, key: &K) -> bool {
+        self.peek(key).is_some()
+    }
+
+    /// Verifies if the cache contains the entry without changing its recency
+    /// using any borrowed form of the key.
+    ///
+    /// The default implementation converts the borrowed key into a `K` and
+    /// calls [`Self::contains_key()`].
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    fn contains_key_borrowed<Q>(&self, key: &Q) -> bool
+    where
+        Self: Sized,
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.contains_key(&K::from(key))
+    }
+
+    /// Gets the value from the cache if it exists without changing its
+    /// recency.
+    ///
+    /// The default implementation falls back to [`Self::get()`], so it does
+    /// change the recency of the entry. Caches able to read an entry without
+    /// touching it should override it.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    ///
+    /// Returns:
+    /// - `Some(v)`: The cached value. `v` is a new [`Arc`] that points to it.
+    /// - `None`: IF the entry is not in the cache;
+    fn peek(&self, key: &K) -> Option<Arc<V>> {
+        self.get(key)
+    }
+
+    /// Gets the value from the cache if it exists without changing its
+    /// recency using any borrowed form of the key.
+    ///
+    /// The default implementation converts the borrowed key into a `K` and
+    /// calls [`Self::peek()`].
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    ///
+    /// Returns:
+    /// - `Some(v)`: The cached value. `v` is a new [`Arc`] that points to it.
+    /// - `None`: IF the entry is not in the cache;
+    fn peek_borrowed<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        Self: Sized,
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.peek(&K::from(key))
+    }
+
+    /// Inserts all entries into the cache under a single lock acquisition.
+    ///
+    /// The default implementation calls [`Self::insert()`] once per entry,
+    /// so it does not insert all entries atomically.
+    ///
+    /// Arguments:
+    /// - `entries`: The entries to be inserted;
+    fn extend<I>(&self, entries: I)
+    where
+        Self: Sized,
+        I: IntoIterator<Item = (K, Arc<V>)>,
+    {
+        for (key, value) in entries {
+            self.insert(key, &value);
+        }
+    }
+
+    /// Removes the entries with the given keys under a single lock
+    /// acquisition.
+    ///
+    /// The default implementation calls [`Self::remove()`] once per key, so
+    /// it does not remove all entries atomically.
+    ///
+    /// Arguments:
+    /// - `keys`: The keys to be removed;
+    ///
+    /// Returns the number of entries removed.
+    fn remove_many(&self, keys: &[K]) -> usize {
+        keys.iter().filter(|key| self.remove(key).is_some()).count()
+    }
+
+    /// Retains only the entries that match the given predicate. The other
+    /// entries are removed.
+    ///
+    /// The default implementation checks a snapshot of the [`Self::keys()`]
+    /// and removes the rejected entries one by one with [`Self::remove()`].
+    /// If the cache cannot list its keys, it clears the whole cache instead.
+    ///
+    /// Arguments:
+    /// - `predicate`: The predicate that receives the key and the value of
+    ///   each entry and returns true if it must be kept;
+    fn retain<F>(&self, mut predicate: F)
+    where
+        Self: Sized,
+        F: FnMut(&K, &V) -> bool,
+    {
+        let keys = match self.keys() {
+            Ok(keys) => keys,
+            Err(_) => {
+                self.clear();
+                return;
+            }
+        };
+        for key in keys {
+            if let Some(value) = self.peek(&key) {
+                if !predicate(&key, &value) {
+                    self.remove(&key);
+                }
+            }
+        }
+    }
+
+    /// Returns a snapshot of the keys in the cache. Later changes to the cache
+    /// are not reflected on the returned keys.
+    ///
+    /// The default implementation always fails with
+    /// [`io::ErrorKind::Unsupported`].
+    fn keys(&self) -> io::Result<Vec<K>> {
+        Err(unsupported())
+    }
+
+    /// Returns the capacity of the cache. Its unit is defined by the engine of
+    /// the cache, usually the maximum number of entries.
+    ///
+    /// The default implementation always fails with
+    /// [`io::ErrorKind::Unsupported`].
+    fn capacity(&self) -> io::Result<usize> {
+        Err(unsupported())
+    }
+
+    /// Changes the capacity of the cache. If the cache does not fit into the
+    /// new capacity, the least recently used entries are evicted immediately
+    /// and reported as [`EvictionReason::Capacity`].
+    ///
+    /// The default implementation always fails with
+    /// [`io::ErrorKind::Unsupported`].
+    ///
+    /// Arguments:
+    /// - `capacity`: The new capacity;
+    fn set_capacity(&self, _capacity: usize) -> io::Result<()> {
+        Err(unsupported())
+    }
+
     /// Removes all entries from the cache.
     fn clear(&self);
 
@@ -75,6 +400,126 @@ pub trait ValueCache<K: Eq + Hash + Copy + Sync, V: Send + Sync>: Send {
 
     /// Returns true if the cache is empty or false otherwise.
     fn is_empty(&self) -> bool;
+
+    /// Returns the statistics of this cache. Caches that do not collect
+    /// statistics report only their current size.
+    fn stats(&self) -> CacheStats {
+        CacheStats {
+            size: self.len(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets all statistics counters of this cache to zero.
+    fn reset_stats(&self) {}
+}
+
+//=============================================================================
+// EvictionListener
+//-----------------------------------------------------------------------------
+/// This trait is implemented by the listeners notified when entries are removed
+/// from a cache. It is implemented by all closures with the signature
+/// `Fn(K, Arc<V>, EvictionReason)`.
+///
+/// Listeners are always called after the internal locks of the cache are
+/// released, so they are allowed to access the cache that called them.
+pub trait EvictionListener<K, V>: Send + Sync {
+    /// Called when an entry is removed from the cache.
+    ///
+    /// Arguments:
+    /// - `key`: The key of the entry;
+    /// - `value`: The value of the entry;
+    /// - `reason`: The reason of the removal;
+    fn on_evict(&self, key: K, value: Arc<V>, reason: EvictionReason);
+}
+
+impl<K, V, F: Fn(K, Arc<V>, EvictionReason) + Send + Sync> EvictionListener<K, V> for F {
+    fn on_evict(&self, key: K, value: Arc<V>, reason: EvictionReason) {
+        self(key, value, reason)
+    }
+}
+
+/// Notifies the listener about the removed entries.
+///
+/// Arguments:
+/// - `listener`: The listener if any;
+/// - `evictions`: The removed entries;
+fn notify_evictions<K, V>(
+    listener: &Option<Arc<dyn EvictionListener<K, V>>>,
+    evictions: Vec<CacheEviction<K, V>>,
+) {
+    if let Some(listener) = listener {
+        for eviction in evictions {
+            listener.on_evict(eviction.key, eviction.value, eviction.reason);
+        }
+    }
+}
+
+/// Returns the error reported by the optional methods of [`ValueCache`] that
+/// are not implemented by a cache.
+fn unsupported() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        "This operation is not supported by the cache.",
+    )
+}
+
+/// Returns the value of an entry removed by [`CacheEngine::remove()`] if it was
+/// still valid when it was removed.
+///
+/// Arguments:
+/// - `removed`: The removed entry;
+fn removed_value<K, V>(removed: &CacheEviction<K, V>) -> Option<Arc<V>> {
+    if removed.reason == EvictionReason::Removed {
+        Some(Arc::clone(&removed.value))
+    } else {
+        None
+    }
+}
+
+/// Acquires the write lock of an engine. If the lock is poisoned because a
+/// thread panicked while holding it, the engine is cleared, since its state may
+/// be inconsistent, and the lock is restored.
+///
+/// Arguments:
+/// - `lock`: The lock that protects the engine;
+fn write_engine<K, V, E>(lock: &RwLock<E>) -> RwLockWriteGuard<'_, E>
+where
+    K: Eq + Hash + Clone + Sync,
+    V: Send + Sync,
+    E: CacheEngine<K, V>,
+{
+    match lock.write() {
+        Ok(engine) => engine,
+        Err(poisoned) => {
+            let mut engine = poisoned.into_inner();
+            engine.clear();
+            lock.clear_poison();
+            engine
+        }
+    }
+}
+
+/// Acquires the read lock of an engine. If the lock is poisoned, the engine is
+/// recovered as described in [`write_engine()`] before the read lock is
+/// acquired.
+///
+/// Arguments:
+/// - `lock`: The lock that protects the engine;
+fn read_engine<K, V, E>(lock: &RwLock<E>) -> RwLockReadGuard<'_, E>
+where
+    K: Eq + Hash + Clone + Sync,
+    V: Send + Sync,
+    E: CacheEngine<K, V>,
+{
+    if let Ok(engine) = lock.read() {
+        return engine;
+    }
+    drop(write_engine(lock));
+    match lock.read() {
+        Ok(engine) => engine,
+        Err(poisoned) => poisoned.into_inner(),
+    }
 }
 
 //=============================================================================
@@ -130,8 +575,10 @@ impl<V: Send + Sync> SimpleCacheEntry<V> {
 /// It is up to the implementator of this trait to define how old values are
 /// prunned from the cache.
 ///
+/// Lookups accept any borrowed form of the key, just like [`HashMap::get()`].
+///
 /// All methods of this trait are required to be thread safe.
-pub trait CacheEngine<K: Eq + Hash + Copy + Sync, V: Send + Sync>: Sync {
+pub trait CacheEngine<K: Eq + Hash + Clone + Sync, V: Send + Sync>: Sync {
     /// Gets the value from the cache if it exists.
     ///
     /// Arguments:
@@ -140,18 +587,148 @@ pub trait CacheEngine<K: Eq + Hash + Copy + Sync, V: Send + Sync>: Sync {
     /// Returns:
     /// - `Some(v)`: The cached value. `v` is a new [`Arc`] that points to it.
     /// - `None`: IF the entry is not in the cache;
-    fn get(&mut self, key: &K) -> Option<Arc<V>>;
+    fn get<Q>(&mut self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized;
 
     /// Inserts the value into the cache.
     ///
     /// Arguments:
     /// - `key`: The key;
     /// - `value`: A reference to an [`Arc`] that points to the value;
-    fn insert(&mut self, key: K, value: &Arc<V>);
+    fn insert(&mut self, key: K, value: &Arc<V>) {
+        let mut evictions = Vec::new();
+        self.insert_with_evictions(key, value, &mut evictions);
+    }
+
+    /// Inserts the value into the cache and reports the entries removed by
+    /// this operation, including the replaced entry if any.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `value`: A reference to an [`Arc`] that points to the value;
+    /// - `evictions`: The vector that will receive the removed entries;
+    fn insert_with_evictions(
+        &mut self,
+        key: K,
+        value: &Arc<V>,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    );
+
+    /// Removes the entry from the cache.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be removed;
+    ///
+    /// Returns:
+    /// - `Some(e)`: The removed entry. Its reason is [`EvictionReason::Removed`]
+    ///   unless the engine decides that the entry was already gone for another
+    ///   reason, like [`EvictionReason::Expired`];
+    /// - `None`: IF the entry is not in the cache;
+    fn remove<Q>(&mut self, key: &Q) -> Option<CacheEviction<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized;
+
+    /// Verifies if the cache contains the entry without changing its
+    /// recency.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized;
+
+    /// Gets the value from the cache if it exists without changing its
+    /// recency.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    ///
+    /// Returns:
+    /// - `Some(v)`: The cached value. `v` is a new [`Arc`] that points to it.
+    /// - `None`: IF the entry is not in the cache;
+    fn peek<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized;
+
+    /// Returns the entries of the cache ordered by their recency, from the
+    /// least to the most recently used. Inserting them in this order into an
+    /// empty engine recreates the recency of this engine as close as possible.
+    fn entries(&self) -> Vec<(K, Arc<V>)>;
+
+    /// Returns the keys of the cache ordered by their recency, from the least
+    /// to the most recently used.
+    fn keys(&self) -> Vec<K> {
+        self.entries().into_iter().map(|(key, _)| key).collect()
+    }
+
+    /// Retains only the entries that match the given predicate.
+    ///
+    /// Arguments:
+    /// - `predicate`: The predicate that receives the key and the value of
+    ///   each entry and returns true if it must be kept;
+    fn retain<F>(&mut self, predicate: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut evictions = Vec::new();
+        self.retain_with_evictions(predicate, &mut evictions);
+    }
+
+    /// Retains only the entries that match the given predicate and reports
+    /// the removed entries. Their reason is [`EvictionReason::Removed`] unless
+    /// the engine decides that they were already gone for another reason,
+    /// like [`EvictionReason::Expired`].
+    ///
+    /// Arguments:
+    /// - `predicate`: The predicate that receives the key and the value of
+    ///   each entry and returns true if it must be kept;
+    /// - `evictions`: The vector that will receive the removed entries;
+    fn retain_with_evictions<F>(&mut self, predicate: F, evictions: &mut Vec<CacheEviction<K, V>>)
+    where
+        F: FnMut(&K, &V) -> bool;
+
+    /// Returns the capacity of the engine. Its unit is defined by the engine,
+    /// usually the maximum number of entries.
+    fn capacity(&self) -> usize;
+
+    /// Changes the capacity of the engine, evicting the least recently used
+    /// entries if required.
+    ///
+    /// Arguments:
+    /// - `capacity`: The new capacity;
+    fn set_capacity(&mut self, capacity: usize) {
+        let mut evictions = Vec::new();
+        self.set_capacity_with_evictions(capacity, &mut evictions);
+    }
+
+    /// Changes the capacity of the engine, evicting the least recently used
+    /// entries if required, and reports the removed entries. Their reason is
+    /// [`EvictionReason::Capacity`] unless the engine decides that they were
+    /// already gone for another reason, like [`EvictionReason::Expired`].
+    ///
+    /// Arguments:
+    /// - `capacity`: The new capacity;
+    /// - `evictions`: The vector that will receive the removed entries;
+    fn set_capacity_with_evictions(
+        &mut self,
+        capacity: usize,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    );
 
     /// Removes all entries from the cache.
     fn clear(&mut self);
 
+    /// Removes all entries from the cache and reports them.
+    ///
+    /// Arguments:
+    /// - `evictions`: The vector that will receive the removed entries;
+    fn clear_with_evictions(&mut self, evictions: &mut Vec<CacheEviction<K, V>>);
+
     /// Returns the number of entries in the cache.
     fn len(&self) -> usize;
 
@@ -162,20 +739,22 @@ pub trait CacheEngine<K: Eq + Hash + Copy + Sync, V: Send + Sync>: Sync {
 //=============================================================================
 // SimpleCacheEngine
 //-----------------------------------------------------------------------------
-/// This struct implements the SimpleCacheEngine. It is the core of the
-/// [`SimpleCache`] implementation.
+/// This struct implements the SimpleCacheEngine. It was the original core of
+/// the [`SimpleCache`] implementation.
 ///
 /// When it reaches its maximum capacity it will drop the oldest unused entries.
+/// Finding the oldest entry requires a scan of the whole cache, so the
+/// [`LRUCacheEngine`] used by [`SimpleCache::new()`] should be preferred.
 ///
 /// This struct is not thread safe and must have its concurrency protected by
 /// an external [`RwLock`] or other synchronization primitive.
-struct SimpleCacheEngine<K: Eq + Hash + Copy + Send + Sync, V: Send + Sync> {
+pub struct SimpleCacheEngine<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> {
     map: HashMap<K, SimpleCacheEntry<V>>,
     max_size: usize,
     counter: u64,
 }
 
-impl<K: Eq + Hash + Copy + Send + Sync, V: Send + Sync> SimpleCacheEngine<K, V> {
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> SimpleCacheEngine<K, V> {
     /// Creates a new `SimpleCacheEngine` with a given capacity.
     ///
     /// Arguments:
@@ -196,25 +775,30 @@ impl<K: Eq + Hash + Copy + Send + Sync, V: Send + Sync> SimpleCacheEngine<K, V>
     }
 
     /// This method removes the entry with the smallest counter.
-    fn remove_oldest(&mut self) {
-        let mut key: Option<K> = None;
+    ///
+    /// Returns the removed entry if any.
+    fn remove_oldest(&mut self) -> Option<(K, SimpleCacheEntry<V>)> {
+        let mut key: Option<&K> = None;
         let mut oldest = u64::MAX;
         for (k, v) in self.map.iter() {
             if v.counter() < oldest {
-                key = Some(*k);
+                key = Some(k);
                 oldest = v.counter()
             }
         }
-        if let Some(k) = key {
-            self.map.remove(&k);
-        }
+        let k = key?.clone();
+        self.map.remove_entry(&k)
     }
 }
 
-impl<K: Eq + Hash + Copy + Send + Sync, V: Send + Sync> CacheEngine<K, V>
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> CacheEngine<K, V>
     for SimpleCacheEngine<K, V>
 {
-    fn get(&mut self, key: &K) -> Option<Arc<V>> {
+    fn get<Q>(&mut self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
         let counter = self.next_counter();
         let entry = match self.map.get_mut(key) {
             Some(entry) => entry,
@@ -224,11 +808,127 @@ impl<K: Eq + Hash + Copy + Send + Sync, V: Send + Sync> CacheEngine<K, V>
         Some(entry.get_value())
     }
 
-    fn insert(&mut self, key: K, value: &Arc<V>) {
+    fn insert_with_evictions(
+        &mut self,
+        key: K,
+        value: &Arc<V>,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
         let counter = self.next_counter();
-        self.map.insert(key, SimpleCacheEntry::new(value, counter));
+        match self.map.entry(key) {
+            Entry::Occupied(mut entry) => {
+                let old = entry.insert(SimpleCacheEntry::new(value, counter));
+                evictions.push(CacheEviction::new(
+                    entry.key().clone(),
+                    old.get_value(),
+                    EvictionReason::Replaced,
+                ));
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(SimpleCacheEntry::new(value, counter));
+            }
+        }
         if self.map.len() > self.max_size {
-            self.remove_oldest();
+            if let Some((k, v)) = self.remove_oldest() {
+                evictions.push(CacheEviction::new(
+                    k,
+                    v.get_value(),
+                    EvictionReason::Capacity,
+                ));
+            }
+        }
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<CacheEviction<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let (key, entry) = self.map.remove_entry(key)?;
+        Some(CacheEviction::new(
+            key,
+            entry.get_value(),
+            EvictionReason::Removed,
+        ))
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    fn peek<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.get(key).map(|entry| entry.get_value())
+    }
+
+    fn entries(&self) -> Vec<(K, Arc<V>)> {
+        let mut entries: Vec<(&K, &SimpleCacheEntry<V>)> = self.map.iter().collect();
+        entries.sort_by_key(|(_, entry)| entry.counter());
+        entries
+            .into_iter()
+            .map(|(key, entry)| (key.clone(), entry.get_value()))
+            .collect()
+    }
+
+    fn retain_with_evictions<F>(
+        &mut self,
+        mut predicate: F,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let removed: Vec<K> = self
+            .map
+            .iter()
+            .filter(|(key, entry)| !predicate(key, &entry.value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in removed {
+            if let Some((key, entry)) = self.map.remove_entry(&key) {
+                evictions.push(CacheEviction::new(
+                    key,
+                    entry.get_value(),
+                    EvictionReason::Removed,
+                ));
+            }
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.max_size
+    }
+
+    fn set_capacity_with_evictions(
+        &mut self,
+        capacity: usize,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
+        self.max_size = capacity;
+        let excess = self.map.len().saturating_sub(capacity);
+        if excess == 0 {
+            return;
+        }
+        let mut oldest: Vec<(u64, K)> = self
+            .map
+            .iter()
+            .map(|(key, entry)| (entry.counter(), key.clone()))
+            .collect();
+        oldest.sort_unstable_by_key(|(counter, _)| *counter);
+        for (_, key) in oldest.into_iter().take(excess) {
+            if let Some((key, entry)) = self.map.remove_entry(&key) {
+                evictions.push(CacheEviction::new(
+                    key,
+                    entry.get_value(),
+                    EvictionReason::Capacity,
+                ));
+            }
         }
     }
 
@@ -236,6 +936,16 @@ impl<K: Eq + Hash + Copy + Send + Sync, V: Send + Sync> CacheEngine<K, V>
         self.map.clear()
     }
 
+    fn clear_with_evictions(&mut self, evictions: &mut Vec<CacheEviction<K, V>>) {
+        for (k, v) in self.map.drain() {
+            evictions.push(CacheEviction::new(
+                k,
+                v.get_value(),
+                EvictionReason::Cleared,
+            ));
+        }
+    }
+
     fn len(&self) -> usize {
         self.map.len()
     }
@@ -252,47 +962,327 @@ impl<K: Eq + Hash + Copy + Send + Sync, V: Send + Sync> CacheEngine<K, V>
 /// number of entries at a time.
 ///
 /// When it reaches its maximum capacity it will drop the oldest unused entries.
+/// The actual policy is defined by the [`CacheEngine`] `E`, which defaults to
+/// [`LRUCacheEngine`].
+///
+/// The collection of statistics is disabled by default and can be enabled by
+/// [`Self::set_stats_enabled()`]. An [`EvictionListener`] can be set by
+/// [`Self::set_eviction_listener()`] to be notified about the removed entries.
+///
+/// If a thread panics while holding the lock of the engine, the next call
+/// clears the engine and the cache keeps working instead of propagating the
+/// panic.
 ///
 /// All methods of this struct are thread-safe.
-pub struct SimpleCache<K: Eq + Hash + Copy + Send + Sync, V: Send + Sync> {
-    engine: RwLock<SimpleCacheEngine<K, V>>,
+pub struct SimpleCache<
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    E: CacheEngine<K, V> + Send = LRUCacheEngine<K, V>,
+> {
+    engine: RwLock<E>,
+    stats: Option<CacheStatsCounters>,
+    listener: Option<Arc<dyn EvictionListener<K, V>>>,
+    loads: SingleFlight<K, V>,
+    _phantom: PhantomData<(K, V)>,
 }
 
-impl<K: Eq + Hash + Copy + Send + Sync, V: Send + Sync> SimpleCache<K, V> {
-    /// Creates a new SimpleCache with a given capacity.
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> SimpleCache<K, V> {
+    /// Creates a new SimpleCache with a given capacity. It uses the
+    /// [`LRUCacheEngine`], so all operations run in constant time.
     ///
     /// Arguments:
     /// - `max_size`: Maximum number of items in the cache;
     pub fn new(max_size: usize) -> Self {
+        Self::with_engine(LRUCacheEngine::new(max_size))
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync, E: CacheEngine<K, V> + Send>
+    SimpleCache<K, V, E>
+{
+    /// Creates a new SimpleCache that uses the given engine.
+    ///
+    /// Arguments:
+    /// - `engine`: The engine that will hold the entries;
+    pub fn with_engine(engine: E) -> Self {
         Self {
-            engine: RwLock::new(SimpleCacheEngine::new(max_size)),
+            engine: RwLock::new(engine),
+            stats: None,
+            listener: None,
+            loads: SingleFlight::new(),
+            _phantom: PhantomData,
         }
     }
+
+    /// Enables or disables the collection of statistics. Disabling it discards
+    /// all statistics collected so far.
+    ///
+    /// Arguments:
+    /// - `enabled`: The new state of the statistics;
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        self.stats = if enabled {
+            Some(CacheStatsCounters::new())
+        } else {
+            None
+        };
+    }
+
+    /// Returns true if the collection of statistics is enabled.
+    pub fn stats_enabled(&self) -> bool {
+        self.stats.is_some()
+    }
+
+    /// Sets the listener that will be notified whenever an entry is removed
+    /// from this cache.
+    ///
+    /// Arguments:
+    /// - `listener`: The new listener or `None` to remove the current one;
+    pub fn set_eviction_listener(&mut self, listener: Option<Arc<dyn EvictionListener<K, V>>>) {
+        self.listener = listener;
+    }
+
+    /// Gets the value from the cache if it exists. It accepts any borrowed
+    /// form of the key.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    fn get_core<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let ret = {
+            let mut s = write_engine(&self.engine);
+            s.get(key)
+        };
+        if let Some(stats) = &self.stats {
+            stats.record_get(ret.is_some());
+        }
+        ret
+    }
+
+    /// Removes the entry from the cache. It accepts any borrowed form of the
+    /// key.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be removed;
+    fn remove_core<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let removed = {
+            let mut s = write_engine(&self.engine);
+            s.remove(key)?
+        };
+        let ret = removed_value(&removed);
+        let evictions = vec![removed];
+        if let Some(stats) = &self.stats {
+            stats.record_evictions(&evictions);
+        }
+        notify_evictions(&self.listener, evictions);
+        ret
+    }
 }
 
-impl<K: Eq + Hash + Copy + Send + Sync, V: Send + Sync> ValueCache<K, V> for SimpleCache<K, V> {
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync, E: CacheEngine<K, V> + Send>
+    ValueCache<K, V> for SimpleCache<K, V, E>
+{
     fn get(&self, key: &K) -> Option<Arc<V>> {
-        let mut s = self.engine.write().unwrap();
-        s.get(key)
+        self.get_core(key)
+    }
+
+    fn get_borrowed<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get_core(key)
     }
 
     fn insert(&self, key: K, value: &Arc<V>) {
-        let mut s = self.engine.write().unwrap();
-        s.insert(key, value)
+        let mut evictions = Vec::new();
+        {
+            let mut s = write_engine(&self.engine);
+            s.insert_with_evictions(key, value, &mut evictions);
+        }
+        if let Some(stats) = &self.stats {
+            stats.record_insert(&evictions);
+        }
+        notify_evictions(&self.listener, evictions);
+    }
+
+    fn get_or_try_insert_with<LoadError, F>(
+        &self,
+        key: K,
+        loader: F,
+    ) -> Result<Arc<V>, Arc<LoadError>>
+    where
+        LoadError: Send + Sync + 'static,
+        F: FnOnce() -> Result<V, LoadError>,
+    {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+        self.loads.load(
+            key,
+            |key| self.peek(key),
+            loader,
+            |key, value| self.insert(key, value),
+        )
+    }
+
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        self.remove_core(key)
+    }
+
+    fn remove_borrowed<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.remove_core(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        let s = read_engine(&self.engine);
+        s.contains_key(key)
+    }
+
+    fn contains_key_borrowed<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let s = read_engine(&self.engine);
+        s.contains_key(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<Arc<V>> {
+        let s = read_engine(&self.engine);
+        s.peek(key)
+    }
+
+    fn peek_borrowed<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let s = read_engine(&self.engine);
+        s.peek(key)
+    }
+
+    fn extend<I>(&self, entries: I)
+    where
+        I: IntoIterator<Item = (K, Arc<V>)>,
+    {
+        let mut evictions = Vec::new();
+        let mut count = 0;
+        {
+            let mut s = write_engine(&self.engine);
+            for (key, value) in entries {
+                s.insert_with_evictions(key, &value, &mut evictions);
+                count += 1;
+            }
+        }
+        if let Some(stats) = &self.stats {
+            stats.record_inserts(count, &evictions);
+        }
+        notify_evictions(&self.listener, evictions);
+    }
+
+    fn remove_many(&self, keys: &[K]) -> usize {
+        let mut evictions = Vec::new();
+        {
+            let mut s = write_engine(&self.engine);
+            evictions.extend(keys.iter().filter_map(|key| s.remove(key)));
+        }
+        let count = evictions
+            .iter()
+            .filter(|removed| removed_value(removed).is_some())
+            .count();
+        if let Some(stats) = &self.stats {
+            stats.record_evictions(&evictions);
+        }
+        notify_evictions(&self.listener, evictions);
+        count
+    }
+
+    fn retain<F>(&self, predicate: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut evictions = Vec::new();
+        {
+            let mut s = write_engine(&self.engine);
+            s.retain_with_evictions(predicate, &mut evictions);
+        }
+        if let Some(stats) = &self.stats {
+            stats.record_evictions(&evictions);
+        }
+        notify_evictions(&self.listener, evictions);
+    }
+
+    fn keys(&self) -> io::Result<Vec<K>> {
+        let s = read_engine(&self.engine);
+        Ok(s.keys())
+    }
+
+    fn capacity(&self) -> io::Result<usize> {
+        let s = read_engine(&self.engine);
+        Ok(s.capacity())
+    }
+
+    fn set_capacity(&self, capacity: usize) -> io::Result<()> {
+        let mut evictions = Vec::new();
+        {
+            let mut s = write_engine(&self.engine);
+            s.set_capacity_with_evictions(capacity, &mut evictions);
+        }
+        if let Some(stats) = &self.stats {
+            stats.record_evictions(&evictions);
+        }
+        notify_evictions(&self.listener, evictions);
+        Ok(())
     }
 
     fn clear(&self) {
-        let mut s = self.engine.write().unwrap();
-        s.clear()
+        let mut evictions = Vec::new();
+        {
+            let mut s = write_engine(&self.engine);
+            if self.listener.is_some() {
+                s.clear_with_evictions(&mut evictions);
+            } else {
+                s.clear();
+            }
+        }
+        notify_evictions(&self.listener, evictions);
     }
 
     fn len(&self) -> usize {
-        let s = self.engine.read().unwrap();
+        let s = read_engine(&self.engine);
         s.len()
     }
 
     fn is_empty(&self) -> bool {
-        let s = self.engine.read().unwrap();
+        let s = read_engine(&self.engine);
         s.is_empty()
     }
+
+    fn stats(&self) -> CacheStats {
+        let size = self.len();
+        match &self.stats {
+            Some(stats) => stats.snapshot(size),
+            None => CacheStats {
+                size,
+                ..Default::default()
+            },
+        }
+    }
+
+    fn reset_stats(&self) {
+        if let Some(stats) = &self.stats {
+            stats.reset();
+        }
+    }
 }
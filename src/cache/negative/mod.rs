@@ -0,0 +1,272 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements a cache that also remembers the keys known to be
+//! absent from the underlying storage, avoiding repeated lookups of keys that
+//! do not exist.
+use super::ttl::{CacheClock, SystemCacheClock, TTLCache, TTLCacheEngine};
+use super::{SimpleCache, ValueCache};
+use std::borrow::Borrow;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(test)]
+mod tests;
+
+//=============================================================================
+// CacheLookup
+//-----------------------------------------------------------------------------
+/// Result of a lookup in a [`NegativeCache`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum CacheLookup<V> {
+    /// The entry is in the cache.
+    Hit(Arc<V>),
+    /// The key is known to be absent.
+    KnownAbsent,
+    /// Nothing is known about the key.
+    Miss,
+}
+
+impl<V> CacheLookup<V> {
+    /// Returns true if this result is a [`CacheLookup::Miss`].
+    pub fn is_miss(&self) -> bool {
+        matches!(self, Self::Miss)
+    }
+
+    /// Converts this result into an [`Option`].
+    ///
+    /// Returns:
+    /// - `Some(v)`: The value found by a [`CacheLookup::Hit`];
+    /// - `None`: Otherwise;
+    pub fn hit(self) -> Option<Arc<V>> {
+        match self {
+            Self::Hit(value) => Some(value),
+            _ => None,
+        }
+    }
+}
+
+//=============================================================================
+// NegativeCache
+//-----------------------------------------------------------------------------
+/// This struct implements a cache that holds both positive entries, stored in
+/// a [`ValueCache`] `C`, and keys known to be absent from the underlying
+/// storage.
+///
+/// The absent keys are kept in a separate [`TTLCache`] with its own capacity
+/// and time-to-live, so they never displace the positive entries and are
+/// forgotten after a while even if they are used frequently. Inserting a value
+/// clears the absent mark of its key and vice versa.
+///
+/// The time used to expire the absent keys is provided by the
+/// [`CacheClock`] `T`.
+///
+/// All methods of this struct are thread-safe.
+pub struct NegativeCache<
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: ValueCache<K, V> = SimpleCache<K, V>,
+    T: CacheClock = SystemCacheClock,
+> {
+    cache: C,
+    absent: TTLCache<K, (), T>,
+    _phantom: PhantomData<V>,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> NegativeCache<K, V> {
+    /// Creates a new `NegativeCache` that stores the positive entries in a
+    /// [`SimpleCache`].
+    ///
+    /// Arguments:
+    /// - `max_size`: Maximum number of positive entries;
+    /// - `max_absent`: Maximum number of absent keys;
+    /// - `absent_ttl`: The time-to-live of the absent keys;
+    pub fn new(max_size: usize, max_absent: usize, absent_ttl: Duration) -> Self {
+        Self::with_cache(
+            SimpleCache::new(max_size),
+            TTLCacheEngine::new(max_absent, absent_ttl),
+        )
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync, C: ValueCache<K, V>, T: CacheClock>
+    NegativeCache<K, V, C, T>
+{
+    /// Creates a new `NegativeCache` with the given caches.
+    ///
+    /// Arguments:
+    /// - `cache`: The cache of the positive entries;
+    /// - `absent`: The engine that will hold the absent keys. Its capacity and
+    ///   time-to-live are used for the absent keys;
+    pub fn with_cache(cache: C, absent: TTLCacheEngine<K, (), T>) -> Self {
+        Self {
+            cache,
+            absent: SimpleCache::with_engine(absent),
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Returns the cache of the positive entries.
+    pub fn cache(&self) -> &C {
+        &self.cache
+    }
+
+    /// Looks the key up.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    ///
+    /// Returns:
+    /// - `CacheLookup::Hit(v)`: The cached value;
+    /// - `CacheLookup::KnownAbsent`: If the key is known to be absent;
+    /// - `CacheLookup::Miss`: If nothing is known about the key;
+    pub fn get(&self, key: &K) -> CacheLookup<V> {
+        if let Some(value) = self.cache.get(key) {
+            CacheLookup::Hit(value)
+        } else if self.absent.get(key).is_some() {
+            CacheLookup::KnownAbsent
+        } else {
+            CacheLookup::Miss
+        }
+    }
+
+    /// Looks the key up using any borrowed form of the key. See
+    /// [`ValueCache::get_borrowed()`].
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    ///
+    /// Returns:
+    /// - `CacheLookup::Hit(v)`: The cached value;
+    /// - `CacheLookup::KnownAbsent`: If the key is known to be absent;
+    /// - `CacheLookup::Miss`: If nothing is known about the key;
+    pub fn get_borrowed<Q>(&self, key: &Q) -> CacheLookup<V>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        if let Some(value) = self.cache.get_borrowed(key) {
+            CacheLookup::Hit(value)
+        } else if self.absent.get_borrowed(key).is_some() {
+            CacheLookup::KnownAbsent
+        } else {
+            CacheLookup::Miss
+        }
+    }
+
+    /// Inserts the value into the cache, clearing the absent mark of the key.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `value`: A reference to an [`Arc`] that points to the value;
+    pub fn insert(&self, key: K, value: &Arc<V>) {
+        self.absent.remove(&key);
+        self.cache.insert(key, value);
+    }
+
+    /// Marks the key as absent, removing its value from the cache if any.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    pub fn insert_absent(&self, key: K) {
+        self.cache.remove(&key);
+        self.absent.insert(key, &Arc::new(()));
+    }
+
+    /// Marks the key as absent with a specific time-to-live, removing its
+    /// value from the cache if any.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `ttl`: The time-to-live of the absent mark;
+    pub fn insert_absent_with_ttl(&self, key: K, ttl: Duration) {
+        self.cache.remove(&key);
+        self.absent.insert_with_ttl(key, &Arc::new(()), ttl);
+    }
+
+    /// Forgets everything known about the key.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be removed;
+    ///
+    /// Returns the value of the removed entry if any.
+    pub fn remove(&self, key: &K) -> Option<Arc<V>> {
+        self.absent.remove(key);
+        self.cache.remove(key)
+    }
+
+    /// Forgets everything known about the key using any borrowed form of the
+    /// key. See [`ValueCache::remove_borrowed()`].
+    ///
+    /// Arguments:
+    /// - `key`: The key to be removed;
+    ///
+    /// Returns the value of the removed entry if any.
+    pub fn remove_borrowed<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.absent.remove_borrowed(key);
+        self.cache.remove_borrowed(key)
+    }
+
+    /// Removes all positive entries and absent keys.
+    pub fn clear(&self) {
+        self.absent.clear();
+        self.cache.clear();
+    }
+
+    /// Removes all absent keys that have expired.
+    ///
+    /// Returns the number of absent keys removed.
+    pub fn purge_expired_absent(&self) -> usize {
+        self.absent.purge_expired()
+    }
+
+    /// Returns the number of positive entries.
+    pub fn len(&self) -> usize {
+        self.cache.len()
+    }
+
+    /// Returns the number of absent keys, including the expired ones that
+    /// were not purged yet.
+    pub fn absent_len(&self) -> usize {
+        self.absent.len()
+    }
+
+    /// Returns true if there are no positive entries nor absent keys.
+    pub fn is_empty(&self) -> bool {
+        self.cache.is_empty() && self.absent.is_empty()
+    }
+}
@@ -0,0 +1,177 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use crate::cache::ttl::ManualCacheClock;
+
+/// The `NegativeCache` used by the tests.
+type TestNegativeCache = NegativeCache<u64, u64, SimpleCache<u64, u64>, Arc<ManualCacheClock>>;
+
+/// Creates a new `NegativeCache` that uses a [`ManualCacheClock`]. The absent
+/// keys expire after 10 seconds.
+fn create_cache(max_size: usize, max_absent: usize) -> (Arc<ManualCacheClock>, TestNegativeCache) {
+    let clock = Arc::new(ManualCacheClock::new());
+    let c = NegativeCache::with_cache(
+        SimpleCache::new(max_size),
+        TTLCacheEngine::with_clock(max_absent, Duration::from_secs(10), Arc::clone(&clock)),
+    );
+    (clock, c)
+}
+
+//=============================================================================
+// CacheLookup
+//-----------------------------------------------------------------------------
+#[test]
+fn test_cachelookup_impl() {
+    let l = CacheLookup::Hit(Arc::new(1));
+    assert!(!l.is_miss());
+    assert_eq!(*l.hit().unwrap(), 1);
+
+    let l: CacheLookup<u64> = CacheLookup::KnownAbsent;
+    assert!(!l.is_miss());
+    assert!(l.hit().is_none());
+
+    let l: CacheLookup<u64> = CacheLookup::Miss;
+    assert!(l.is_miss());
+    assert!(l.hit().is_none());
+}
+
+//=============================================================================
+// NegativeCache
+//-----------------------------------------------------------------------------
+#[test]
+fn test_negativecache_impl_new() {
+    let c: NegativeCache<u64, u64> = NegativeCache::new(10, 5, Duration::from_secs(1));
+    assert!(c.is_empty());
+    assert_eq!(c.len(), 0);
+    assert_eq!(c.absent_len(), 0);
+    assert_eq!(c.get(&1), CacheLookup::Miss);
+}
+
+#[test]
+fn test_negativecache_impl_get() {
+    let (_, c) = create_cache(10, 10);
+
+    c.insert(1, &Arc::new(10));
+    c.insert_absent(2);
+    assert_eq!(c.get(&1), CacheLookup::Hit(Arc::new(10)));
+    assert_eq!(c.get(&2), CacheLookup::KnownAbsent);
+    assert_eq!(c.get(&3), CacheLookup::Miss);
+    assert_eq!(c.len(), 1);
+    assert_eq!(c.absent_len(), 1);
+    assert!(!c.is_empty());
+}
+
+#[test]
+fn test_negativecache_impl_insert_clears_absent() {
+    let (_, c) = create_cache(10, 10);
+
+    c.insert_absent(1);
+    assert_eq!(c.get(&1), CacheLookup::KnownAbsent);
+    c.insert(1, &Arc::new(10));
+    assert_eq!(c.get(&1), CacheLookup::Hit(Arc::new(10)));
+    assert_eq!(c.absent_len(), 0);
+
+    // Marking it as absent removes the value
+    c.insert_absent(1);
+    assert_eq!(c.get(&1), CacheLookup::KnownAbsent);
+    assert_eq!(c.len(), 0);
+}
+
+#[test]
+fn test_negativecache_impl_absent_ttl() {
+    let (clock, c) = create_cache(10, 10);
+
+    c.insert(1, &Arc::new(10));
+    c.insert_absent(2);
+    c.insert_absent_with_ttl(3, Duration::from_secs(20));
+    clock.advance(Duration::from_secs(10));
+    // Positive entries do not expire
+    assert_eq!(c.get(&1), CacheLookup::Hit(Arc::new(10)));
+    assert_eq!(c.get(&2), CacheLookup::Miss);
+    assert_eq!(c.get(&3), CacheLookup::KnownAbsent);
+    assert_eq!(c.purge_expired_absent(), 1);
+    assert_eq!(c.absent_len(), 1);
+    clock.advance(Duration::from_secs(10));
+    assert_eq!(c.get(&3), CacheLookup::Miss);
+}
+
+#[test]
+fn test_negativecache_impl_capacity_share() {
+    let (_, c) = create_cache(4, 2);
+
+    for key in 0..4 {
+        c.insert(key, &Arc::new(key));
+    }
+    for key in 10..20 {
+        c.insert_absent(key);
+    }
+    // The absent keys do not displace the positive entries
+    assert_eq!(c.len(), 4);
+    assert_eq!(c.absent_len(), 2);
+    for key in 0..4 {
+        assert_eq!(c.get(&key), CacheLookup::Hit(Arc::new(key)));
+    }
+    assert_eq!(c.get(&17), CacheLookup::Miss);
+    assert_eq!(c.get(&18), CacheLookup::KnownAbsent);
+    assert_eq!(c.get(&19), CacheLookup::KnownAbsent);
+}
+
+#[test]
+fn test_negativecache_impl_remove_clear() {
+    let (_, c) = create_cache(10, 10);
+
+    c.insert(1, &Arc::new(10));
+    c.insert_absent(2);
+    assert_eq!(*c.remove(&1).unwrap(), 10);
+    assert!(c.remove(&2).is_none());
+    assert_eq!(c.get(&1), CacheLookup::Miss);
+    assert_eq!(c.get(&2), CacheLookup::Miss);
+
+    c.insert(1, &Arc::new(10));
+    c.insert_absent(2);
+    c.clear();
+    assert!(c.is_empty());
+}
+
+#[test]
+fn test_negativecache_impl_borrowed_keys() {
+    let c: NegativeCache<String, u64> = NegativeCache::new(10, 10, Duration::from_secs(60));
+
+    c.insert(String::from("a"), &Arc::new(1));
+    c.insert_absent(String::from("b"));
+    assert_eq!(c.get_borrowed("a"), CacheLookup::Hit(Arc::new(1)));
+    assert_eq!(c.get_borrowed("b"), CacheLookup::KnownAbsent);
+    assert_eq!(c.get_borrowed("c"), CacheLookup::Miss);
+    assert!(c.cache().contains_key_borrowed("a"));
+    assert_eq!(*c.remove_borrowed("a").unwrap(), 1);
+    assert_eq!(c.get_borrowed("a"), CacheLookup::Miss);
+}
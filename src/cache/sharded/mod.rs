@@ -0,0 +1,482 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements a [`ValueCache`] that splits its entries among
+//! multiple independently locked [`CacheEngine`] instances, reducing the lock
+//! contention when the cache is shared by many threads.
+use super::loading::SingleFlight;
+use super::lru::LRUCacheEngine;
+use super::stats::{CacheStats, CacheStatsCounters};
+use super::{
+    notify_evictions, read_engine, removed_value, write_engine, CacheEngine, CacheEviction,
+    EvictionListener, ValueCache,
+};
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hash};
+use std::io;
+use std::marker::PhantomData;
+use std::sync::{Arc, RwLock};
+
+#[cfg(test)]
+mod tests;
+
+//=============================================================================
+// ShardedCache
+//-----------------------------------------------------------------------------
+/// This struct implements a value cache that distributes its entries among a
+/// fixed number of shards. Each shard is a [`CacheEngine`] protected by its own
+/// [`RwLock`], so operations on keys that belong to distinct shards do not
+/// block each other.
+///
+/// The shard of each key is selected by its hash. Since each shard prunes its
+/// own entries, the eviction policy is applied per shard instead of globally.
+///
+/// The collection of statistics is disabled by default and can be enabled by
+/// [`Self::set_stats_enabled()`]. Each shard has its own counters. An
+/// [`EvictionListener`] shared by all shards can be set by
+/// [`Self::set_eviction_listener()`].
+///
+/// If a thread panics while holding the lock of a shard, the next call clears
+/// that shard while the other shards keep their entries.
+///
+/// All methods of this struct are thread-safe.
+pub struct ShardedCache<
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    E: CacheEngine<K, V> + Send = LRUCacheEngine<K, V>,
+> {
+    shards: Vec<RwLock<E>>,
+    stats: Option<Vec<CacheStatsCounters>>,
+    listener: Option<Arc<dyn EvictionListener<K, V>>>,
+    hasher: RandomState,
+    loads: Vec<SingleFlight<K, V>>,
+    _phantom: PhantomData<(K, V)>,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> ShardedCache<K, V> {
+    /// Creates a new ShardedCache that uses [`LRUCacheEngine`] as the engine
+    /// of its shards, so all operations run in constant time while the lock
+    /// of a shard is held. The capacity is split among the shards as described
+    /// by [`Self::shard_size()`].
+    ///
+    /// Arguments:
+    /// - `shard_count`: The number of shards. It must be greater than 0;
+    /// - `max_size`: Maximum number of items in the cache;
+    pub fn new(shard_count: usize, max_size: usize) -> Self {
+        Self::with_engines(
+            (0..shard_count)
+                .map(|shard| LRUCacheEngine::new(Self::shard_size(shard_count, max_size, shard)))
+                .collect(),
+        )
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync, E: CacheEngine<K, V> + Send>
+    ShardedCache<K, V, E>
+{
+    /// Creates a new ShardedCache with one shard for each engine.
+    ///
+    /// Arguments:
+    /// - `engines`: The engines of the shards. It must not be empty;
+    pub fn with_engines(engines: Vec<E>) -> Self {
+        assert!(!engines.is_empty(), "At least one shard is required.");
+        let loads = engines.iter().map(|_| SingleFlight::new()).collect();
+        Self {
+            shards: engines.into_iter().map(RwLock::new).collect(),
+            stats: None,
+            listener: None,
+            hasher: RandomState::new(),
+            loads,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Computes the capacity of a shard when the total capacity is split
+    /// among a given number of shards. The remainder of the division is given
+    /// to the first shards, so the capacities of all shards add up to the
+    /// total capacity.
+    ///
+    /// Arguments:
+    /// - `shard_count`: The number of shards. It must be greater than 0;
+    /// - `max_size`: Maximum number of items in the cache;
+    /// - `shard`: The index of the shard;
+    pub fn shard_size(shard_count: usize, max_size: usize, shard: usize) -> usize {
+        assert!(shard_count > 0, "At least one shard is required.");
+        let extra = if shard < max_size % shard_count { 1 } else { 0 };
+        max_size / shard_count + extra
+    }
+
+    /// Returns the number of shards.
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Enables or disables the collection of statistics. Disabling it discards
+    /// all statistics collected so far.
+    ///
+    /// Arguments:
+    /// - `enabled`: The new state of the statistics;
+    pub fn set_stats_enabled(&mut self, enabled: bool) {
+        self.stats = if enabled {
+            Some(
+                (0..self.shards.len())
+                    .map(|_| CacheStatsCounters::new())
+                    .collect(),
+            )
+        } else {
+            None
+        };
+    }
+
+    /// Returns true if the collection of statistics is enabled.
+    pub fn stats_enabled(&self) -> bool {
+        self.stats.is_some()
+    }
+
+    /// Sets the listener that will be notified whenever an entry is removed
+    /// from any shard of this cache.
+    ///
+    /// Arguments:
+    /// - `listener`: The new listener or `None` to remove the current one;
+    pub fn set_eviction_listener(&mut self, listener: Option<Arc<dyn EvictionListener<K, V>>>) {
+        self.listener = listener;
+    }
+
+    /// Returns the index of the shard that holds the given key.
+    fn shard_index<Q: Hash + ?Sized>(&self, key: &Q) -> usize {
+        let hash = self.hasher.hash_one(key);
+        (hash % self.shards.len() as u64) as usize
+    }
+
+    /// Returns the shard that holds the given key.
+    fn shard<Q: Hash + ?Sized>(&self, key: &Q) -> &RwLock<E> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    /// Returns the statistics counters of a shard if enabled.
+    ///
+    /// Arguments:
+    /// - `index`: The index of the shard;
+    fn shard_stats(&self, index: usize) -> Option<&CacheStatsCounters> {
+        self.stats.as_ref().map(|stats| &stats[index])
+    }
+
+    /// Gets the value from the cache if it exists. It accepts any borrowed
+    /// form of the key.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be found;
+    fn get_core<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let index = self.shard_index(key);
+        let ret = {
+            let mut s = write_engine(&self.shards[index]);
+            s.get(key)
+        };
+        if let Some(stats) = self.shard_stats(index) {
+            stats.record_get(ret.is_some());
+        }
+        ret
+    }
+
+    /// Removes the entry from the cache. It accepts any borrowed form of the
+    /// key.
+    ///
+    /// Arguments:
+    /// - `key`: The key to be removed;
+    fn remove_core<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let index = self.shard_index(key);
+        let removed = {
+            let mut s = write_engine(&self.shards[index]);
+            s.remove(key)?
+        };
+        let ret = removed_value(&removed);
+        let evictions = vec![removed];
+        if let Some(stats) = self.shard_stats(index) {
+            stats.record_evictions(&evictions);
+        }
+        notify_evictions(&self.listener, evictions);
+        ret
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync, E: CacheEngine<K, V> + Send>
+    ValueCache<K, V> for ShardedCache<K, V, E>
+{
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.get_core(key)
+    }
+
+    fn get_borrowed<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get_core(key)
+    }
+
+    fn insert(&self, key: K, value: &Arc<V>) {
+        let index = self.shard_index(&key);
+        let mut evictions = Vec::new();
+        {
+            let mut s = write_engine(&self.shards[index]);
+            s.insert_with_evictions(key, value, &mut evictions);
+        }
+        if let Some(stats) = self.shard_stats(index) {
+            stats.record_insert(&evictions);
+        }
+        notify_evictions(&self.listener, evictions);
+    }
+
+    fn get_or_try_insert_with<LoadError, F>(
+        &self,
+        key: K,
+        loader: F,
+    ) -> Result<Arc<V>, Arc<LoadError>>
+    where
+        LoadError: Send + Sync + 'static,
+        F: FnOnce() -> Result<V, LoadError>,
+    {
+        if let Some(value) = self.get(&key) {
+            return Ok(value);
+        }
+        self.loads[self.shard_index(&key)].load(
+            key,
+            |key| self.peek(key),
+            loader,
+            |key, value| self.insert(key, value),
+        )
+    }
+
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        self.remove_core(key)
+    }
+
+    fn remove_borrowed<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.remove_core(key)
+    }
+
+    fn contains_key(&self, key: &K) -> bool {
+        let s = read_engine(self.shard(key));
+        s.contains_key(key)
+    }
+
+    fn contains_key_borrowed<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let s = read_engine(self.shard(key));
+        s.contains_key(key)
+    }
+
+    fn peek(&self, key: &K) -> Option<Arc<V>> {
+        let s = read_engine(self.shard(key));
+        s.peek(key)
+    }
+
+    fn peek_borrowed<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q> + for<'q> From<&'q Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let s = read_engine(self.shard(key));
+        s.peek(key)
+    }
+
+    fn extend<I>(&self, entries: I)
+    where
+        I: IntoIterator<Item = (K, Arc<V>)>,
+    {
+        let mut groups: Vec<Vec<(K, Arc<V>)>> = self.shards.iter().map(|_| Vec::new()).collect();
+        for (key, value) in entries {
+            groups[self.shard_index(&key)].push((key, value));
+        }
+        for (index, group) in groups.into_iter().enumerate() {
+            if group.is_empty() {
+                continue;
+            }
+            let count = group.len() as u64;
+            let mut evictions = Vec::new();
+            {
+                let mut s = write_engine(&self.shards[index]);
+                for (key, value) in group {
+                    s.insert_with_evictions(key, &value, &mut evictions);
+                }
+            }
+            if let Some(stats) = self.shard_stats(index) {
+                stats.record_inserts(count, &evictions);
+            }
+            notify_evictions(&self.listener, evictions);
+        }
+    }
+
+    fn remove_many(&self, keys: &[K]) -> usize {
+        let mut groups: Vec<Vec<&K>> = self.shards.iter().map(|_| Vec::new()).collect();
+        for key in keys {
+            groups[self.shard_index(key)].push(key);
+        }
+        let mut count = 0;
+        for (index, group) in groups.into_iter().enumerate() {
+            if group.is_empty() {
+                continue;
+            }
+            let evictions: Vec<CacheEviction<K, V>> = {
+                let mut s = write_engine(&self.shards[index]);
+                group.into_iter().filter_map(|key| s.remove(key)).collect()
+            };
+            count += evictions
+                .iter()
+                .filter(|removed| removed_value(removed).is_some())
+                .count();
+            if let Some(stats) = self.shard_stats(index) {
+                stats.record_evictions(&evictions);
+            }
+            notify_evictions(&self.listener, evictions);
+        }
+        count
+    }
+
+    fn retain<F>(&self, mut predicate: F)
+    where
+        F: FnMut(&K, &V) -> bool,
+    {
+        for (index, shard) in self.shards.iter().enumerate() {
+            let mut evictions = Vec::new();
+            {
+                let mut s = write_engine(shard);
+                s.retain_with_evictions(&mut predicate, &mut evictions);
+            }
+            if let Some(stats) = self.shard_stats(index) {
+                stats.record_evictions(&evictions);
+            }
+            notify_evictions(&self.listener, evictions);
+        }
+    }
+
+    fn keys(&self) -> io::Result<Vec<K>> {
+        Ok(self
+            .shards
+            .iter()
+            .flat_map(|shard| read_engine(shard).keys())
+            .collect())
+    }
+
+    fn capacity(&self) -> io::Result<usize> {
+        Ok(self
+            .shards
+            .iter()
+            .map(|shard| read_engine(shard).capacity())
+            .sum())
+    }
+
+    /// The new capacity is split among the shards as described by
+    /// [`Self::shard_size()`].
+    fn set_capacity(&self, capacity: usize) -> io::Result<()> {
+        for (index, shard) in self.shards.iter().enumerate() {
+            let shard_size = Self::shard_size(self.shards.len(), capacity, index);
+            let mut evictions = Vec::new();
+            {
+                let mut s = write_engine(shard);
+                s.set_capacity_with_evictions(shard_size, &mut evictions);
+            }
+            if let Some(stats) = self.shard_stats(index) {
+                stats.record_evictions(&evictions);
+            }
+            notify_evictions(&self.listener, evictions);
+        }
+        Ok(())
+    }
+
+    fn clear(&self) {
+        for shard in self.shards.iter() {
+            let mut evictions = Vec::new();
+            {
+                let mut s = write_engine(shard);
+                if self.listener.is_some() {
+                    s.clear_with_evictions(&mut evictions);
+                } else {
+                    s.clear();
+                }
+            }
+            notify_evictions(&self.listener, evictions);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| read_engine(shard).len())
+            .sum()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.shards
+            .iter()
+            .all(|shard| read_engine(shard).is_empty())
+    }
+
+    fn stats(&self) -> CacheStats {
+        let mut ret = CacheStats::default();
+        for (index, shard) in self.shards.iter().enumerate() {
+            let size = read_engine(shard).len();
+            ret = ret
+                + match self.shard_stats(index) {
+                    Some(stats) => stats.snapshot(size),
+                    None => CacheStats {
+                        size,
+                        ..Default::default()
+                    },
+                };
+        }
+        ret
+    }
+
+    fn reset_stats(&self) {
+        if let Some(stats) = &self.stats {
+            for s in stats.iter() {
+                s.reset();
+            }
+        }
+    }
+}
@@ -0,0 +1,477 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use crate::cache::lru::LRUCacheEngine;
+use crate::cache::EvictionReason;
+use std::sync::Mutex;
+
+/// Finds `count` keys that belong to the given shard.
+fn keys_of_shard<E: CacheEngine<u64, u64> + Send>(
+    c: &ShardedCache<u64, u64, E>,
+    shard: usize,
+    count: usize,
+) -> Vec<u64> {
+    (0..u64::MAX)
+        .filter(|key| c.shard_index(key) == shard)
+        .take(count)
+        .collect()
+}
+
+//=============================================================================
+// ShardedCache
+//-----------------------------------------------------------------------------
+#[test]
+fn test_shardedcache_impl_new() {
+    let c: ShardedCache<u64, u64> = ShardedCache::new(4, 10);
+    assert_eq!(c.shard_count(), 4);
+    for shard in c.shards.iter() {
+        assert!(shard.read().unwrap().is_empty());
+    }
+    assert!(c.is_empty());
+}
+
+#[test]
+fn test_shardedcache_impl_shard_size() {
+    assert_eq!(ShardedCache::<u64, u64>::shard_size(1, 10, 0), 10);
+    assert_eq!(ShardedCache::<u64, u64>::shard_size(4, 10, 0), 3);
+    assert_eq!(ShardedCache::<u64, u64>::shard_size(4, 10, 1), 3);
+    assert_eq!(ShardedCache::<u64, u64>::shard_size(4, 10, 2), 2);
+    assert_eq!(ShardedCache::<u64, u64>::shard_size(4, 10, 3), 2);
+    assert_eq!(ShardedCache::<u64, u64>::shard_size(5, 10, 4), 2);
+    assert_eq!(ShardedCache::<u64, u64>::shard_size(16, 10, 9), 1);
+    assert_eq!(ShardedCache::<u64, u64>::shard_size(16, 10, 10), 0);
+    assert_eq!(ShardedCache::<u64, u64>::shard_size(4, 0, 0), 0);
+}
+
+#[test]
+fn test_shardedcache_impl_new_total_capacity() {
+    for (shard_count, max_size) in [(1, 10), (4, 10), (5, 10), (16, 10), (3, 100), (4, 0)] {
+        let c: ShardedCache<u64, u64> = ShardedCache::new(shard_count, max_size);
+        assert_eq!(c.capacity().unwrap(), max_size);
+        let total: usize = (0..shard_count)
+            .map(|shard| ShardedCache::<u64, u64>::shard_size(shard_count, max_size, shard))
+            .sum();
+        assert_eq!(total, max_size);
+    }
+
+    let c: ShardedCache<u64, u64> = ShardedCache::new(16, 10);
+    for key in 0..1000 {
+        c.insert(key, &Arc::new(key));
+    }
+    assert!(c.len() <= 10);
+    c.set_capacity(7).unwrap();
+    assert_eq!(c.capacity().unwrap(), 7);
+    assert!(c.len() <= 7);
+}
+
+#[test]
+fn test_shardedcache_impl_new_lru() {
+    let c: ShardedCache<u64, u64> = ShardedCache::new(4, 8);
+
+    // The shards evict their least recently used entries
+    let shard0 = keys_of_shard(&c, 0, 3);
+    c.insert(shard0[0], &Arc::new(0));
+    c.insert(shard0[1], &Arc::new(1));
+    assert!(c.get(&shard0[0]).is_some());
+    c.insert(shard0[2], &Arc::new(2));
+    assert!(c.contains_key(&shard0[0]));
+    assert!(!c.contains_key(&shard0[1]));
+    assert!(c.contains_key(&shard0[2]));
+}
+
+#[test]
+#[should_panic]
+fn test_shardedcache_impl_with_engines_empty() {
+    let _c: ShardedCache<u64, u64, LRUCacheEngine<u64, u64>> = ShardedCache::with_engines(vec![]);
+}
+
+#[test]
+fn test_shardedcache_impl_shard_index() {
+    let c: ShardedCache<u64, u64> = ShardedCache::new(8, 80);
+
+    // The same key always goes to the same shard
+    for key in 0..100 {
+        assert_eq!(c.shard_index(&key), c.shard_index(&key));
+        assert!(c.shard_index(&key) < c.shard_count());
+    }
+    // All shards are used
+    for shard in 0..c.shard_count() {
+        assert_eq!(keys_of_shard(&c, shard, 1).len(), 1);
+    }
+}
+
+#[test]
+fn test_shardedcache_valuecache() {
+    let c: ShardedCache<u64, u64> = ShardedCache::new(4, 1000);
+
+    for key in 0..100 {
+        c.insert(key, &Arc::new(key + 100));
+    }
+    assert_eq!(c.len(), 100);
+    assert!(!c.is_empty());
+    for key in 0..100 {
+        assert_eq!(*c.get(&key).unwrap(), key + 100);
+    }
+    assert!(c.get(&100).is_none());
+
+    c.clear();
+    assert_eq!(c.len(), 0);
+    assert!(c.is_empty());
+}
+
+#[test]
+fn test_shardedcache_valuecache_eviction_per_shard() {
+    let c: ShardedCache<u64, u64, LRUCacheEngine<u64, u64>> =
+        ShardedCache::with_engines((0..4).map(|_| LRUCacheEngine::new(5)).collect());
+
+    let shard0 = keys_of_shard(&c, 0, 10);
+    let shard1 = keys_of_shard(&c, 1, 5);
+    for key in shard1.iter() {
+        c.insert(*key, &Arc::new(*key));
+    }
+    for key in shard0.iter() {
+        c.insert(*key, &Arc::new(*key));
+    }
+
+    // Only the shard 0 evicted its oldest entries
+    assert_eq!(c.len(), 10);
+    assert_eq!(c.shards[0].read().unwrap().len(), 5);
+    assert_eq!(c.shards[1].read().unwrap().len(), 5);
+    for key in shard0[..5].iter() {
+        assert!(c.get(key).is_none());
+    }
+    for key in shard0[5..].iter().chain(shard1.iter()) {
+        assert!(c.get(key).is_some());
+    }
+}
+
+#[test]
+fn test_shardedcache_valuecache_concurrent() {
+    const SHARD_SIZE: usize = 16;
+    let c: Arc<ShardedCache<u64, u64, LRUCacheEngine<u64, u64>>> = Arc::new(
+        ShardedCache::with_engines((0..4).map(|_| LRUCacheEngine::new(SHARD_SIZE)).collect()),
+    );
+
+    let mut threads = Vec::new();
+    for t in 0..8 {
+        let tc = Arc::clone(&c);
+        threads.push(std::thread::spawn(move || {
+            for i in 0..1000 {
+                let key = t * 1000 + i;
+                tc.insert(key, &Arc::new(key));
+                if let Some(v) = tc.get(&key) {
+                    assert_eq!(*v, key);
+                }
+                // Recently inserted keys of other threads may be present
+                if let Some(v) = tc.get(&(key ^ 1)) {
+                    assert_eq!(*v, key ^ 1);
+                }
+            }
+        }));
+    }
+    for t in threads {
+        t.join().unwrap();
+    }
+
+    // Each shard must be full but never above its capacity
+    for shard in c.shards.iter() {
+        assert_eq!(shard.read().unwrap().len(), SHARD_SIZE);
+    }
+    assert_eq!(c.len(), 4 * SHARD_SIZE);
+}
+
+#[test]
+fn test_shardedcache_valuecache_stats() {
+    let mut c: ShardedCache<u64, u64, LRUCacheEngine<u64, u64>> =
+        ShardedCache::with_engines((0..4).map(|_| LRUCacheEngine::new(5)).collect());
+    assert!(!c.stats_enabled());
+    c.insert(1, &Arc::new(1));
+    assert_eq!(
+        c.stats(),
+        CacheStats {
+            size: 1,
+            ..Default::default()
+        }
+    );
+
+    c.clear();
+
+    c.set_stats_enabled(true);
+    assert!(c.stats_enabled());
+    let shard0 = keys_of_shard(&c, 0, 7);
+    let shard1 = keys_of_shard(&c, 1, 2);
+    for key in shard0.iter().chain(shard1.iter()) {
+        c.insert(*key, &Arc::new(*key));
+    }
+    c.insert(shard1[0], &Arc::new(0));
+    for key in shard0.iter().chain(shard1.iter()) {
+        c.get(key);
+    }
+    let stats = c.stats();
+    assert_eq!(stats.inserts, 10);
+    assert_eq!(stats.replacements, 1);
+    // Only the shard 0 reached its capacity
+    assert_eq!(stats.evictions, 2);
+    assert_eq!(stats.hits, 7);
+    assert_eq!(stats.misses, 2);
+    assert_eq!(stats.size, c.len());
+
+    c.reset_stats();
+    assert_eq!(
+        c.stats(),
+        CacheStats {
+            size: c.len(),
+            ..Default::default()
+        }
+    );
+
+    c.set_stats_enabled(false);
+    c.get(&shard0[0]);
+    assert_eq!(c.stats().misses, 0);
+}
+
+#[test]
+fn test_shardedcache_valuecache_eviction_listener() {
+    let mut c: ShardedCache<u64, u64, LRUCacheEngine<u64, u64>> =
+        ShardedCache::with_engines((0..4).map(|_| LRUCacheEngine::new(2)).collect());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = Arc::clone(&log);
+    c.set_eviction_listener(Some(Arc::new(
+        move |k: u64, _v: Arc<u64>, r: EvictionReason| {
+            log2.lock().unwrap().push((k, r));
+        },
+    )));
+
+    let shard0 = keys_of_shard(&c, 0, 3);
+    let shard1 = keys_of_shard(&c, 1, 1);
+    c.insert(shard1[0], &Arc::new(0));
+    for key in shard0.iter() {
+        c.insert(*key, &Arc::new(*key));
+    }
+    c.insert(shard0[2], &Arc::new(0));
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            (shard0[0], EvictionReason::Capacity),
+            (shard0[2], EvictionReason::Replaced)
+        ]
+    );
+
+    log.lock().unwrap().clear();
+    c.clear();
+    let mut cleared = log.lock().unwrap().clone();
+    cleared.sort_by_key(|e| e.0);
+    let mut expected = vec![
+        (shard0[1], EvictionReason::Cleared),
+        (shard0[2], EvictionReason::Cleared),
+        (shard1[0], EvictionReason::Cleared),
+    ];
+    expected.sort_by_key(|e| e.0);
+    assert_eq!(cleared, expected);
+}
+
+#[test]
+fn test_shardedcache_valuecache_remove_peek_contains_key() {
+    let c: ShardedCache<u64, u64, LRUCacheEngine<u64, u64>> =
+        ShardedCache::with_engines((0..4).map(|_| LRUCacheEngine::new(2)).collect());
+
+    let shard0 = keys_of_shard(&c, 0, 3);
+    c.insert(shard0[0], &Arc::new(0));
+    c.insert(shard0[1], &Arc::new(1));
+    assert_eq!(*c.peek(&shard0[0]).unwrap(), 0);
+    assert!(c.contains_key(&shard0[0]));
+    // peek() does not change the recency
+    c.insert(shard0[2], &Arc::new(2));
+    assert!(!c.contains_key(&shard0[0]));
+    assert!(c.peek(&shard0[0]).is_none());
+
+    assert_eq!(*c.remove(&shard0[1]).unwrap(), 1);
+    assert!(c.remove(&shard0[1]).is_none());
+    assert_eq!(c.len(), 1);
+}
+
+#[test]
+fn test_shardedcache_valuecache_get_or_try_insert_with() {
+    let c: ShardedCache<u64, u64> = ShardedCache::new(4, 64);
+
+    for key in 0..8 {
+        let ret: Result<Arc<u64>, Arc<String>> = c.get_or_try_insert_with(key, || Ok(key * 10));
+        assert_eq!(*ret.unwrap(), key * 10);
+    }
+    for key in 0..8 {
+        let ret: Result<Arc<u64>, Arc<String>> =
+            c.get_or_try_insert_with(key, || panic!("Should not be called."));
+        assert_eq!(*ret.unwrap(), key * 10);
+    }
+    let ret = c.get_or_try_insert_with(8, || Err(String::from("failed")));
+    assert_eq!(ret.unwrap_err().as_str(), "failed");
+    assert!(!c.contains_key(&8));
+    assert_eq!(c.len(), 8);
+}
+
+#[test]
+fn test_shardedcache_valuecache_borrowed_keys() {
+    let c: ShardedCache<String, u64> = ShardedCache::new(4, 100);
+
+    for i in 0..20 {
+        c.insert(format!("key{}", i), &Arc::new(i));
+    }
+    for i in 0..20 {
+        let key = format!("key{}", i);
+        assert_eq!(*c.get_borrowed(key.as_str()).unwrap(), i);
+        assert_eq!(*c.peek_borrowed(key.as_str()).unwrap(), i);
+        assert!(c.contains_key_borrowed(key.as_str()));
+    }
+    assert_eq!(*c.remove_borrowed("key3").unwrap(), 3);
+    assert!(!c.contains_key_borrowed("key3"));
+    assert_eq!(c.len(), 19);
+}
+
+#[test]
+fn test_shardedcache_valuecache_poisoned_shard() {
+    let c: Arc<ShardedCache<u64, u64>> = Arc::new(ShardedCache::new(4, 100));
+    let shard0 = keys_of_shard(&c, 0, 2);
+    let shard1 = keys_of_shard(&c, 1, 2);
+    for key in shard0.iter().chain(shard1.iter()) {
+        c.insert(*key, &Arc::new(*key));
+    }
+
+    let c2 = Arc::clone(&c);
+    let ret = std::thread::spawn(move || {
+        let _shard = c2.shards[0].write().unwrap();
+        panic!("Panic inside a guarded section.");
+    })
+    .join();
+    assert!(ret.is_err());
+    assert!(c.shards[0].is_poisoned());
+
+    // Only the poisoned shard is cleared
+    assert_eq!(c.len(), 2);
+    assert!(!c.shards[0].is_poisoned());
+    for key in &shard0 {
+        assert!(c.get(key).is_none());
+    }
+    for key in &shard1 {
+        assert_eq!(*c.get(key).unwrap(), *key);
+    }
+    c.insert(shard0[0], &Arc::new(1));
+    assert_eq!(*c.get(&shard0[0]).unwrap(), 1);
+}
+
+#[test]
+fn test_shardedcache_valuecache_retain_keys() {
+    let mut c: ShardedCache<u64, u64> = ShardedCache::new(4, 100);
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = Arc::clone(&log);
+    c.set_eviction_listener(Some(Arc::new(
+        move |k: u64, _v: Arc<u64>, r: EvictionReason| {
+            log2.lock().unwrap().push((k, r));
+        },
+    )));
+
+    for key in 0..20 {
+        c.insert(key, &Arc::new(key));
+    }
+    let mut keys = c.keys().unwrap();
+    keys.sort_unstable();
+    assert_eq!(keys, (0..20).collect::<Vec<u64>>());
+
+    c.retain(|k, _| k % 4 == 0);
+    let mut keys = c.keys().unwrap();
+    keys.sort_unstable();
+    assert_eq!(keys, vec![0, 4, 8, 12, 16]);
+    let log = log.lock().unwrap();
+    assert_eq!(log.len(), 15);
+    assert!(log
+        .iter()
+        .all(|(k, r)| k % 4 != 0 && *r == EvictionReason::Removed));
+}
+
+#[test]
+fn test_shardedcache_valuecache_extend_remove_many() {
+    let mut c: ShardedCache<u64, u64, LRUCacheEngine<u64, u64>> =
+        ShardedCache::with_engines((0..4).map(|_| LRUCacheEngine::new(2)).collect());
+    c.set_stats_enabled(true);
+
+    let shard0 = keys_of_shard(&c, 0, 3);
+    let shard1 = keys_of_shard(&c, 1, 2);
+    c.extend(
+        shard0
+            .iter()
+            .chain(shard1.iter())
+            .map(|key| (*key, Arc::new(*key))),
+    );
+    assert_eq!(c.len(), 4);
+    assert!(!c.contains_key(&shard0[0]));
+    let stats = c.stats();
+    assert_eq!(stats.inserts, 5);
+    assert_eq!(stats.evictions, 1);
+
+    assert_eq!(c.remove_many(&[shard0[0], shard0[1], shard1[1]]), 2);
+    assert_eq!(c.len(), 2);
+    assert!(c.contains_key(&shard0[2]));
+    assert!(c.contains_key(&shard1[0]));
+    assert_eq!(c.stats().evictions, 1);
+}
+
+#[test]
+fn test_shardedcache_valuecache_set_capacity() {
+    let mut c: ShardedCache<u64, u64, LRUCacheEngine<u64, u64>> =
+        ShardedCache::with_engines((0..4).map(|_| LRUCacheEngine::new(4)).collect());
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = Arc::clone(&log);
+    c.set_eviction_listener(Some(Arc::new(
+        move |k: u64, _v: Arc<u64>, r: EvictionReason| {
+            log2.lock().unwrap().push((k, r));
+        },
+    )));
+    c.set_stats_enabled(true);
+    assert_eq!(c.capacity().unwrap(), 16);
+
+    let shard0 = keys_of_shard(&c, 0, 4);
+    let shard1 = keys_of_shard(&c, 1, 1);
+    for key in shard0.iter().chain(shard1.iter()) {
+        c.insert(*key, &Arc::new(*key));
+    }
+    // The first 3 shards keep at most 2 entries and the last one keeps 1
+    c.set_capacity(7).unwrap();
+    assert_eq!(c.capacity().unwrap(), 7);
+    assert_eq!(c.len(), 3);
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            (shard0[0], EvictionReason::Capacity),
+            (shard0[1], EvictionReason::Capacity)
+        ]
+    );
+    assert_eq!(c.stats().evictions, 2);
+}
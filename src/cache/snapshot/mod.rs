@@ -0,0 +1,284 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements snapshots of the [`SimpleCache`]. A snapshot holds
+//! the keys of the cache, and optionally their values, ordered by their
+//! recency. It can be used to warm-start a cache after a restart.
+//!
+//! Snapshots use the format of the [`crate::simple_serialization`] module:
+//! - `u8`: Format version, currently 1;
+//! - `u8`: Flags. Bit 0 is set if the values are present;
+//! - `u32`: Number of entries;
+//! - For each entry, from the least to the most recently used:
+//!     - `u32` + bytes: The encoded key;
+//!     - `u32` + bytes: The encoded value, only if the values are present;
+//!
+//! Keys and values are encoded by caller-supplied encoders.
+use super::{read_engine, CacheEngine, SimpleCache, ValueCache};
+use crate::simple_serialization::{
+    ErrorKind, Result, SimpleDataDeserializer, SimpleDataSerializer, SimpleSliceDeserializer,
+};
+use std::convert::TryFrom;
+use std::hash::Hash;
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests;
+
+/// Current version of the snapshot format.
+pub const SNAPSHOT_VERSION: u8 = 1;
+
+/// Flag set when the snapshot contains the values of the entries.
+pub const SNAPSHOT_FLAG_VALUES: u8 = 0x01;
+
+/// Writes a length prefixed blob produced by an encoder.
+///
+/// Arguments:
+/// - `serializer`: The serializer;
+/// - `value`: The value to be encoded;
+/// - `encoder`: The encoder;
+fn write_blob<S, T, F>(serializer: &mut S, value: &T, encoder: &F) -> Result<()>
+where
+    S: SimpleDataSerializer + ?Sized,
+    T: ?Sized,
+    F: Fn(&T, &mut Vec<u8>) -> Result<()>,
+{
+    let mut blob = Vec::new();
+    encoder(value, &mut blob)?;
+    let size = u32::try_from(blob.len()).map_err(|_| ErrorKind::UnableToWrite)?;
+    serializer.write_u32(size)?;
+    serializer.write(&blob)
+}
+
+/// Reads a length prefixed blob and decodes it.
+///
+/// Arguments:
+/// - `deserializer`: The deserializer;
+/// - `decoder`: The decoder;
+fn read_blob<D, T, F>(deserializer: &mut D, decoder: &F) -> Result<T>
+where
+    D: SimpleDataDeserializer + ?Sized,
+    F: Fn(&mut SimpleSliceDeserializer) -> Result<T>,
+{
+    let size = deserializer.read_u32()? as usize;
+    deserializer.read(size)?;
+    decoder(&mut SimpleSliceDeserializer::new(deserializer.data()))
+}
+
+/// Skips a length prefixed blob.
+///
+/// Arguments:
+/// - `deserializer`: The deserializer;
+fn skip_blob<D: SimpleDataDeserializer + ?Sized>(deserializer: &mut D) -> Result<()> {
+    let size = deserializer.read_u32()? as usize;
+    deserializer.read(size)
+}
+
+/// Reads the header of a snapshot.
+///
+/// Arguments:
+/// - `deserializer`: The deserializer;
+///
+/// Returns the flags and the number of entries.
+fn read_header<D: SimpleDataDeserializer + ?Sized>(deserializer: &mut D) -> Result<(u8, usize)> {
+    if deserializer.read_u8()? != SNAPSHOT_VERSION {
+        return Err(ErrorKind::UnableToRead);
+    }
+    let flags = deserializer.read_u8()?;
+    if flags & !SNAPSHOT_FLAG_VALUES != 0 {
+        return Err(ErrorKind::UnableToRead);
+    }
+    Ok((flags, deserializer.read_u32()? as usize))
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync, E: CacheEngine<K, V> + Send>
+    SimpleCache<K, V, E>
+{
+    /// Writes a snapshot with the keys of this cache.
+    ///
+    /// Arguments:
+    /// - `serializer`: The serializer that will receive the snapshot;
+    /// - `key_encoder`: The function that encodes the keys;
+    ///
+    /// Returns the number of entries written.
+    pub fn write_snapshot<S, KE>(&self, serializer: &mut S, key_encoder: KE) -> Result<usize>
+    where
+        S: SimpleDataSerializer + ?Sized,
+        KE: Fn(&K, &mut Vec<u8>) -> Result<()>,
+    {
+        self.write_snapshot_entries(
+            serializer,
+            key_encoder,
+            None::<fn(&V, &mut Vec<u8>) -> Result<()>>,
+        )
+    }
+
+    /// Writes a snapshot with the keys and values of this cache.
+    ///
+    /// Arguments:
+    /// - `serializer`: The serializer that will receive the snapshot;
+    /// - `key_encoder`: The function that encodes the keys;
+    /// - `value_encoder`: The function that encodes the values;
+    ///
+    /// Returns the number of entries written.
+    pub fn write_snapshot_with_values<S, KE, VE>(
+        &self,
+        serializer: &mut S,
+        key_encoder: KE,
+        value_encoder: VE,
+    ) -> Result<usize>
+    where
+        S: SimpleDataSerializer + ?Sized,
+        KE: Fn(&K, &mut Vec<u8>) -> Result<()>,
+        VE: Fn(&V, &mut Vec<u8>) -> Result<()>,
+    {
+        self.write_snapshot_entries(serializer, key_encoder, Some(value_encoder))
+    }
+
+    /// Writes a snapshot of this cache.
+    ///
+    /// Arguments:
+    /// - `serializer`: The serializer that will receive the snapshot;
+    /// - `key_encoder`: The function that encodes the keys;
+    /// - `value_encoder`: The function that encodes the values if they must
+    ///   be included;
+    fn write_snapshot_entries<S, KE, VE>(
+        &self,
+        serializer: &mut S,
+        key_encoder: KE,
+        value_encoder: Option<VE>,
+    ) -> Result<usize>
+    where
+        S: SimpleDataSerializer + ?Sized,
+        KE: Fn(&K, &mut Vec<u8>) -> Result<()>,
+        VE: Fn(&V, &mut Vec<u8>) -> Result<()>,
+    {
+        let entries = read_engine(&self.engine).entries();
+        let count = u32::try_from(entries.len()).map_err(|_| ErrorKind::UnableToWrite)?;
+        let flags = if value_encoder.is_some() {
+            SNAPSHOT_FLAG_VALUES
+        } else {
+            0
+        };
+        serializer.write_u8(SNAPSHOT_VERSION)?;
+        serializer.write_u8(flags)?;
+        serializer.write_u32(count)?;
+        for (key, value) in entries.iter() {
+            write_blob(serializer, key, &key_encoder)?;
+            if let Some(value_encoder) = &value_encoder {
+                write_blob(serializer, value.as_ref(), value_encoder)?;
+            }
+        }
+        Ok(entries.len())
+    }
+
+    /// Repopulates this cache with the keys of a snapshot. The values are
+    /// provided by the loader, so the values stored in the snapshot, if any,
+    /// are ignored. The entries are inserted from the least to the most
+    /// recently used, so the most recent ones are kept if the snapshot does
+    /// not fit into this cache. If the snapshot is invalid, the entries
+    /// inserted before the error is found remain in the cache.
+    ///
+    /// Arguments:
+    /// - `deserializer`: The deserializer that holds the snapshot;
+    /// - `key_decoder`: The function that decodes the keys;
+    /// - `loader`: The function that loads the value of each key. Keys whose
+    ///   values cannot be loaded are skipped;
+    ///
+    /// Returns the number of entries inserted.
+    pub fn restore_snapshot<D, KD, L>(
+        &self,
+        deserializer: &mut D,
+        key_decoder: KD,
+        mut loader: L,
+    ) -> Result<usize>
+    where
+        D: SimpleDataDeserializer + ?Sized,
+        KD: Fn(&mut SimpleSliceDeserializer) -> Result<K>,
+        L: FnMut(&K) -> Option<V>,
+    {
+        let (flags, count) = read_header(deserializer)?;
+        let mut inserted = 0;
+        for _ in 0..count {
+            let key = read_blob(deserializer, &key_decoder)?;
+            if flags & SNAPSHOT_FLAG_VALUES != 0 {
+                skip_blob(deserializer)?;
+            }
+            if let Some(value) = loader(&key) {
+                self.insert(key, &Arc::new(value));
+                inserted += 1;
+            }
+        }
+        Ok(inserted)
+    }
+
+    /// Repopulates this cache with the entries of a snapshot that contains the
+    /// values. The entries are inserted from the least to the most recently
+    /// used, so the most recent ones are kept if the snapshot does not fit
+    /// into this cache.
+    ///
+    /// The snapshot is fully decoded before the first insertion, so this cache
+    /// is not modified if the snapshot is invalid.
+    ///
+    /// Arguments:
+    /// - `deserializer`: The deserializer that holds the snapshot;
+    /// - `key_decoder`: The function that decodes the keys;
+    /// - `value_decoder`: The function that decodes the values;
+    ///
+    /// Returns the number of entries inserted.
+    pub fn restore_snapshot_with_values<D, KD, VD>(
+        &self,
+        deserializer: &mut D,
+        key_decoder: KD,
+        value_decoder: VD,
+    ) -> Result<usize>
+    where
+        D: SimpleDataDeserializer + ?Sized,
+        KD: Fn(&mut SimpleSliceDeserializer) -> Result<K>,
+        VD: Fn(&mut SimpleSliceDeserializer) -> Result<V>,
+    {
+        let (flags, count) = read_header(deserializer)?;
+        if flags & SNAPSHOT_FLAG_VALUES == 0 {
+            return Err(ErrorKind::UnableToRead);
+        }
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            let key = read_blob(deserializer, &key_decoder)?;
+            let value = read_blob(deserializer, &value_decoder)?;
+            entries.push((key, value));
+        }
+        let inserted = entries.len();
+        for (key, value) in entries {
+            self.insert(key, &Arc::new(value));
+        }
+        Ok(inserted)
+    }
+}
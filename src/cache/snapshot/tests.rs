@@ -0,0 +1,267 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use crate::cache::lru::{LRUCache, LRUCacheEngine};
+
+/// Encodes an u64 key.
+fn encode_key(key: &u64, out: &mut Vec<u8>) -> Result<()> {
+    out.write_u64(*key)
+}
+
+/// Decodes an u64 key.
+fn decode_key(deserializer: &mut SimpleSliceDeserializer) -> Result<u64> {
+    deserializer.read_u64()
+}
+
+/// Encodes a String value.
+fn encode_value(value: &String, out: &mut Vec<u8>) -> Result<()> {
+    out.write_byte_array(value.as_bytes())
+}
+
+/// Decodes a String value.
+fn decode_value(deserializer: &mut SimpleSliceDeserializer) -> Result<String> {
+    deserializer.read_byte_array()?;
+    String::from_utf8(deserializer.data().to_vec()).map_err(|_| ErrorKind::UnableToRead)
+}
+
+/// Creates a LRU cache with the keys from 0 to `count` - 1. The key 0 is used
+/// last, so it is the most recent entry.
+fn create_cache(max_size: usize, count: u64) -> LRUCache<u64, String> {
+    let c = SimpleCache::with_engine(LRUCacheEngine::new(max_size));
+    for key in 0..count {
+        c.insert(key, &Arc::new(format!("value{}", key)));
+    }
+    c.get(&0).unwrap();
+    c
+}
+
+/// Returns the keys of the cache from the least to the most recently used.
+fn keys_by_recency(c: &LRUCache<u64, String>) -> Vec<u64> {
+    read_engine(&c.engine)
+        .entries()
+        .into_iter()
+        .map(|(key, _)| key)
+        .collect()
+}
+
+//=============================================================================
+// SimpleCache
+//-----------------------------------------------------------------------------
+#[test]
+fn test_simplecache_write_snapshot() {
+    let c = create_cache(10, 3);
+    let mut snapshot: Vec<u8> = Vec::new();
+
+    assert_eq!(c.write_snapshot(&mut snapshot, encode_key).unwrap(), 3);
+    let mut expected: Vec<u8> = vec![SNAPSHOT_VERSION, 0, 0, 0, 0, 3];
+    for key in &[1u64, 2, 0] {
+        expected.extend_from_slice(&[0, 0, 0, 8]);
+        expected.extend_from_slice(&key.to_be_bytes());
+    }
+    assert_eq!(snapshot, expected);
+
+    // Empty cache
+    let c: LRUCache<u64, String> = SimpleCache::with_engine(LRUCacheEngine::new(10));
+    let mut snapshot: Vec<u8> = Vec::new();
+    assert_eq!(c.write_snapshot(&mut snapshot, encode_key).unwrap(), 0);
+    assert_eq!(snapshot, vec![SNAPSHOT_VERSION, 0, 0, 0, 0, 0]);
+}
+
+#[test]
+fn test_simplecache_write_snapshot_with_values() {
+    let c = create_cache(10, 2);
+    let mut snapshot: Vec<u8> = Vec::new();
+
+    assert_eq!(
+        c.write_snapshot_with_values(&mut snapshot, encode_key, encode_value)
+            .unwrap(),
+        2
+    );
+    let mut expected: Vec<u8> = vec![SNAPSHOT_VERSION, SNAPSHOT_FLAG_VALUES, 0, 0, 0, 2];
+    for key in &[1u64, 0] {
+        expected.extend_from_slice(&[0, 0, 0, 8]);
+        expected.extend_from_slice(&key.to_be_bytes());
+        expected.extend_from_slice(&[0, 0, 0, 8, 0, 6]);
+        expected.extend_from_slice(format!("value{}", key).as_bytes());
+    }
+    assert_eq!(snapshot, expected);
+
+    // Encoder errors are propagated
+    let mut snapshot: Vec<u8> = Vec::new();
+    let ret = c.write_snapshot(&mut snapshot, |_, _| Err(ErrorKind::UnableToWrite));
+    assert!(matches!(ret, Err(ErrorKind::UnableToWrite)));
+}
+
+#[test]
+fn test_simplecache_restore_snapshot_with_values() {
+    let c = create_cache(10, 5);
+    let mut snapshot: Vec<u8> = Vec::new();
+    c.write_snapshot_with_values(&mut snapshot, encode_key, encode_value)
+        .unwrap();
+
+    let restored: LRUCache<u64, String> = SimpleCache::with_engine(LRUCacheEngine::new(10));
+    let mut deserializer = SimpleSliceDeserializer::new(&snapshot);
+    assert_eq!(
+        restored
+            .restore_snapshot_with_values(&mut deserializer, decode_key, decode_value)
+            .unwrap(),
+        5
+    );
+    assert!(deserializer.is_empty());
+    assert_eq!(keys_by_recency(&restored), vec![1, 2, 3, 4, 0]);
+    for key in 0..5 {
+        assert_eq!(*restored.peek(&key).unwrap(), format!("value{}", key));
+    }
+
+    // Only the most recent entries fit into a smaller cache
+    let restored: LRUCache<u64, String> = SimpleCache::with_engine(LRUCacheEngine::new(2));
+    let mut deserializer = SimpleSliceDeserializer::new(&snapshot);
+    assert_eq!(
+        restored
+            .restore_snapshot_with_values(&mut deserializer, decode_key, decode_value)
+            .unwrap(),
+        5
+    );
+    assert_eq!(keys_by_recency(&restored), vec![4, 0]);
+}
+
+#[test]
+fn test_simplecache_restore_snapshot_with_values_invalid() {
+    let c = create_cache(10, 5);
+    let mut snapshot: Vec<u8> = Vec::new();
+    c.write_snapshot_with_values(&mut snapshot, encode_key, encode_value)
+        .unwrap();
+    let restored: LRUCache<u64, String> = SimpleCache::with_engine(LRUCacheEngine::new(10));
+
+    // Truncated snapshot
+    let mut deserializer = SimpleSliceDeserializer::new(&snapshot[..snapshot.len() - 1]);
+    assert!(restored
+        .restore_snapshot_with_values(&mut deserializer, decode_key, decode_value)
+        .is_err());
+    assert!(restored.is_empty());
+
+    // Unknown version and flags
+    for (offset, value) in &[(0, SNAPSHOT_VERSION + 1), (1, 0x02)] {
+        let mut invalid = snapshot.clone();
+        invalid[*offset] = *value;
+        let mut deserializer = SimpleSliceDeserializer::new(&invalid);
+        assert!(restored
+            .restore_snapshot_with_values(&mut deserializer, decode_key, decode_value)
+            .is_err());
+    }
+
+    // Snapshot without values
+    let mut snapshot: Vec<u8> = Vec::new();
+    c.write_snapshot(&mut snapshot, encode_key).unwrap();
+    let mut deserializer = SimpleSliceDeserializer::new(&snapshot);
+    assert!(restored
+        .restore_snapshot_with_values(&mut deserializer, decode_key, decode_value)
+        .is_err());
+    assert!(restored.is_empty());
+}
+
+#[test]
+fn test_simplecache_restore_snapshot() {
+    let c = create_cache(10, 5);
+    let restored: LRUCache<u64, String> = SimpleCache::with_engine(LRUCacheEngine::new(10));
+    let loader = |key: &u64| {
+        if *key == 3 {
+            None
+        } else {
+            Some(format!("loaded{}", key))
+        }
+    };
+
+    // Keys only
+    let mut snapshot: Vec<u8> = Vec::new();
+    c.write_snapshot(&mut snapshot, encode_key).unwrap();
+    let mut deserializer = SimpleSliceDeserializer::new(&snapshot);
+    assert_eq!(
+        restored
+            .restore_snapshot(&mut deserializer, decode_key, loader)
+            .unwrap(),
+        4
+    );
+    assert_eq!(keys_by_recency(&restored), vec![1, 2, 4, 0]);
+    assert_eq!(*restored.peek(&1).unwrap(), "loaded1");
+
+    // The values of the snapshot are ignored
+    restored.clear();
+    let mut snapshot: Vec<u8> = Vec::new();
+    c.write_snapshot_with_values(&mut snapshot, encode_key, encode_value)
+        .unwrap();
+    let mut deserializer = SimpleSliceDeserializer::new(&snapshot);
+    assert_eq!(
+        restored
+            .restore_snapshot(&mut deserializer, decode_key, loader)
+            .unwrap(),
+        4
+    );
+    assert!(deserializer.is_empty());
+    assert_eq!(keys_by_recency(&restored), vec![1, 2, 4, 0]);
+    assert_eq!(*restored.peek(&0).unwrap(), "loaded0");
+
+    // Invalid snapshot
+    let mut deserializer = SimpleSliceDeserializer::new(&snapshot[..5]);
+    assert!(restored
+        .restore_snapshot(&mut deserializer, decode_key, loader)
+        .is_err());
+}
+
+#[test]
+fn test_simplecache_snapshot_string_keys() {
+    let c: SimpleCache<String, u64> = SimpleCache::new(10);
+    c.insert(String::from("a"), &Arc::new(1));
+    c.insert(String::from("b"), &Arc::new(2));
+    let mut snapshot: Vec<u8> = Vec::new();
+    c.write_snapshot_with_values(
+        &mut snapshot,
+        |key: &String, out: &mut Vec<u8>| out.write_byte_array(key.as_bytes()),
+        |value: &u64, out: &mut Vec<u8>| out.write_u64(*value),
+    )
+    .unwrap();
+
+    let restored: SimpleCache<String, u64> = SimpleCache::new(10);
+    let mut deserializer = SimpleSliceDeserializer::new(&snapshot);
+    restored
+        .restore_snapshot_with_values(
+            &mut deserializer,
+            |d| {
+                d.read_byte_array()?;
+                String::from_utf8(d.data().to_vec()).map_err(|_| ErrorKind::UnableToRead)
+            },
+            |d| d.read_u64(),
+        )
+        .unwrap();
+    assert_eq!(*restored.get_borrowed("a").unwrap(), 1);
+    assert_eq!(*restored.get_borrowed("b").unwrap(), 2);
+}
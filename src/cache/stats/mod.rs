@@ -0,0 +1,185 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements the statistics collected by the caches of the
+//! [`crate::cache`] module.
+use super::{CacheEviction, EvictionReason};
+use std::ops::Add;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+#[cfg(test)]
+mod tests;
+
+//=============================================================================
+// CacheStats
+//-----------------------------------------------------------------------------
+/// This struct holds a snapshot of the statistics of a cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of lookups that found the entry.
+    pub hits: u64,
+    /// Number of lookups that did not find the entry.
+    pub misses: u64,
+    /// Number of insertions, including the ones that replaced an entry.
+    pub inserts: u64,
+    /// Number of insertions that replaced an existing entry.
+    pub replacements: u64,
+    /// Number of entries removed by the eviction policy of the cache.
+    pub evictions: u64,
+    /// Number of entries in the cache when the snapshot was taken.
+    pub size: usize,
+}
+
+impl CacheStats {
+    /// Returns the ratio between the hits and the total number of lookups. It
+    /// returns 0.0 if no lookup was performed.
+    pub fn hit_ratio(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+impl Add for CacheStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            hits: self.hits + other.hits,
+            misses: self.misses + other.misses,
+            inserts: self.inserts + other.inserts,
+            replacements: self.replacements + other.replacements,
+            evictions: self.evictions + other.evictions,
+            size: self.size + other.size,
+        }
+    }
+}
+
+//=============================================================================
+// CacheStatsCounters
+//-----------------------------------------------------------------------------
+/// This struct implements the counters used to compute the [`CacheStats`]. All
+/// counters are updated atomically, so they can be updated without holding the
+/// locks of the cache.
+#[derive(Default)]
+pub(crate) struct CacheStatsCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    inserts: AtomicU64,
+    replacements: AtomicU64,
+    evictions: AtomicU64,
+}
+
+impl CacheStatsCounters {
+    /// Creates a new `CacheStatsCounters` with all counters set to zero.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the result of a lookup.
+    ///
+    /// Arguments:
+    /// - `hit`: Indicates if the entry was found or not;
+    pub fn record_get(&self, hit: bool) {
+        if hit {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Records an insertion.
+    ///
+    /// Arguments:
+    /// - `evictions`: The entries removed by the insertion;
+    pub fn record_insert<K, V>(&self, evictions: &[CacheEviction<K, V>]) {
+        self.record_inserts(1, evictions);
+    }
+
+    /// Records multiple insertions.
+    ///
+    /// Arguments:
+    /// - `count`: The number of insertions;
+    /// - `evictions`: The entries removed by the insertions;
+    pub fn record_inserts<K, V>(&self, count: u64, evictions: &[CacheEviction<K, V>]) {
+        self.inserts.fetch_add(count, Ordering::Relaxed);
+        self.record_evictions(evictions);
+    }
+
+    /// Records the entries removed from the cache.
+    ///
+    /// Arguments:
+    /// - `evictions`: The entries removed;
+    pub fn record_evictions<K, V>(&self, evictions: &[CacheEviction<K, V>]) {
+        let mut replacements = 0;
+        let mut evicted = 0;
+        for eviction in evictions {
+            match eviction.reason {
+                EvictionReason::Replaced => replacements += 1,
+                EvictionReason::Capacity | EvictionReason::Expired => evicted += 1,
+                EvictionReason::Cleared | EvictionReason::Removed => (),
+            }
+        }
+        if replacements > 0 {
+            self.replacements.fetch_add(replacements, Ordering::Relaxed);
+        }
+        if evicted > 0 {
+            self.evictions.fetch_add(evicted, Ordering::Relaxed);
+        }
+    }
+
+    /// Returns the current value of the counters.
+    ///
+    /// Arguments:
+    /// - `size`: The current number of entries in the cache;
+    pub fn snapshot(&self, size: usize) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            inserts: self.inserts.load(Ordering::Relaxed),
+            replacements: self.replacements.load(Ordering::Relaxed),
+            evictions: self.evictions.load(Ordering::Relaxed),
+            size,
+        }
+    }
+
+    /// Sets all counters to zero.
+    pub fn reset(&self) {
+        self.hits.store(0, Ordering::Relaxed);
+        self.misses.store(0, Ordering::Relaxed);
+        self.inserts.store(0, Ordering::Relaxed);
+        self.replacements.store(0, Ordering::Relaxed);
+        self.evictions.store(0, Ordering::Relaxed);
+    }
+}
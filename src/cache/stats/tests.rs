@@ -0,0 +1,142 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use std::sync::Arc;
+
+//=============================================================================
+// CacheStats
+//-----------------------------------------------------------------------------
+#[test]
+fn test_cachestats_impl_hit_ratio() {
+    let mut s = CacheStats::default();
+    assert_eq!(s.hit_ratio(), 0.0);
+
+    s.hits = 3;
+    assert_eq!(s.hit_ratio(), 1.0);
+    s.misses = 1;
+    assert_eq!(s.hit_ratio(), 0.75);
+    s.hits = 0;
+    assert_eq!(s.hit_ratio(), 0.0);
+}
+
+#[test]
+fn test_cachestats_add() {
+    let a = CacheStats {
+        hits: 1,
+        misses: 2,
+        inserts: 3,
+        replacements: 4,
+        evictions: 5,
+        size: 6,
+    };
+    let b = CacheStats {
+        hits: 10,
+        misses: 20,
+        inserts: 30,
+        replacements: 40,
+        evictions: 50,
+        size: 60,
+    };
+    let exp = CacheStats {
+        hits: 11,
+        misses: 22,
+        inserts: 33,
+        replacements: 44,
+        evictions: 55,
+        size: 66,
+    };
+    assert_eq!(a + b, exp);
+    assert_eq!(a + CacheStats::default(), a);
+}
+
+//=============================================================================
+// CacheStatsCounters
+//-----------------------------------------------------------------------------
+#[test]
+fn test_cachestatscounters_impl() {
+    let c = CacheStatsCounters::new();
+    assert_eq!(c.snapshot(0), CacheStats::default());
+
+    c.record_get(true);
+    c.record_get(true);
+    c.record_get(false);
+    c.record_insert::<u64, u64>(&[]);
+    c.record_insert(&[
+        CacheEviction::new(1, Arc::new(1), EvictionReason::Replaced),
+        CacheEviction::new(2, Arc::new(2), EvictionReason::Capacity),
+    ]);
+    c.record_evictions(&[
+        CacheEviction::new(3, Arc::new(3), EvictionReason::Expired),
+        CacheEviction::new(4, Arc::new(4), EvictionReason::Capacity),
+    ]);
+    assert_eq!(
+        c.snapshot(10),
+        CacheStats {
+            hits: 2,
+            misses: 1,
+            inserts: 2,
+            replacements: 1,
+            evictions: 3,
+            size: 10,
+        }
+    );
+
+    c.reset();
+    assert_eq!(
+        c.snapshot(10),
+        CacheStats {
+            size: 10,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn test_cachestatscounters_impl_concurrent() {
+    let c = Arc::new(CacheStatsCounters::new());
+
+    let mut threads = Vec::new();
+    for _ in 0..4 {
+        let tc = Arc::clone(&c);
+        threads.push(std::thread::spawn(move || {
+            for i in 0..1000 {
+                tc.record_get(i % 2 == 0);
+            }
+        }));
+    }
+    for t in threads {
+        t.join().unwrap();
+    }
+    let s = c.snapshot(0);
+    assert_eq!(s.hits, 2000);
+    assert_eq!(s.misses, 2000);
+}
@@ -30,6 +30,19 @@
  * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
  */
 use super::*;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Barrier, Mutex};
+
+type EvictionLog = Arc<Mutex<Vec<(u64, u64, EvictionReason)>>>;
+
+fn create_eviction_log() -> (EvictionLog, Arc<dyn EvictionListener<u64, u64>>) {
+    let log: EvictionLog = Arc::new(Mutex::new(Vec::new()));
+    let log2 = Arc::clone(&log);
+    let listener = move |k: u64, v: Arc<u64>, r: EvictionReason| {
+        log2.lock().unwrap().push((k, *v, r));
+    };
+    (log, Arc::new(listener))
+}
 
 //=============================================================================
 // CacheEntry
@@ -166,6 +179,179 @@ fn test_simplecacheengine_simplecacheengine_insert() {
     }
 }
 
+#[test]
+fn test_simplecacheengine_simplecacheengine_insert_with_evictions() {
+    let mut e: SimpleCacheEngine<u64, u64> = SimpleCacheEngine::new(2);
+
+    let mut evictions = Vec::new();
+    e.insert_with_evictions(1, &Arc::new(1), &mut evictions);
+    e.insert_with_evictions(2, &Arc::new(2), &mut evictions);
+    assert!(evictions.is_empty());
+
+    e.insert_with_evictions(1, &Arc::new(10), &mut evictions);
+    assert_eq!(evictions.len(), 1);
+    assert_eq!(evictions[0].key, 1);
+    assert_eq!(*evictions[0].value, 1);
+    assert_eq!(evictions[0].reason, EvictionReason::Replaced);
+
+    evictions.clear();
+    e.insert_with_evictions(3, &Arc::new(3), &mut evictions);
+    assert_eq!(evictions.len(), 1);
+    assert_eq!(evictions[0].key, 2);
+    assert_eq!(*evictions[0].value, 2);
+    assert_eq!(evictions[0].reason, EvictionReason::Capacity);
+}
+
+#[test]
+fn test_simplecacheengine_simplecacheengine_remove() {
+    let mut e: SimpleCacheEngine<u64, u64> = SimpleCacheEngine::new(10);
+
+    e.insert(1, &Arc::new(10));
+    e.insert(2, &Arc::new(20));
+    let removed = e.remove(&1).unwrap();
+    assert_eq!(removed.key, 1);
+    assert_eq!(*removed.value, 10);
+    assert_eq!(removed.reason, EvictionReason::Removed);
+    assert!(e.remove(&1).is_none());
+    assert_eq!(e.len(), 1);
+    assert!(e.get(&2).is_some());
+}
+
+#[test]
+fn test_simplecacheengine_simplecacheengine_peek_contains_key() {
+    let mut e: SimpleCacheEngine<u64, u64> = SimpleCacheEngine::new(2);
+
+    e.insert(1, &Arc::new(10));
+    e.insert(2, &Arc::new(20));
+    let counter = e.map.get(&1).unwrap().counter();
+    assert_eq!(*e.peek(&1).unwrap(), 10);
+    assert!(e.contains_key(&1));
+    assert!(e.peek(&3).is_none());
+    assert!(!e.contains_key(&3));
+    // The recency is not updated, so 1 is still the oldest entry
+    assert_eq!(e.map.get(&1).unwrap().counter(), counter);
+    e.insert(3, &Arc::new(30));
+    assert!(!e.contains_key(&1));
+    assert!(e.contains_key(&2));
+}
+
+#[test]
+fn test_simplecacheengine_simplecacheengine_string_keys() {
+    let mut e: SimpleCacheEngine<String, u64> = SimpleCacheEngine::new(2);
+
+    e.insert(String::from("a"), &Arc::new(1));
+    e.insert(String::from("b"), &Arc::new(2));
+    assert_eq!(*e.get("a").unwrap(), 1);
+    assert_eq!(*e.peek("b").unwrap(), 2);
+    assert!(e.contains_key("a"));
+    e.insert(String::from("c"), &Arc::new(3));
+    assert!(!e.contains_key("b"));
+
+    let removed = e.remove("a").unwrap();
+    assert_eq!(removed.key, "a");
+    assert_eq!(*removed.value, 1);
+    assert_eq!(e.len(), 1);
+}
+
+#[test]
+fn test_simplecacheengine_simplecacheengine_entries() {
+    let mut e: SimpleCacheEngine<u64, u64> = SimpleCacheEngine::new(10);
+    assert!(e.entries().is_empty());
+
+    for key in 0..4 {
+        e.insert(key, &Arc::new(key + 10));
+    }
+    e.get(&1).unwrap();
+    let entries: Vec<(u64, u64)> = e.entries().into_iter().map(|(k, v)| (k, *v)).collect();
+    assert_eq!(entries, vec![(0, 10), (2, 12), (3, 13), (1, 11)]);
+}
+
+#[test]
+fn test_simplecacheengine_simplecacheengine_retain_with_evictions() {
+    let mut e: SimpleCacheEngine<u64, u64> = SimpleCacheEngine::new(10);
+
+    for key in 0..6 {
+        e.insert(key, &Arc::new(key + 10));
+    }
+    let mut evictions = Vec::new();
+    e.retain_with_evictions(|k, _| k % 2 == 0, &mut evictions);
+    assert_eq!(e.len(), 3);
+    evictions.sort_by_key(|e| e.key);
+    let removed: Vec<(u64, u64, EvictionReason)> = evictions
+        .iter()
+        .map(|e| (e.key, *e.value, e.reason))
+        .collect();
+    assert_eq!(
+        removed,
+        vec![
+            (1, 11, EvictionReason::Removed),
+            (3, 13, EvictionReason::Removed),
+            (5, 15, EvictionReason::Removed)
+        ]
+    );
+
+    e.retain(|_, v| *v > 10);
+    let mut keys = e.keys();
+    keys.sort_unstable();
+    assert_eq!(keys, vec![2, 4]);
+}
+
+#[test]
+fn test_simplecacheengine_simplecacheengine_set_capacity_with_evictions() {
+    let mut e: SimpleCacheEngine<u64, u64> = SimpleCacheEngine::new(10);
+
+    for key in 0..6 {
+        e.insert(key, &Arc::new(key));
+    }
+    e.get(&0).unwrap();
+    e.get(&1).unwrap();
+    let mut evictions = Vec::new();
+    e.set_capacity_with_evictions(10, &mut evictions);
+    assert!(evictions.is_empty());
+
+    // From the oldest to the most recent
+    e.set_capacity_with_evictions(3, &mut evictions);
+    assert_eq!(e.capacity(), 3);
+    let removed: Vec<(u64, EvictionReason)> = evictions.iter().map(|e| (e.key, e.reason)).collect();
+    assert_eq!(
+        removed,
+        vec![
+            (2, EvictionReason::Capacity),
+            (3, EvictionReason::Capacity),
+            (4, EvictionReason::Capacity)
+        ]
+    );
+    let mut keys = e.keys();
+    keys.sort_unstable();
+    assert_eq!(keys, vec![0, 1, 5]);
+
+    // The new capacity is used by later insertions
+    e.set_capacity(4);
+    e.insert(6, &Arc::new(6));
+    e.insert(7, &Arc::new(7));
+    assert_eq!(e.len(), 4);
+    assert!(!e.contains_key(&5));
+}
+
+#[test]
+fn test_simplecacheengine_simplecacheengine_clear_with_evictions() {
+    let mut e: SimpleCacheEngine<u64, u64> = SimpleCacheEngine::new(10);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(key + 100));
+    }
+    let mut evictions = Vec::new();
+    e.clear_with_evictions(&mut evictions);
+    assert!(e.is_empty());
+    assert_eq!(evictions.len(), 10);
+    evictions.sort_by_key(|e| e.key);
+    for (key, eviction) in evictions.iter().enumerate() {
+        assert_eq!(eviction.key, key as u64);
+        assert_eq!(*eviction.value, key as u64 + 100);
+        assert_eq!(eviction.reason, EvictionReason::Cleared);
+    }
+}
+
 #[test]
 fn test_simplecacheengine_simplecacheengine_clear() {
     let mut e: SimpleCacheEngine<u64, u64> = SimpleCacheEngine::new(10);
@@ -196,6 +382,314 @@ fn test_simplecache_impl() {
     }
 }
 
+#[test]
+fn test_simplecache_impl_new() {
+    // The default engine keeps the exact LRU order
+    let c: lru::LRUCache<u64, u64> = SimpleCache::new(2);
+    assert_eq!(c.engine.read().unwrap().max_size(), 2);
+    c.insert(1, &Arc::new(1));
+    c.insert(2, &Arc::new(2));
+    assert_eq!(c.get(&1), Some(Arc::new(1)));
+    c.insert(3, &Arc::new(3));
+    assert_eq!(c.keys().unwrap().len(), 2);
+    assert!(c.contains_key(&1));
+    assert!(!c.contains_key(&2));
+    assert!(c.contains_key(&3));
+}
+
+#[test]
+fn test_simplecache_impl_set_stats_enabled() {
+    let mut c: SimpleCache<u64, u64> = SimpleCache::new(10);
+    assert!(!c.stats_enabled());
+    c.set_stats_enabled(true);
+    assert!(c.stats_enabled());
+    c.get(&1);
+    assert_eq!(c.stats().misses, 1);
+
+    // Disabling discards the statistics
+    c.set_stats_enabled(false);
+    assert!(!c.stats_enabled());
+    c.set_stats_enabled(true);
+    assert_eq!(c.stats().misses, 0);
+}
+
+#[test]
+fn test_simplecache_valuecache_stats() {
+    let mut c: SimpleCache<u64, u64> = SimpleCache::new(3);
+
+    // Disabled
+    c.insert(1, &Arc::new(1));
+    c.get(&1);
+    assert_eq!(
+        c.stats(),
+        CacheStats {
+            size: 1,
+            ..Default::default()
+        }
+    );
+    c.reset_stats();
+
+    c.set_stats_enabled(true);
+    c.insert(2, &Arc::new(2));
+    c.insert(3, &Arc::new(3));
+    c.insert(1, &Arc::new(10));
+    c.insert(4, &Arc::new(4));
+    c.get(&1).unwrap();
+    c.get(&3).unwrap();
+    assert!(c.get(&2).is_none());
+    assert_eq!(
+        c.stats(),
+        CacheStats {
+            hits: 2,
+            misses: 1,
+            inserts: 4,
+            replacements: 1,
+            evictions: 1,
+            size: 3,
+        }
+    );
+    assert_eq!(c.stats().hit_ratio(), 2.0 / 3.0);
+
+    c.reset_stats();
+    assert_eq!(
+        c.stats(),
+        CacheStats {
+            size: 3,
+            ..Default::default()
+        }
+    );
+}
+
+#[test]
+fn test_simplecache_valuecache_eviction_listener() {
+    let mut c: SimpleCache<u64, u64> = SimpleCache::new(2);
+    let (log, listener) = create_eviction_log();
+    c.set_eviction_listener(Some(listener));
+
+    c.insert(1, &Arc::new(1));
+    c.insert(2, &Arc::new(2));
+    c.insert(1, &Arc::new(10));
+    c.insert(3, &Arc::new(3));
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            (1, 1, EvictionReason::Replaced),
+            (2, 2, EvictionReason::Capacity)
+        ]
+    );
+
+    log.lock().unwrap().clear();
+    c.clear();
+    let mut cleared = log.lock().unwrap().clone();
+    cleared.sort_by_key(|e| e.0);
+    assert_eq!(
+        cleared,
+        vec![
+            (1, 10, EvictionReason::Cleared),
+            (3, 3, EvictionReason::Cleared)
+        ]
+    );
+
+    // Without listener
+    log.lock().unwrap().clear();
+    c.set_eviction_listener(None);
+    c.insert(1, &Arc::new(1));
+    c.clear();
+    assert!(log.lock().unwrap().is_empty());
+}
+
+#[test]
+fn test_simplecache_valuecache_get_or_try_insert_with() {
+    let mut c: SimpleCache<u64, u64> = SimpleCache::new(10);
+    c.set_stats_enabled(true);
+
+    let ret: Result<Arc<u64>, Arc<String>> = c.get_or_try_insert_with(1, || Ok(10));
+    assert_eq!(*ret.unwrap(), 10);
+    assert_eq!(*c.get(&1).unwrap(), 10);
+    let ret: Result<Arc<u64>, Arc<String>> =
+        c.get_or_try_insert_with(1, || panic!("Should not be called."));
+    assert_eq!(*ret.unwrap(), 10);
+
+    // Errors are not cached
+    let ret = c.get_or_try_insert_with(2, || Err(String::from("failed")));
+    assert_eq!(ret.unwrap_err().as_str(), "failed");
+    assert!(!c.contains_key(&2));
+    let ret: Result<Arc<u64>, Arc<String>> = c.get_or_try_insert_with(2, || Ok(20));
+    assert_eq!(*ret.unwrap(), 20);
+
+    let stats = c.stats();
+    assert_eq!(stats.hits, 2);
+    assert_eq!(stats.misses, 3);
+    assert_eq!(stats.inserts, 2);
+}
+
+#[test]
+fn test_simplecache_valuecache_get_or_try_insert_with_concurrent() {
+    let c: Arc<SimpleCache<u64, u64>> = Arc::new(SimpleCache::new(10));
+    let calls = Arc::new(AtomicUsize::new(0));
+    let barrier = Arc::new(Barrier::new(8));
+
+    let mut threads = Vec::new();
+    for _ in 0..8 {
+        let c = Arc::clone(&c);
+        let calls = Arc::clone(&calls);
+        let barrier = Arc::clone(&barrier);
+        threads.push(std::thread::spawn(move || {
+            barrier.wait();
+            c.get_or_try_insert_with(1, || -> Result<u64, String> {
+                calls.fetch_add(1, Ordering::SeqCst);
+                std::thread::sleep(std::time::Duration::from_millis(200));
+                Ok(10)
+            })
+        }));
+    }
+    for t in threads {
+        assert_eq!(*t.join().unwrap().unwrap(), 10);
+    }
+    assert_eq!(calls.load(Ordering::SeqCst), 1);
+    assert_eq!(c.len(), 1);
+}
+
+#[test]
+fn test_simplecache_valuecache_borrowed_keys() {
+    let c: SimpleCache<String, u64> = SimpleCache::new(10);
+
+    c.insert(String::from("a"), &Arc::new(1));
+    assert_eq!(*c.get_borrowed("a").unwrap(), 1);
+    assert_eq!(*c.get_borrowed::<str>("a").unwrap(), 1);
+    assert_eq!(*c.get(&String::from("a")).unwrap(), 1);
+    assert_eq!(*c.peek_borrowed("a").unwrap(), 1);
+    assert!(c.contains_key_borrowed("a"));
+    assert!(c.get_borrowed("b").is_none());
+    let ret: Result<Arc<u64>, Arc<String>> = c.get_or_try_insert_with(String::from("b"), || Ok(2));
+    assert_eq!(*ret.unwrap(), 2);
+    assert_eq!(*c.remove_borrowed("b").unwrap(), 2);
+
+    let c: SimpleCache<Vec<u8>, u64> = SimpleCache::new(10);
+    c.insert(vec![1, 2, 3], &Arc::new(1));
+    assert_eq!(*c.get_borrowed(&[1u8, 2, 3][..]).unwrap(), 1);
+    assert!(c.contains_key_borrowed(&[1u8, 2, 3][..]));
+
+    let c: SimpleCache<Arc<str>, u64> = SimpleCache::new(10);
+    c.insert(Arc::from("id"), &Arc::new(1));
+    assert_eq!(*c.get_borrowed("id").unwrap(), 1);
+}
+
+#[test]
+fn test_simplecache_valuecache_poisoned_read() {
+    let c: Arc<SimpleCache<u64, u64>> = Arc::new(SimpleCache::new(10));
+    c.insert(1, &Arc::new(10));
+    c.insert(2, &Arc::new(20));
+
+    let c2 = Arc::clone(&c);
+    let ret = std::thread::spawn(move || {
+        let _engine = c2.engine.write().unwrap();
+        panic!("Panic inside a guarded section.");
+    })
+    .join();
+    assert!(ret.is_err());
+    assert!(c.engine.is_poisoned());
+
+    // The engine is cleared and the cache keeps working
+    assert!(c.is_empty());
+    assert!(!c.engine.is_poisoned());
+    assert!(c.peek(&1).is_none());
+    c.insert(3, &Arc::new(30));
+    assert_eq!(*c.get(&3).unwrap(), 30);
+    assert_eq!(c.len(), 1);
+}
+
+#[test]
+fn test_simplecache_valuecache_poisoned_write() {
+    let c: Arc<SimpleCache<u64, u64>> = Arc::new(SimpleCache::new(10));
+    c.insert(1, &Arc::new(10));
+
+    let c2 = Arc::clone(&c);
+    let ret = std::thread::spawn(move || {
+        let mut engine = c2.engine.write().unwrap();
+        engine.insert(2, &Arc::new(20));
+        panic!("Panic inside a guarded section.");
+    })
+    .join();
+    assert!(ret.is_err());
+    assert!(c.engine.is_poisoned());
+
+    assert!(c.get(&1).is_none());
+    assert!(c.get(&2).is_none());
+    assert!(!c.engine.is_poisoned());
+    c.insert(1, &Arc::new(11));
+    assert_eq!(*c.get(&1).unwrap(), 11);
+}
+
+#[test]
+fn test_simplecache_valuecache_remove() {
+    let mut c: SimpleCache<u64, u64> = SimpleCache::new(10);
+    let (log, listener) = create_eviction_log();
+    c.set_eviction_listener(Some(listener));
+
+    c.insert(1, &Arc::new(10));
+    c.insert(2, &Arc::new(20));
+    assert_eq!(*c.remove(&1).unwrap(), 10);
+    assert!(c.remove(&1).is_none());
+    assert!(c.get(&1).is_none());
+    assert_eq!(c.len(), 1);
+    assert_eq!(*log.lock().unwrap(), vec![(1, 10, EvictionReason::Removed)]);
+}
+
+#[test]
+fn test_simplecache_valuecache_peek_contains_key() {
+    let c: SimpleCache<u64, u64> = SimpleCache::new(3);
+
+    for key in 1..4 {
+        c.insert(key, &Arc::new(key * 10));
+    }
+    // peek() and contains_key() do not change the recency
+    assert_eq!(*c.peek(&1).unwrap(), 10);
+    assert!(c.contains_key(&1));
+    c.insert(4, &Arc::new(40));
+    assert!(!c.contains_key(&1));
+    assert!(c.peek(&1).is_none());
+
+    // get() does
+    assert_eq!(*c.get(&2).unwrap(), 20);
+    c.insert(5, &Arc::new(50));
+    assert!(c.contains_key(&2));
+    assert!(!c.contains_key(&3));
+}
+
+#[test]
+fn test_simplecache_valuecache_eviction_listener_reentrant() {
+    type Slot = Arc<Mutex<Option<std::sync::Weak<SimpleCache<u64, u64>>>>>;
+    let slot: Slot = Arc::new(Mutex::new(None));
+    let (log, log_listener) = create_eviction_log();
+
+    // The listener is called without any lock held by the cache, so it can
+    // safely access the cache that is notifying it.
+    let mut c: SimpleCache<u64, u64> = SimpleCache::new(1);
+    let slot2 = Arc::clone(&slot);
+    c.set_eviction_listener(Some(Arc::new(
+        move |k: u64, v: Arc<u64>, r: EvictionReason| {
+            let c = slot2.lock().unwrap().as_ref().unwrap().upgrade().unwrap();
+            assert!(c.get(&k).is_none());
+            assert_eq!(c.len(), 1);
+            log_listener.on_evict(k, v, r);
+        },
+    )));
+    let c = Arc::new(c);
+    *slot.lock().unwrap() = Some(Arc::downgrade(&c));
+
+    c.insert(1, &Arc::new(1));
+    c.insert(2, &Arc::new(2));
+    c.insert(3, &Arc::new(3));
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            (1, 1, EvictionReason::Capacity),
+            (2, 2, EvictionReason::Capacity)
+        ]
+    );
+}
+
 #[test]
 fn test_simplecache_valuecache_concurrent_insert() {
     let c: Arc<SimpleCache<u64, u64>> = Arc::new(SimpleCache::new(10));
@@ -225,7 +719,8 @@ fn test_simplecache_valuecache_concurrent_insert() {
 
 #[test]
 fn test_simplecache_valuecache_concurrent_get() {
-    let c: Arc<SimpleCache<u64, u64>> = Arc::new(SimpleCache::new(10));
+    let c: Arc<SimpleCache<u64, u64, SimpleCacheEngine<u64, u64>>> =
+        Arc::new(SimpleCache::with_engine(SimpleCacheEngine::new(10)));
 
     for key in 0..10 as u64 {
         let value = Arc::new(key + 1000);
@@ -282,3 +777,363 @@ fn test_simplecache_valuecache_concurrent_clear() {
     assert_eq!(c.len(), 0);
     assert!(c.is_empty());
 }
+
+#[test]
+fn test_simplecache_valuecache_retain() {
+    let mut c: SimpleCache<u64, u64> = SimpleCache::new(10);
+    let (log, listener) = create_eviction_log();
+    c.set_eviction_listener(Some(listener));
+    c.set_stats_enabled(true);
+
+    for key in 0..6 {
+        c.insert(key, &Arc::new(key + 100));
+    }
+    c.retain(|k, v| k % 3 != 0 && *v != 104);
+    let mut keys = c.keys().unwrap();
+    keys.sort_unstable();
+    assert_eq!(keys, vec![1, 2, 5]);
+    let mut removed = log.lock().unwrap().clone();
+    removed.sort_by_key(|e| e.0);
+    assert_eq!(
+        removed,
+        vec![
+            (0, 100, EvictionReason::Removed),
+            (3, 103, EvictionReason::Removed),
+            (4, 104, EvictionReason::Removed)
+        ]
+    );
+    // Removals requested by the user are not evictions
+    assert_eq!(c.stats().evictions, 0);
+    assert_eq!(c.stats().size, 3);
+}
+
+#[test]
+fn test_simplecache_valuecache_keys() {
+    let c: SimpleCache<u64, u64> = SimpleCache::new(10);
+    assert!(c.keys().unwrap().is_empty());
+
+    for key in 0..4 {
+        c.insert(key, &Arc::new(key));
+    }
+    let mut keys = c.keys().unwrap();
+    // The snapshot is not affected by later changes
+    c.clear();
+    keys.sort_unstable();
+    assert_eq!(keys, vec![0, 1, 2, 3]);
+    assert!(c.keys().unwrap().is_empty());
+}
+
+#[test]
+fn test_simplecache_valuecache_extend() {
+    let mut c: SimpleCache<u64, u64> = SimpleCache::new(3);
+    let (log, listener) = create_eviction_log();
+    c.set_eviction_listener(Some(listener));
+    c.set_stats_enabled(true);
+
+    c.insert(1, &Arc::new(1));
+    c.extend((1..5).map(|key| (key, Arc::new(key + 10))));
+    assert_eq!(c.len(), 3);
+    assert_eq!(*c.peek(&4).unwrap(), 14);
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            (1, 1, EvictionReason::Replaced),
+            (1, 11, EvictionReason::Capacity)
+        ]
+    );
+    assert_eq!(
+        c.stats(),
+        CacheStats {
+            inserts: 5,
+            replacements: 1,
+            evictions: 1,
+            size: 3,
+            ..Default::default()
+        }
+    );
+
+    c.extend(Vec::new());
+    assert_eq!(c.stats().inserts, 5);
+}
+
+#[test]
+fn test_simplecache_valuecache_remove_many() {
+    let mut c: SimpleCache<u64, u64> = SimpleCache::new(10);
+    let (log, listener) = create_eviction_log();
+    c.set_eviction_listener(Some(listener));
+
+    for key in 0..5 {
+        c.insert(key, &Arc::new(key));
+    }
+    assert_eq!(c.remove_many(&[0, 2, 2, 7]), 2);
+    assert_eq!(c.len(), 3);
+    assert!(!c.contains_key(&0));
+    assert!(!c.contains_key(&2));
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            (0, 0, EvictionReason::Removed),
+            (2, 2, EvictionReason::Removed)
+        ]
+    );
+    assert_eq!(c.remove_many(&[]), 0);
+    assert_eq!(c.remove_many(&[1, 3, 4]), 3);
+    assert!(c.is_empty());
+}
+
+#[test]
+fn test_simplecache_valuecache_set_capacity() {
+    let mut c: SimpleCache<u64, u64> = SimpleCache::new(5);
+    let (log, listener) = create_eviction_log();
+    c.set_eviction_listener(Some(listener));
+    c.set_stats_enabled(true);
+    assert_eq!(c.capacity().unwrap(), 5);
+
+    for key in 0..5 {
+        c.insert(key, &Arc::new(key));
+    }
+    c.get(&0).unwrap();
+    c.set_capacity(2).unwrap();
+    assert_eq!(c.capacity().unwrap(), 2);
+    assert_eq!(c.len(), 2);
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![
+            (1, 1, EvictionReason::Capacity),
+            (2, 2, EvictionReason::Capacity),
+            (3, 3, EvictionReason::Capacity)
+        ]
+    );
+    assert_eq!(c.stats().evictions, 3);
+
+    // Growing does not evict anything
+    log.lock().unwrap().clear();
+    c.set_capacity(10).unwrap();
+    for key in 5..13 {
+        c.insert(key, &Arc::new(key));
+    }
+    assert_eq!(c.len(), 10);
+    assert!(log.lock().unwrap().is_empty());
+    assert_eq!(c.stats().evictions, 3);
+}
+
+//=============================================================================
+// ValueCache
+//-----------------------------------------------------------------------------
+/// A cache that implements only the required methods of [`ValueCache`] and the
+/// ones that need a per-key access to the map.
+struct MapCache {
+    map: Mutex<HashMap<u64, Arc<u64>>>,
+}
+
+impl MapCache {
+    fn new() -> Self {
+        Self {
+            map: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl ValueCache<u64, u64> for MapCache {
+    fn get(&self, key: &u64) -> Option<Arc<u64>> {
+        self.map.lock().unwrap().get(key).map(Arc::clone)
+    }
+
+    fn insert(&self, key: u64, value: &Arc<u64>) {
+        self.map.lock().unwrap().insert(key, Arc::clone(value));
+    }
+
+    fn remove(&self, key: &u64) -> Option<Arc<u64>> {
+        self.map.lock().unwrap().remove(key)
+    }
+
+    fn keys(&self) -> io::Result<Vec<u64>> {
+        Ok(self.map.lock().unwrap().keys().copied().collect())
+    }
+
+    fn clear(&self) {
+        self.map.lock().unwrap().clear();
+    }
+
+    fn len(&self) -> usize {
+        self.map.lock().unwrap().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.lock().unwrap().is_empty()
+    }
+}
+
+/// A cache that implements only the required methods of [`ValueCache`].
+struct MinimalCache {
+    inner: MapCache,
+}
+
+impl MinimalCache {
+    fn new() -> Self {
+        Self {
+            inner: MapCache::new(),
+        }
+    }
+}
+
+impl ValueCache<u64, u64> for MinimalCache {
+    fn get(&self, key: &u64) -> Option<Arc<u64>> {
+        self.inner.get(key)
+    }
+
+    fn insert(&self, key: u64, value: &Arc<u64>) {
+        self.inner.insert(key, value)
+    }
+
+    fn clear(&self) {
+        self.inner.clear()
+    }
+
+    fn len(&self) -> usize {
+        self.inner.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.inner.is_empty()
+    }
+}
+
+#[test]
+fn test_valuecache_default_peek_contains_key() {
+    let c = MapCache::new();
+
+    c.insert(1, &Arc::new(10));
+    assert_eq!(*c.peek(&1).unwrap(), 10);
+    assert!(c.peek(&2).is_none());
+    assert!(c.contains_key(&1));
+    assert!(!c.contains_key(&2));
+    c.remove(&1).unwrap();
+    assert!(!c.contains_key(&1));
+}
+
+#[test]
+fn test_valuecache_default_extend() {
+    let c = MapCache::new();
+
+    c.extend((0..5).map(|key| (key, Arc::new(key * 10))));
+    assert_eq!(c.len(), 5);
+    for key in 0..5 {
+        assert_eq!(*c.get(&key).unwrap(), key * 10);
+    }
+}
+
+#[test]
+fn test_valuecache_default_retain() {
+    let c = MapCache::new();
+
+    for key in 0..10 {
+        c.insert(key, &Arc::new(key));
+    }
+    c.retain(|k, v| k % 2 == 0 && *v < 8);
+    let mut keys = c.keys().unwrap();
+    keys.sort_unstable();
+    assert_eq!(keys, vec![0, 2, 4, 6]);
+
+    // Without keys() the whole cache is cleared
+    let c = MinimalCache::new();
+    for key in 0..10 {
+        c.insert(key, &Arc::new(key));
+    }
+    c.retain(|k, _| k % 2 == 0);
+    assert!(c.is_empty());
+}
+
+#[test]
+fn test_valuecache_default_get_or_try_insert_with() {
+    let c = MinimalCache::new();
+
+    let ret: Result<Arc<u64>, Arc<String>> = c.get_or_try_insert_with(1, || Ok(10));
+    assert_eq!(*ret.unwrap(), 10);
+    let ret: Result<Arc<u64>, Arc<String>> = c.get_or_try_insert_with(1, || Ok(20));
+    assert_eq!(*ret.unwrap(), 10);
+    let ret: Result<Arc<u64>, Arc<String>> =
+        c.get_or_try_insert_with(2, || Err(String::from("failed")));
+    assert_eq!(ret.unwrap_err().as_str(), "failed");
+    assert_eq!(c.len(), 1);
+}
+
+#[test]
+fn test_valuecache_default_remove() {
+    let c = MinimalCache::new();
+
+    c.insert(1, &Arc::new(10));
+    c.insert(2, &Arc::new(20));
+    assert!(c.remove(&3).is_none());
+    assert_eq!(c.len(), 2);
+    assert_eq!(*c.remove(&1).unwrap(), 10);
+    assert!(c.is_empty());
+}
+
+#[test]
+fn test_valuecache_default_remove_many() {
+    let c = MapCache::new();
+
+    for key in 0..10 {
+        c.insert(key, &Arc::new(key));
+    }
+    assert_eq!(c.remove_many(&[1, 3, 5, 20]), 3);
+    assert_eq!(c.len(), 7);
+    assert!(!c.contains_key(&3));
+    assert!(c.contains_key(&4));
+}
+
+#[test]
+fn test_valuecache_default_unsupported() {
+    let c = MinimalCache::new();
+
+    c.insert(1, &Arc::new(10));
+    assert_eq!(c.keys().unwrap_err().kind(), io::ErrorKind::Unsupported);
+    assert_eq!(c.capacity().unwrap_err().kind(), io::ErrorKind::Unsupported);
+    assert_eq!(
+        c.set_capacity(10).unwrap_err().kind(),
+        io::ErrorKind::Unsupported
+    );
+    assert_eq!(c.len(), 1);
+}
+
+#[test]
+fn test_valuecache_dyn() {
+    let caches: Vec<Box<dyn ValueCache<u64, u64>>> = vec![
+        Box::new(MapCache::new()),
+        Box::new(SimpleCache::<u64, u64>::new(10)),
+        Box::new(sharded::ShardedCache::<u64, u64>::new(4, 10)),
+    ];
+
+    for c in caches.iter() {
+        c.insert(1, &Arc::new(10));
+        c.insert(2, &Arc::new(20));
+        assert_eq!(*c.get(&1).unwrap(), 10);
+        assert!(c.get(&3).is_none());
+        assert_eq!(*c.peek(&2).unwrap(), 20);
+        assert!(c.contains_key(&2));
+        assert_eq!(*c.remove(&2).unwrap(), 20);
+        assert!(!c.contains_key(&2));
+        assert!(c.remove(&2).is_none());
+        assert_eq!(c.len(), 1);
+    }
+}
+
+#[test]
+fn test_simplecache_valuecache_peek_recency() {
+    let c: SimpleCache<u64, u64> = SimpleCache::new(2);
+
+    c.insert(1, &Arc::new(1));
+    c.insert(2, &Arc::new(2));
+    // peek() and contains_key() do not change the recency
+    assert!(c.peek(&1).is_some());
+    assert!(c.contains_key(&1));
+    c.insert(3, &Arc::new(3));
+    assert!(!c.contains_key(&1));
+
+    // get() does
+    c.get(&2).unwrap();
+    c.insert(4, &Arc::new(4));
+    assert!(c.contains_key(&2));
+    assert!(!c.contains_key(&3));
+}
@@ -0,0 +1,476 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements a cache engine whose entries expire after a given
+//! time-to-live (TTL). Expired entries are treated as misses and are reclaimed
+//! lazily, when they are replaced or reach the end of the eviction queue, or by
+//! an explicit call to [`TTLCacheEngine::purge_expired()`].
+//!
+//! The time is provided by a [`CacheClock`], allowing the use of a
+//! [`ManualCacheClock`] to test the expiration deterministically.
+use super::list::IndexedList;
+use super::{
+    notify_evictions, write_engine, CacheEngine, CacheEviction, EvictionReason, SimpleCache,
+};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests;
+
+//=============================================================================
+// CacheClock
+//-----------------------------------------------------------------------------
+/// This trait is implemented by all clocks used to determine the expiration of
+/// cache entries. The clock is required to be monotonic.
+pub trait CacheClock: Send + Sync {
+    /// Returns the current instant.
+    fn now(&self) -> Instant;
+}
+
+impl<C: CacheClock> CacheClock for Arc<C> {
+    fn now(&self) -> Instant {
+        self.as_ref().now()
+    }
+}
+
+//=============================================================================
+// SystemCacheClock
+//-----------------------------------------------------------------------------
+/// The default [`CacheClock`]. It uses [`Instant::now()`] as its time source.
+#[derive(Default)]
+pub struct SystemCacheClock;
+
+impl CacheClock for SystemCacheClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+//=============================================================================
+// ManualCacheClock
+//-----------------------------------------------------------------------------
+/// A [`CacheClock`] that only moves forward when [`Self::advance()`] is called.
+/// It is useful to test the expiration of entries without relying on the
+/// actual passage of time.
+///
+/// Since the clock is usually moved by the owner of the cache, it is common to
+/// share it using an [`Arc`].
+pub struct ManualCacheClock {
+    start: Instant,
+    elapsed: AtomicU64,
+}
+
+impl ManualCacheClock {
+    /// Creates a new `ManualCacheClock`. The initial instant is the instant of
+    /// its creation.
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            elapsed: AtomicU64::new(0),
+        }
+    }
+
+    /// Moves the clock forward.
+    ///
+    /// Arguments:
+    /// - `duration`: The amount of time to move. It is truncated to
+    ///   nanoseconds;
+    pub fn advance(&self, duration: Duration) {
+        self.elapsed
+            .fetch_add(duration.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Default for ManualCacheClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CacheClock for ManualCacheClock {
+    fn now(&self) -> Instant {
+        self.start + Duration::from_nanos(self.elapsed.load(Ordering::SeqCst))
+    }
+}
+
+//=============================================================================
+// TTLCacheEntry
+//-----------------------------------------------------------------------------
+/// An entry of the [`TTLCacheEngine`].
+struct TTLCacheEntry<K, V> {
+    key: K,
+    value: Arc<V>,
+    expiration: Option<Instant>,
+}
+
+impl<K, V> TTLCacheEntry<K, V> {
+    /// Verifies if this entry is expired.
+    ///
+    /// Arguments:
+    /// - `now`: The current instant;
+    fn expired(&self, now: Instant) -> bool {
+        match self.expiration {
+            Some(expiration) => expiration <= now,
+            None => false,
+        }
+    }
+
+    /// Returns the reason used to report the removal of this entry. Expired
+    /// entries are always reported as [`EvictionReason::Expired`].
+    ///
+    /// Arguments:
+    /// - `now`: The current instant;
+    /// - `reason`: The reason of the removal if the entry is not expired;
+    fn eviction_reason(&self, now: Instant, reason: EvictionReason) -> EvictionReason {
+        if self.expired(now) {
+            EvictionReason::Expired
+        } else {
+            reason
+        }
+    }
+}
+
+//=============================================================================
+// TTLCacheEngine
+//-----------------------------------------------------------------------------
+/// This struct implements a [`CacheEngine`] whose entries expire after a
+/// certain amount of time. When it reaches its maximum capacity it will drop
+/// the least recently used entry.
+///
+/// Entries are inserted with the default TTL of the engine unless
+/// [`Self::insert_with_ttl()`] is used. Expired entries are never returned but
+/// they are still counted by [`CacheEngine::len()`] until they are reclaimed.
+///
+/// This struct is not thread safe and must have its concurrency protected by
+/// an external [`std::sync::RwLock`] or other synchronization primitive.
+pub struct TTLCacheEngine<
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: CacheClock = SystemCacheClock,
+> {
+    map: HashMap<K, usize>,
+    list: IndexedList<TTLCacheEntry<K, V>>,
+    max_size: usize,
+    ttl: Duration,
+    clock: C,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> TTLCacheEngine<K, V> {
+    /// Creates a new `TTLCacheEngine` that uses the [`SystemCacheClock`].
+    ///
+    /// Arguments:
+    /// - `max_size`: Maximum number of items in the cache;
+    /// - `ttl`: The default time-to-live of the entries;
+    pub fn new(max_size: usize, ttl: Duration) -> Self {
+        Self::with_clock(max_size, ttl, SystemCacheClock)
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync, C: CacheClock> TTLCacheEngine<K, V, C> {
+    /// Creates a new `TTLCacheEngine` with a custom clock.
+    ///
+    /// Arguments:
+    /// - `max_size`: Maximum number of items in the cache;
+    /// - `ttl`: The default time-to-live of the entries;
+    /// - `clock`: The clock used to determine the expiration of the entries;
+    pub fn with_clock(max_size: usize, ttl: Duration, clock: C) -> Self {
+        Self {
+            map: HashMap::new(),
+            list: IndexedList::new(),
+            max_size,
+            ttl,
+            clock,
+        }
+    }
+
+    /// Returns the default time-to-live of the entries.
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    /// Inserts the value into the cache with a specific time-to-live.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `value`: A reference to an [`Arc`] that points to the value;
+    /// - `ttl`: The time-to-live of this entry;
+    pub fn insert_with_ttl(&mut self, key: K, value: &Arc<V>, ttl: Duration) {
+        let mut evictions = Vec::new();
+        self.insert_with_ttl_and_evictions(key, value, ttl, &mut evictions);
+    }
+
+    /// Inserts the value into the cache with a specific time-to-live and
+    /// reports the entries removed by this operation.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `value`: A reference to an [`Arc`] that points to the value;
+    /// - `ttl`: The time-to-live of this entry;
+    /// - `evictions`: The vector that will receive the removed entries;
+    pub fn insert_with_ttl_and_evictions(
+        &mut self,
+        key: K,
+        value: &Arc<V>,
+        ttl: Duration,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
+        let now = self.clock.now();
+        let expiration = now.checked_add(ttl);
+        if let Some(&index) = self.map.get(&key) {
+            let entry = self.list.get_mut(index);
+            let reason = entry.eviction_reason(now, EvictionReason::Replaced);
+            let old = std::mem::replace(&mut entry.value, Arc::clone(value));
+            entry.expiration = expiration;
+            self.list.move_to_front(index);
+            evictions.push(CacheEviction::new(key, old, reason));
+        } else {
+            let index = self.list.push_front(TTLCacheEntry {
+                key: key.clone(),
+                value: Arc::clone(value),
+                expiration,
+            });
+            self.map.insert(key, index);
+            if self.list.len() > self.max_size {
+                if let Some(entry) = self.list.pop_back() {
+                    self.map.remove(&entry.key);
+                    let reason = entry.eviction_reason(now, EvictionReason::Capacity);
+                    evictions.push(CacheEviction::new(entry.key, entry.value, reason));
+                }
+            }
+        }
+    }
+
+    /// Removes all expired entries from the cache.
+    ///
+    /// Returns the number of entries removed.
+    pub fn purge_expired(&mut self) -> usize {
+        let mut evictions = Vec::new();
+        self.purge_expired_with_evictions(&mut evictions);
+        evictions.len()
+    }
+
+    /// Removes all expired entries from the cache and reports them.
+    ///
+    /// Arguments:
+    /// - `evictions`: The vector that will receive the removed entries;
+    pub fn purge_expired_with_evictions(&mut self, evictions: &mut Vec<CacheEviction<K, V>>) {
+        let now = self.clock.now();
+        for entry in self.list.remove_if(|entry| entry.expired(now)) {
+            self.map.remove(&entry.key);
+            evictions.push(CacheEviction::new(
+                entry.key,
+                entry.value,
+                EvictionReason::Expired,
+            ));
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync, C: CacheClock> CacheEngine<K, V>
+    for TTLCacheEngine<K, V, C>
+{
+    fn get<Q>(&mut self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        if self.list.get(index).expired(self.clock.now()) {
+            return None;
+        }
+        self.list.move_to_front(index);
+        Some(Arc::clone(&self.list.get(index).value))
+    }
+
+    fn insert_with_evictions(
+        &mut self,
+        key: K,
+        value: &Arc<V>,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
+        self.insert_with_ttl_and_evictions(key, value, self.ttl, evictions)
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<CacheEviction<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let index = self.map.remove(key)?;
+        let entry = self.list.remove(index);
+        let reason = entry.eviction_reason(self.clock.now(), EvictionReason::Removed);
+        Some(CacheEviction::new(entry.key, entry.value, reason))
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.peek(key).is_some()
+    }
+
+    fn peek<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let entry = self.list.get(*self.map.get(key)?);
+        if entry.expired(self.clock.now()) {
+            None
+        } else {
+            Some(Arc::clone(&entry.value))
+        }
+    }
+
+    fn entries(&self) -> Vec<(K, Arc<V>)> {
+        let now = self.clock.now();
+        self.list
+            .iter()
+            .filter(|entry| !entry.expired(now))
+            .map(|entry| (entry.key.clone(), Arc::clone(&entry.value)))
+            .collect()
+    }
+
+    /// Expired entries are always removed and reported as
+    /// [`EvictionReason::Expired`] without being passed to the predicate.
+    fn retain_with_evictions<F>(
+        &mut self,
+        mut predicate: F,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let now = self.clock.now();
+        for entry in self
+            .list
+            .remove_if(|entry| entry.expired(now) || !predicate(&entry.key, &entry.value))
+        {
+            self.map.remove(&entry.key);
+            let reason = entry.eviction_reason(now, EvictionReason::Removed);
+            evictions.push(CacheEviction::new(entry.key, entry.value, reason));
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.max_size
+    }
+
+    fn set_capacity_with_evictions(
+        &mut self,
+        capacity: usize,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
+        self.max_size = capacity;
+        let now = self.clock.now();
+        while self.list.len() > self.max_size {
+            match self.list.pop_back() {
+                Some(entry) => {
+                    self.map.remove(&entry.key);
+                    let reason = entry.eviction_reason(now, EvictionReason::Capacity);
+                    evictions.push(CacheEviction::new(entry.key, entry.value, reason));
+                }
+                None => break,
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.list.clear();
+    }
+
+    fn clear_with_evictions(&mut self, evictions: &mut Vec<CacheEviction<K, V>>) {
+        let now = self.clock.now();
+        for entry in self.list.drain() {
+            let reason = entry.eviction_reason(now, EvictionReason::Cleared);
+            evictions.push(CacheEviction::new(entry.key, entry.value, reason));
+        }
+        self.map.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.list.len() == 0
+    }
+}
+
+//=============================================================================
+// TTLCache
+//-----------------------------------------------------------------------------
+/// A thread safe [`SimpleCache`] that uses the [`TTLCacheEngine`]. It can be
+/// created with `SimpleCache::with_engine(TTLCacheEngine::new(max_size, ttl))`.
+pub type TTLCache<K, V, C = SystemCacheClock> = SimpleCache<K, V, TTLCacheEngine<K, V, C>>;
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync, C: CacheClock> TTLCache<K, V, C> {
+    /// Inserts the value into the cache with a specific time-to-live.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    /// - `value`: A reference to an [`Arc`] that points to the value;
+    /// - `ttl`: The time-to-live of this entry;
+    pub fn insert_with_ttl(&self, key: K, value: &Arc<V>, ttl: Duration) {
+        let mut evictions = Vec::new();
+        {
+            let mut s = write_engine(&self.engine);
+            s.insert_with_ttl_and_evictions(key, value, ttl, &mut evictions);
+        }
+        if let Some(stats) = &self.stats {
+            stats.record_insert(&evictions);
+        }
+        notify_evictions(&self.listener, evictions);
+    }
+
+    /// Removes all expired entries from the cache.
+    ///
+    /// Returns the number of entries removed.
+    pub fn purge_expired(&self) -> usize {
+        let mut evictions = Vec::new();
+        {
+            let mut s = write_engine(&self.engine);
+            s.purge_expired_with_evictions(&mut evictions);
+        }
+        if let Some(stats) = &self.stats {
+            stats.record_evictions(&evictions);
+        }
+        let count = evictions.len();
+        notify_evictions(&self.listener, evictions);
+        count
+    }
+}
@@ -0,0 +1,485 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use crate::cache::ValueCache;
+use std::sync::Mutex;
+
+//=============================================================================
+// SystemCacheClock
+//-----------------------------------------------------------------------------
+#[test]
+fn test_systemcacheclock_cacheclock_now() {
+    let c = SystemCacheClock;
+    let t1 = c.now();
+    let t2 = c.now();
+    assert!(t1 <= t2);
+}
+
+//=============================================================================
+// ManualCacheClock
+//-----------------------------------------------------------------------------
+#[test]
+fn test_manualcacheclock_impl() {
+    let c = ManualCacheClock::new();
+    let t0 = c.now();
+    assert_eq!(c.now(), t0);
+
+    c.advance(Duration::from_secs(1));
+    assert_eq!(c.now(), t0 + Duration::from_secs(1));
+    c.advance(Duration::from_millis(1));
+    assert_eq!(c.now(), t0 + Duration::from_millis(1001));
+
+    let shared = Arc::new(c);
+    assert_eq!(CacheClock::now(&shared), t0 + Duration::from_millis(1001));
+}
+
+//=============================================================================
+// TTLCacheEntry
+//-----------------------------------------------------------------------------
+#[test]
+fn test_ttlcacheentry_impl_expired() {
+    let now = Instant::now();
+    let mut e = TTLCacheEntry {
+        key: 1,
+        value: Arc::new(1),
+        expiration: Some(now + Duration::from_secs(1)),
+    };
+    assert!(!e.expired(now));
+    assert!(e.expired(now + Duration::from_secs(1)));
+    assert!(e.expired(now + Duration::from_secs(2)));
+
+    e.expiration = None;
+    assert!(!e.expired(now + Duration::from_secs(2)));
+}
+
+//=============================================================================
+// TTLCacheEngine
+//-----------------------------------------------------------------------------
+fn create_engine(
+    max_size: usize,
+) -> (
+    Arc<ManualCacheClock>,
+    TTLCacheEngine<u64, u64, Arc<ManualCacheClock>>,
+) {
+    let clock = Arc::new(ManualCacheClock::new());
+    let e = TTLCacheEngine::with_clock(max_size, Duration::from_secs(10), Arc::clone(&clock));
+    (clock, e)
+}
+
+#[test]
+fn test_ttlcacheengine_impl_new() {
+    let e: TTLCacheEngine<u64, u64> = TTLCacheEngine::new(10, Duration::from_secs(5));
+    assert_eq!(e.max_size, 10);
+    assert_eq!(e.ttl(), Duration::from_secs(5));
+    assert!(e.is_empty());
+}
+
+#[test]
+fn test_ttlcacheengine_cacheengine_get_expired() {
+    let (clock, mut e) = create_engine(10);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(key + 100));
+    }
+    clock.advance(Duration::from_secs(9));
+    for key in 0..10 {
+        assert_eq!(*e.get(&key).unwrap(), key + 100);
+    }
+
+    // Expired entries are misses but are still there until reclaimed
+    clock.advance(Duration::from_secs(1));
+    for key in 0..10 {
+        assert!(e.get(&key).is_none());
+    }
+    assert_eq!(e.len(), 10);
+
+    // Inserting new entries reclaims the oldest expired entries
+    let mut evictions = Vec::new();
+    for key in 10..15 {
+        e.insert_with_evictions(key, &Arc::new(key), &mut evictions);
+    }
+    assert_eq!(e.len(), 10);
+    assert_eq!(evictions.len(), 5);
+    for (key, eviction) in evictions.iter().enumerate() {
+        assert_eq!(eviction.key, key as u64);
+        assert_eq!(eviction.reason, EvictionReason::Expired);
+    }
+}
+
+#[test]
+fn test_ttlcacheengine_impl_insert_with_ttl() {
+    let (clock, mut e) = create_engine(10);
+
+    e.insert_with_ttl(1, &Arc::new(1), Duration::from_secs(1));
+    e.insert_with_ttl(2, &Arc::new(2), Duration::from_secs(20));
+    e.insert_with_ttl(3, &Arc::new(3), Duration::MAX);
+    e.insert(4, &Arc::new(4));
+
+    clock.advance(Duration::from_secs(1));
+    assert!(e.get(&1).is_none());
+    assert!(e.get(&2).is_some());
+    assert!(e.get(&3).is_some());
+    assert!(e.get(&4).is_some());
+
+    clock.advance(Duration::from_secs(9));
+    assert!(e.get(&2).is_some());
+    assert!(e.get(&3).is_some());
+    assert!(e.get(&4).is_none());
+
+    // Replacing an entry also renews its TTL
+    e.insert_with_ttl(5, &Arc::new(5), Duration::from_secs(1));
+    e.insert(5, &Arc::new(50));
+    clock.advance(Duration::from_secs(9));
+    assert_eq!(*e.get(&5).unwrap(), 50);
+    assert!(e.get(&3).is_some());
+}
+
+#[test]
+fn test_ttlcacheengine_cacheengine_insert_capacity() {
+    let (_clock, mut e) = create_engine(3);
+
+    e.insert(1, &Arc::new(1));
+    e.insert(2, &Arc::new(2));
+    e.insert(3, &Arc::new(3));
+    e.get(&1).unwrap();
+    let mut evictions = Vec::new();
+    e.insert_with_evictions(4, &Arc::new(4), &mut evictions);
+    assert_eq!(evictions.len(), 1);
+    assert_eq!(evictions[0].key, 2);
+    assert_eq!(evictions[0].reason, EvictionReason::Capacity);
+    assert_eq!(e.len(), 3);
+    assert!(e.get(&2).is_none());
+    assert!(e.get(&1).is_some());
+    assert!(e.get(&3).is_some());
+    assert!(e.get(&4).is_some());
+}
+
+#[test]
+fn test_ttlcacheengine_impl_purge_expired() {
+    let (clock, mut e) = create_engine(10);
+
+    for key in 0..10 {
+        let ttl = Duration::from_secs(if key % 2 == 0 { 1 } else { 5 });
+        e.insert_with_ttl(key, &Arc::new(key), ttl);
+    }
+    assert_eq!(e.purge_expired(), 0);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(e.purge_expired(), 5);
+    assert_eq!(e.len(), 5);
+    assert_eq!(e.map.len(), 5);
+    for key in 0..10 {
+        assert_eq!(e.get(&key).is_some(), key % 2 == 1);
+    }
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(e.purge_expired(), 5);
+    assert!(e.is_empty());
+}
+
+#[test]
+fn test_ttlcacheengine_cacheengine_remove() {
+    let (clock, mut e) = create_engine(10);
+
+    e.insert_with_ttl(1, &Arc::new(1), Duration::from_secs(1));
+    e.insert(2, &Arc::new(2));
+    e.insert(3, &Arc::new(3));
+    let removed = e.remove(&2).unwrap();
+    assert_eq!(removed.key, 2);
+    assert_eq!(removed.reason, EvictionReason::Removed);
+    assert!(e.remove(&2).is_none());
+
+    // Expired entries are reported as expired
+    clock.advance(Duration::from_secs(1));
+    let removed = e.remove(&1).unwrap();
+    assert_eq!(removed.key, 1);
+    assert_eq!(removed.reason, EvictionReason::Expired);
+    assert_eq!(e.len(), 1);
+}
+
+#[test]
+fn test_ttlcacheengine_cacheengine_peek_contains_key() {
+    let (clock, mut e) = create_engine(2);
+
+    e.insert_with_ttl(1, &Arc::new(1), Duration::from_secs(1));
+    e.insert(2, &Arc::new(2));
+    assert_eq!(*e.peek(&1).unwrap(), 1);
+    assert!(e.contains_key(&1));
+    // The recency is not updated
+    e.insert(3, &Arc::new(3));
+    assert!(!e.contains_key(&1));
+
+    e.insert_with_ttl(4, &Arc::new(4), Duration::from_secs(1));
+    clock.advance(Duration::from_secs(1));
+    assert!(e.peek(&4).is_none());
+    assert!(!e.contains_key(&4));
+    assert!(e.contains_key(&3));
+}
+
+#[test]
+fn test_ttlcacheengine_cacheengine_string_keys() {
+    let clock = Arc::new(ManualCacheClock::new());
+    let mut e: TTLCacheEngine<String, u64, _> =
+        TTLCacheEngine::with_clock(10, Duration::from_secs(10), Arc::clone(&clock));
+
+    e.insert(String::from("a"), &Arc::new(1));
+    e.insert_with_ttl(String::from("b"), &Arc::new(2), Duration::from_secs(1));
+    assert_eq!(*e.get("a").unwrap(), 1);
+    assert!(e.contains_key("b"));
+    clock.advance(Duration::from_secs(1));
+    assert!(e.get("b").is_none());
+    assert!(e.peek("b").is_none());
+    assert_eq!(e.remove("b").unwrap().reason, EvictionReason::Expired);
+    assert_eq!(e.remove("a").unwrap().reason, EvictionReason::Removed);
+    assert!(e.is_empty());
+}
+
+#[test]
+fn test_ttlcacheengine_cacheengine_entries() {
+    let (clock, mut e) = create_engine(10);
+
+    e.insert(1, &Arc::new(1));
+    e.insert_with_ttl(2, &Arc::new(2), Duration::from_secs(1));
+    e.insert(3, &Arc::new(3));
+    e.get(&1).unwrap();
+    let keys: Vec<u64> = e.entries().into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![2, 3, 1]);
+
+    // Expired entries are not included
+    clock.advance(Duration::from_secs(1));
+    let keys: Vec<u64> = e.entries().into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![3, 1]);
+}
+
+#[test]
+fn test_ttlcacheengine_cacheengine_retain_with_evictions() {
+    let (clock, mut e) = create_engine(10);
+
+    e.insert_with_ttl(0, &Arc::new(0), Duration::from_secs(1));
+    for key in 1..4 {
+        e.insert(key, &Arc::new(key));
+    }
+    clock.advance(Duration::from_secs(1));
+    let mut visited = Vec::new();
+    let mut evictions = Vec::new();
+    e.retain_with_evictions(
+        |k, _| {
+            visited.push(*k);
+            *k != 2
+        },
+        &mut evictions,
+    );
+    // Expired entries are removed without calling the predicate
+    assert_eq!(visited, vec![1, 2, 3]);
+    let removed: Vec<(u64, EvictionReason)> = evictions.iter().map(|e| (e.key, e.reason)).collect();
+    assert_eq!(
+        removed,
+        vec![(0, EvictionReason::Expired), (2, EvictionReason::Removed)]
+    );
+    assert_eq!(e.len(), 2);
+    assert_eq!(e.map.len(), 2);
+    assert_eq!(e.keys(), vec![1, 3]);
+}
+
+#[test]
+fn test_ttlcacheengine_cacheengine_set_capacity_with_evictions() {
+    let (clock, mut e) = create_engine(10);
+
+    e.insert_with_ttl(0, &Arc::new(0), Duration::from_secs(1));
+    for key in 1..4 {
+        e.insert(key, &Arc::new(key));
+    }
+    clock.advance(Duration::from_secs(1));
+    let mut evictions = Vec::new();
+    e.set_capacity_with_evictions(2, &mut evictions);
+    assert_eq!(e.capacity(), 2);
+    let removed: Vec<(u64, EvictionReason)> = evictions.iter().map(|e| (e.key, e.reason)).collect();
+    assert_eq!(
+        removed,
+        vec![(0, EvictionReason::Expired), (1, EvictionReason::Capacity)]
+    );
+    assert_eq!(e.map.len(), 2);
+    assert_eq!(e.keys(), vec![2, 3]);
+}
+
+#[test]
+fn test_ttlcacheengine_cacheengine_clear_with_evictions() {
+    let (clock, mut e) = create_engine(10);
+
+    e.insert_with_ttl(0, &Arc::new(0), Duration::from_secs(1));
+    e.insert(1, &Arc::new(1));
+    clock.advance(Duration::from_secs(1));
+    let mut evictions = Vec::new();
+    e.clear_with_evictions(&mut evictions);
+    assert!(e.is_empty());
+    assert_eq!(e.map.len(), 0);
+    assert_eq!(evictions.len(), 2);
+    assert_eq!(evictions[0].key, 0);
+    assert_eq!(evictions[0].reason, EvictionReason::Expired);
+    assert_eq!(evictions[1].key, 1);
+    assert_eq!(evictions[1].reason, EvictionReason::Cleared);
+}
+
+#[test]
+fn test_ttlcacheengine_cacheengine_clear() {
+    let (_clock, mut e) = create_engine(10);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(key));
+    }
+    e.clear();
+    assert!(e.is_empty());
+    assert_eq!(e.map.len(), 0);
+}
+
+//=============================================================================
+// TTLCache
+//-----------------------------------------------------------------------------
+#[test]
+fn test_ttlcache_impl() {
+    let clock = Arc::new(ManualCacheClock::new());
+    let c: TTLCache<u64, u64, Arc<ManualCacheClock>> = SimpleCache::with_engine(
+        TTLCacheEngine::with_clock(10, Duration::from_secs(10), Arc::clone(&clock)),
+    );
+
+    c.insert(1, &Arc::new(1));
+    c.insert_with_ttl(2, &Arc::new(2), Duration::from_secs(1));
+    c.insert_with_ttl(3, &Arc::new(3), Duration::from_secs(1));
+    assert_eq!(c.len(), 3);
+
+    clock.advance(Duration::from_secs(1));
+    assert!(c.get(&2).is_none());
+    assert_eq!(c.len(), 3);
+    assert_eq!(c.purge_expired(), 2);
+    assert_eq!(c.len(), 1);
+    assert_eq!(*c.get(&1).unwrap(), 1);
+}
+
+#[test]
+fn test_ttlcache_stats() {
+    let clock = Arc::new(ManualCacheClock::new());
+    let mut c: TTLCache<u64, u64, Arc<ManualCacheClock>> = SimpleCache::with_engine(
+        TTLCacheEngine::with_clock(2, Duration::from_secs(10), Arc::clone(&clock)),
+    );
+    c.set_stats_enabled(true);
+
+    c.insert_with_ttl(1, &Arc::new(1), Duration::from_secs(1));
+    c.insert(2, &Arc::new(2));
+    clock.advance(Duration::from_secs(1));
+    // Replacing an expired entry is not a replacement
+    c.insert(1, &Arc::new(1));
+    c.insert_with_ttl(3, &Arc::new(3), Duration::from_secs(1));
+    clock.advance(Duration::from_secs(1));
+    assert!(c.get(&3).is_none());
+    assert_eq!(c.purge_expired(), 1);
+
+    let stats = c.stats();
+    assert_eq!(stats.inserts, 4);
+    assert_eq!(stats.replacements, 0);
+    assert_eq!(stats.evictions, 3);
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.size, 1);
+}
+
+#[test]
+fn test_ttlcache_eviction_listener() {
+    let clock = Arc::new(ManualCacheClock::new());
+    let mut c: TTLCache<u64, u64, Arc<ManualCacheClock>> = SimpleCache::with_engine(
+        TTLCacheEngine::with_clock(2, Duration::from_secs(10), Arc::clone(&clock)),
+    );
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = Arc::clone(&log);
+    c.set_eviction_listener(Some(Arc::new(
+        move |k: u64, _v: Arc<u64>, r: EvictionReason| {
+            log2.lock().unwrap().push((k, r));
+        },
+    )));
+
+    c.insert_with_ttl(1, &Arc::new(1), Duration::from_secs(1));
+    c.insert_with_ttl(2, &Arc::new(2), Duration::from_secs(1));
+    c.insert_with_ttl(2, &Arc::new(2), Duration::from_secs(2));
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(c.purge_expired(), 1);
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![(2, EvictionReason::Replaced), (1, EvictionReason::Expired)]
+    );
+}
+
+#[test]
+fn test_ttlcache_remove_expired() {
+    let clock = Arc::new(ManualCacheClock::new());
+    let mut c: TTLCache<u64, u64, Arc<ManualCacheClock>> = SimpleCache::with_engine(
+        TTLCacheEngine::with_clock(2, Duration::from_secs(10), Arc::clone(&clock)),
+    );
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let log2 = Arc::clone(&log);
+    c.set_eviction_listener(Some(Arc::new(
+        move |k: u64, _v: Arc<u64>, r: EvictionReason| {
+            log2.lock().unwrap().push((k, r));
+        },
+    )));
+
+    c.insert_with_ttl(1, &Arc::new(1), Duration::from_secs(1));
+    c.insert(2, &Arc::new(2));
+    clock.advance(Duration::from_secs(1));
+    assert!(c.remove(&1).is_none());
+    assert_eq!(*c.remove(&2).unwrap(), 2);
+    assert!(c.is_empty());
+    assert_eq!(
+        *log.lock().unwrap(),
+        vec![(1, EvictionReason::Expired), (2, EvictionReason::Removed)]
+    );
+}
+
+#[test]
+fn test_ttlcache_bulk_operations() {
+    let clock = Arc::new(ManualCacheClock::new());
+    let mut c: TTLCache<u64, u64, Arc<ManualCacheClock>> = SimpleCache::with_engine(
+        TTLCacheEngine::with_clock(10, Duration::from_secs(10), Arc::clone(&clock)),
+    );
+    c.set_stats_enabled(true);
+
+    c.extend((0..6).map(|key| (key, Arc::new(key))));
+    c.insert_with_ttl(6, &Arc::new(6), Duration::from_secs(1));
+    clock.advance(Duration::from_secs(1));
+    // Expired entries are not listed
+    let mut keys = c.keys().unwrap();
+    keys.sort_unstable();
+    assert_eq!(keys, vec![0, 1, 2, 3, 4, 5]);
+
+    c.retain(|k, _| *k != 0);
+    assert_eq!(c.len(), 5);
+    assert_eq!(c.remove_many(&[1, 2, 6]), 2);
+    assert_eq!(c.len(), 3);
+    let stats = c.stats();
+    assert_eq!(stats.inserts, 7);
+    assert_eq!(stats.evictions, 1);
+}
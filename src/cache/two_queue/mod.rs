@@ -0,0 +1,386 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements a scan-resistant [`CacheEngine`] based on the 2Q
+//! replacement policy.
+use super::list::IndexedList;
+use super::{CacheEngine, CacheEviction, EvictionReason, SimpleCache};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests;
+
+//=============================================================================
+// TwoQueueSlot
+//-----------------------------------------------------------------------------
+/// Position of an entry inside the queues of the [`TwoQueueCacheEngine`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum TwoQueueSlot {
+    /// The entry is in the queue of recent entries.
+    Recent(usize),
+    /// The entry is in the queue of frequent entries.
+    Frequent(usize),
+}
+
+//=============================================================================
+// TwoQueueCacheEngine
+//-----------------------------------------------------------------------------
+/// This struct implements a [`CacheEngine`] that uses the 2Q replacement
+/// policy. Unlike the LRU policy, a single pass over a large number of keys
+/// does not flush the entries that are used frequently.
+///
+/// New entries are placed in a FIFO queue of recent entries. Entries that are
+/// used again while in this queue are promoted to an LRU queue of frequent
+/// entries. When the cache is full, the entries are evicted from the recent
+/// queue while it is larger than its share of the capacity, so entries that
+/// are used only once never displace the frequent ones.
+///
+/// The keys evicted from the recent queue are remembered for a while as ghost
+/// entries. If a ghost key is inserted again, it goes straight to the frequent
+/// queue.
+///
+/// This struct is not thread safe and must have its concurrency protected by
+/// an external [`std::sync::RwLock`] or other synchronization primitive.
+pub struct TwoQueueCacheEngine<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> {
+    map: HashMap<K, TwoQueueSlot>,
+    recent: IndexedList<(K, Arc<V>)>,
+    frequent: IndexedList<(K, Arc<V>)>,
+    ghost_map: HashMap<K, usize>,
+    ghosts: IndexedList<K>,
+    max_size: usize,
+    recent_size: usize,
+    ghost_size: usize,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> TwoQueueCacheEngine<K, V> {
+    /// Creates a new `TwoQueueCacheEngine` with a given capacity. The recent
+    /// queue gets 25% of the capacity while up to 50% of the capacity is used
+    /// to remember ghost keys.
+    ///
+    /// Arguments:
+    /// - `max_size`: Maximum number of items in the cache;
+    pub fn new(max_size: usize) -> Self {
+        Self::with_queue_sizes(max_size, (max_size / 4).max(1), max_size / 2)
+    }
+
+    /// Creates a new `TwoQueueCacheEngine` with a given capacity and queue
+    /// sizes.
+    ///
+    /// Arguments:
+    /// - `max_size`: Maximum number of items in the cache;
+    /// - `recent_size`: Share of the capacity reserved to the recent entries;
+    /// - `ghost_size`: Maximum number of ghost keys;
+    pub fn with_queue_sizes(max_size: usize, recent_size: usize, ghost_size: usize) -> Self {
+        Self {
+            map: HashMap::new(),
+            recent: IndexedList::new(),
+            frequent: IndexedList::new(),
+            ghost_map: HashMap::new(),
+            ghosts: IndexedList::new(),
+            max_size,
+            recent_size,
+            ghost_size,
+        }
+    }
+
+    /// Returns the maximum number of items in the cache.
+    pub fn max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Returns the share of the capacity reserved to the recent entries.
+    pub fn recent_size(&self) -> usize {
+        self.recent_size
+    }
+
+    /// Returns the maximum number of ghost keys.
+    pub fn ghost_size(&self) -> usize {
+        self.ghost_size
+    }
+
+    /// Returns the value of the entry stored in the given slot.
+    ///
+    /// Arguments:
+    /// - `slot`: The slot of the entry;
+    fn value(&self, slot: TwoQueueSlot) -> Arc<V> {
+        match slot {
+            TwoQueueSlot::Recent(index) => Arc::clone(&self.recent.get(index).1),
+            TwoQueueSlot::Frequent(index) => Arc::clone(&self.frequent.get(index).1),
+        }
+    }
+
+    /// Removes the entry stored in the given slot from its queue.
+    ///
+    /// Arguments:
+    /// - `slot`: The slot of the entry;
+    fn take(&mut self, slot: TwoQueueSlot) -> (K, Arc<V>) {
+        match slot {
+            TwoQueueSlot::Recent(index) => self.recent.remove(index),
+            TwoQueueSlot::Frequent(index) => self.frequent.remove(index),
+        }
+    }
+
+    /// Remembers a key evicted from the recent queue, forgetting the oldest
+    /// ghost key if required.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    fn add_ghost(&mut self, key: K) {
+        if self.ghost_size == 0 {
+            return;
+        }
+        let index = self.ghosts.push_front(key.clone());
+        self.ghost_map.insert(key, index);
+        if self.ghosts.len() > self.ghost_size {
+            if let Some(oldest) = self.ghosts.pop_back() {
+                self.ghost_map.remove(&oldest);
+            }
+        }
+    }
+
+    /// Forgets a ghost key.
+    ///
+    /// Arguments:
+    /// - `key`: The key;
+    ///
+    /// Returns true if the key was a ghost key.
+    fn remove_ghost<Q>(&mut self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        match self.ghost_map.remove(key) {
+            Some(index) => {
+                self.ghosts.remove(index);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Evicts entries until the cache fits its capacity.
+    ///
+    /// Arguments:
+    /// - `evictions`: The vector that will receive the removed entries;
+    fn reclaim(&mut self, evictions: &mut Vec<CacheEviction<K, V>>) {
+        while self.len() > self.max_size {
+            let (key, value) = if self.recent.len() > self.recent_size || self.frequent.len() == 0 {
+                match self.recent.pop_back() {
+                    Some((key, value)) => {
+                        self.add_ghost(key.clone());
+                        (key, value)
+                    }
+                    None => return,
+                }
+            } else {
+                match self.frequent.pop_back() {
+                    Some(entry) => entry,
+                    None => return,
+                }
+            };
+            self.map.remove(&key);
+            evictions.push(CacheEviction::new(key, value, EvictionReason::Capacity));
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> CacheEngine<K, V>
+    for TwoQueueCacheEngine<K, V>
+{
+    fn get<Q>(&mut self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let slot = self.map.get_mut(key)?;
+        match *slot {
+            TwoQueueSlot::Recent(index) => {
+                // A second use promotes the entry to the frequent queue
+                let entry = self.recent.remove(index);
+                let value = Arc::clone(&entry.1);
+                *slot = TwoQueueSlot::Frequent(self.frequent.push_front(entry));
+                Some(value)
+            }
+            TwoQueueSlot::Frequent(index) => {
+                self.frequent.move_to_front(index);
+                Some(Arc::clone(&self.frequent.get(index).1))
+            }
+        }
+    }
+
+    fn insert_with_evictions(
+        &mut self,
+        key: K,
+        value: &Arc<V>,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
+        match self.map.get(&key) {
+            Some(&TwoQueueSlot::Recent(index)) => {
+                let old = std::mem::replace(&mut self.recent.get_mut(index).1, Arc::clone(value));
+                evictions.push(CacheEviction::new(key, old, EvictionReason::Replaced));
+            }
+            Some(&TwoQueueSlot::Frequent(index)) => {
+                let old = std::mem::replace(&mut self.frequent.get_mut(index).1, Arc::clone(value));
+                self.frequent.move_to_front(index);
+                evictions.push(CacheEviction::new(key, old, EvictionReason::Replaced));
+            }
+            None => {
+                let slot = if self.remove_ghost(&key) {
+                    TwoQueueSlot::Frequent(
+                        self.frequent.push_front((key.clone(), Arc::clone(value))),
+                    )
+                } else {
+                    TwoQueueSlot::Recent(self.recent.push_front((key.clone(), Arc::clone(value))))
+                };
+                self.map.insert(key, slot);
+                self.reclaim(evictions);
+            }
+        }
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<CacheEviction<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.remove_ghost(key);
+        let slot = self.map.remove(key)?;
+        let (key, value) = self.take(slot);
+        Some(CacheEviction::new(key, value, EvictionReason::Removed))
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    fn peek<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let slot = *self.map.get(key)?;
+        Some(self.value(slot))
+    }
+
+    fn entries(&self) -> Vec<(K, Arc<V>)> {
+        // The frequent entries come last so they are the last ones to be
+        // dropped if the entries do not fit into another engine
+        self.recent
+            .iter()
+            .chain(self.frequent.iter())
+            .map(|(key, value)| (key.clone(), Arc::clone(value)))
+            .collect()
+    }
+
+    fn retain_with_evictions<F>(
+        &mut self,
+        mut predicate: F,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) where
+        F: FnMut(&K, &V) -> bool,
+    {
+        let mut removed = self.recent.remove_if(|(key, value)| !predicate(key, value));
+        removed.extend(
+            self.frequent
+                .remove_if(|(key, value)| !predicate(key, value)),
+        );
+        for (key, value) in removed {
+            self.map.remove(&key);
+            evictions.push(CacheEviction::new(key, value, EvictionReason::Removed));
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.max_size
+    }
+
+    /// The share of the recent queue and the maximum number of ghost keys are
+    /// scaled in the same proportion as the capacity.
+    fn set_capacity_with_evictions(
+        &mut self,
+        capacity: usize,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
+        if self.max_size == 0 {
+            self.recent_size = (capacity / 4).max(1);
+            self.ghost_size = capacity / 2;
+        } else {
+            let max_size = self.max_size as u128;
+            let scale = |size: usize| size as u128 * capacity as u128 / max_size;
+            self.recent_size = (scale(self.recent_size) as usize).max(1);
+            self.ghost_size = scale(self.ghost_size) as usize;
+        }
+        self.max_size = capacity;
+        self.reclaim(evictions);
+        while self.ghosts.len() > self.ghost_size {
+            if let Some(oldest) = self.ghosts.pop_back() {
+                self.ghost_map.remove(&oldest);
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.recent.clear();
+        self.frequent.clear();
+        self.ghost_map.clear();
+        self.ghosts.clear();
+    }
+
+    fn clear_with_evictions(&mut self, evictions: &mut Vec<CacheEviction<K, V>>) {
+        for (key, value) in self.recent.drain().into_iter().chain(self.frequent.drain()) {
+            evictions.push(CacheEviction::new(key, value, EvictionReason::Cleared));
+        }
+        self.clear();
+    }
+
+    fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+}
+
+//=============================================================================
+// TwoQueueCache
+//-----------------------------------------------------------------------------
+/// A thread safe [`SimpleCache`] that uses the [`TwoQueueCacheEngine`]. It can
+/// be created with `SimpleCache::with_engine(TwoQueueCacheEngine::new(max_size))`.
+pub type TwoQueueCache<K, V> = SimpleCache<K, V, TwoQueueCacheEngine<K, V>>;
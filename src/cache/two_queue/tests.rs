@@ -0,0 +1,332 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use crate::cache::lru::LRUCacheEngine;
+use crate::cache::ValueCache;
+
+/// Number of entries used by the hot set of the scan tests.
+const HOT_SET: u64 = 20;
+
+/// Number of distinct keys read by the scan tests.
+const SCAN_SIZE: u64 = 10_000;
+
+/// Simulates a workload where a small hot set is used repeatedly and then a
+/// one-pass scan reads a large number of keys only once. All reads follow the
+/// pattern "get; on miss, load and insert".
+///
+/// Arguments:
+/// - `cache`: The cache;
+///
+/// Returns the hit ratio of the hot set after the scan.
+fn run_hot_set_scan<E: CacheEngine<u64, u64> + Send>(cache: &mut SimpleCache<u64, u64, E>) -> f64 {
+    let read = |cache: &SimpleCache<u64, u64, E>, key: u64| {
+        let ret: Result<Arc<u64>, Arc<()>> = cache.get_or_try_insert_with(key, || Ok(key));
+        assert_eq!(*ret.unwrap(), key);
+    };
+
+    for _ in 0..10 {
+        for key in 0..HOT_SET {
+            read(cache, key);
+        }
+    }
+    for key in HOT_SET..HOT_SET + SCAN_SIZE {
+        read(cache, key);
+    }
+
+    cache.set_stats_enabled(true);
+    for key in 0..HOT_SET {
+        read(cache, key);
+    }
+    cache.stats().hit_ratio()
+}
+
+//=============================================================================
+// TwoQueueCacheEngine
+//-----------------------------------------------------------------------------
+#[test]
+fn test_twoqueuecacheengine_impl_new() {
+    let e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::new(100);
+    assert_eq!(e.max_size(), 100);
+    assert_eq!(e.recent_size(), 25);
+    assert_eq!(e.ghost_size(), 50);
+    assert!(e.is_empty());
+
+    let e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::new(2);
+    assert_eq!(e.recent_size(), 1);
+    assert_eq!(e.ghost_size(), 1);
+
+    let e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::with_queue_sizes(10, 3, 4);
+    assert_eq!(e.max_size(), 10);
+    assert_eq!(e.recent_size(), 3);
+    assert_eq!(e.ghost_size(), 4);
+}
+
+#[test]
+fn test_twoqueuecacheengine_cacheengine_get() {
+    let mut e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::new(10);
+
+    for key in 0..5 {
+        e.insert(key, &Arc::new(key + 100));
+    }
+    assert_eq!(e.recent.len(), 5);
+    assert_eq!(e.frequent.len(), 0);
+
+    // The second use promotes the entries
+    for key in 0..5 {
+        assert_eq!(*e.get(&key).unwrap(), key + 100);
+    }
+    assert_eq!(e.recent.len(), 0);
+    assert_eq!(e.frequent.len(), 5);
+    assert_eq!(*e.get(&0).unwrap(), 100);
+    assert_eq!(e.frequent.get(e.frequent.back().unwrap()).0, 1);
+    assert!(e.get(&5).is_none());
+    assert_eq!(e.len(), 5);
+}
+
+#[test]
+fn test_twoqueuecacheengine_cacheengine_insert_with_evictions() {
+    let mut e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::with_queue_sizes(4, 2, 2);
+    let mut evictions = Vec::new();
+
+    // 0 and 1 become frequent
+    for key in 0..2 {
+        e.insert(key, &Arc::new(key));
+        e.get(&key).unwrap();
+    }
+    for key in 2..6 {
+        e.insert_with_evictions(key, &Arc::new(key), &mut evictions);
+    }
+    // Only the recent entries are evicted
+    let evicted: Vec<u64> = evictions.iter().map(|e| e.key).collect();
+    assert_eq!(evicted, vec![2, 3]);
+    assert!(evictions
+        .iter()
+        .all(|e| e.reason == EvictionReason::Capacity));
+    assert!(e.contains_key(&0));
+    assert!(e.contains_key(&1));
+    assert_eq!(e.ghosts.len(), 2);
+
+    // Reinserting a ghost key makes it frequent
+    evictions.clear();
+    e.insert_with_evictions(2, &Arc::new(2), &mut evictions);
+    assert!(matches!(e.map.get(&2), Some(TwoQueueSlot::Frequent(_))));
+    assert!(!e.ghost_map.contains_key(&2));
+    // The recent queue is at its share, so the oldest frequent is evicted
+    assert_eq!(evictions.len(), 1);
+    assert_eq!(evictions[0].key, 0);
+
+    // Replacements
+    evictions.clear();
+    e.insert_with_evictions(2, &Arc::new(20), &mut evictions);
+    e.insert_with_evictions(5, &Arc::new(50), &mut evictions);
+    assert_eq!(evictions.len(), 2);
+    assert!(evictions
+        .iter()
+        .all(|e| e.reason == EvictionReason::Replaced));
+    assert_eq!(*e.peek(&2).unwrap(), 20);
+    assert_eq!(*e.peek(&5).unwrap(), 50);
+    assert_eq!(e.len(), 4);
+}
+
+#[test]
+fn test_twoqueuecacheengine_cacheengine_ghosts_limit() {
+    let mut e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::with_queue_sizes(2, 1, 3);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(key));
+    }
+    assert_eq!(e.len(), 2);
+    assert_eq!(e.ghosts.len(), 3);
+    assert_eq!(e.ghost_map.len(), 3);
+    for key in 5..8 {
+        assert!(e.ghost_map.contains_key(&key));
+    }
+
+    let mut e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::with_queue_sizes(2, 1, 0);
+    for key in 0..10 {
+        e.insert(key, &Arc::new(key));
+    }
+    assert_eq!(e.len(), 2);
+    assert_eq!(e.ghosts.len(), 0);
+}
+
+#[test]
+fn test_twoqueuecacheengine_cacheengine_remove_peek_contains_key() {
+    let mut e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::with_queue_sizes(4, 2, 2);
+
+    for key in 0..4 {
+        e.insert(key, &Arc::new(key));
+    }
+    e.get(&0).unwrap();
+    // peek() does not promote the entry
+    assert_eq!(*e.peek(&1).unwrap(), 1);
+    assert!(e.contains_key(&1));
+    assert_eq!(e.recent.len(), 3);
+
+    let removed = e.remove(&0).unwrap();
+    assert_eq!(removed.key, 0);
+    assert_eq!(removed.reason, EvictionReason::Removed);
+    let removed = e.remove(&1).unwrap();
+    assert_eq!(removed.key, 1);
+    assert!(e.remove(&1).is_none());
+    assert!(!e.contains_key(&1));
+    assert!(e.peek(&1).is_none());
+    assert_eq!(e.len(), 2);
+
+    // Removing a key also forgets its ghost
+    for key in 4..8 {
+        e.insert(key, &Arc::new(key));
+    }
+    assert!(e.ghost_map.contains_key(&3));
+    assert!(e.remove(&3).is_none());
+    assert!(!e.ghost_map.contains_key(&3));
+}
+
+#[test]
+fn test_twoqueuecacheengine_cacheengine_entries() {
+    let mut e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::new(10);
+
+    for key in 0..4 {
+        e.insert(key, &Arc::new(key));
+    }
+    e.get(&2).unwrap();
+    e.get(&0).unwrap();
+    // The recent entries come first
+    let keys: Vec<u64> = e.entries().into_iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, vec![1, 3, 2, 0]);
+}
+
+#[test]
+fn test_twoqueuecacheengine_cacheengine_retain_with_evictions() {
+    let mut e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::new(10);
+
+    for key in 0..6 {
+        e.insert(key, &Arc::new(key));
+    }
+    e.get(&2).unwrap();
+    e.get(&3).unwrap();
+    let mut evictions = Vec::new();
+    e.retain_with_evictions(|k, _| k % 2 == 1, &mut evictions);
+    let mut removed: Vec<(u64, EvictionReason)> =
+        evictions.iter().map(|e| (e.key, e.reason)).collect();
+    removed.sort_by_key(|e| e.0);
+    assert_eq!(
+        removed,
+        vec![
+            (0, EvictionReason::Removed),
+            (2, EvictionReason::Removed),
+            (4, EvictionReason::Removed)
+        ]
+    );
+    assert_eq!(e.len(), 3);
+    assert_eq!(e.recent.len(), 2);
+    assert_eq!(e.frequent.len(), 1);
+    assert_eq!(e.keys(), vec![1, 5, 3]);
+    // Removed entries do not become ghosts
+    assert!(e.ghost_map.is_empty());
+}
+
+#[test]
+fn test_twoqueuecacheengine_cacheengine_set_capacity_with_evictions() {
+    let mut e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::new(8);
+    assert_eq!(e.recent_size(), 2);
+    assert_eq!(e.ghost_size(), 4);
+
+    for key in 0..8 {
+        e.insert(key, &Arc::new(key));
+    }
+    e.get(&0).unwrap();
+    e.get(&1).unwrap();
+    let mut evictions = Vec::new();
+    e.set_capacity_with_evictions(4, &mut evictions);
+    assert_eq!(e.capacity(), 4);
+    assert_eq!(e.recent_size(), 1);
+    assert_eq!(e.ghost_size(), 2);
+    // The recent entries are evicted first
+    let removed: Vec<(u64, EvictionReason)> = evictions.iter().map(|e| (e.key, e.reason)).collect();
+    assert_eq!(
+        removed,
+        vec![
+            (2, EvictionReason::Capacity),
+            (3, EvictionReason::Capacity),
+            (4, EvictionReason::Capacity),
+            (5, EvictionReason::Capacity)
+        ]
+    );
+    assert_eq!(e.keys(), vec![6, 7, 0, 1]);
+    // Only the most recent ghosts are kept
+    assert_eq!(e.ghosts.len(), 2);
+    assert!(e.ghost_map.contains_key(&4));
+    assert!(e.ghost_map.contains_key(&5));
+
+    e.set_capacity(16);
+    assert_eq!(e.recent_size(), 4);
+    assert_eq!(e.ghost_size(), 8);
+    assert_eq!(e.len(), 4);
+}
+
+#[test]
+fn test_twoqueuecacheengine_cacheengine_clear_with_evictions() {
+    let mut e: TwoQueueCacheEngine<u64, u64> = TwoQueueCacheEngine::with_queue_sizes(4, 2, 2);
+
+    for key in 0..6 {
+        e.insert(key, &Arc::new(key));
+    }
+    e.get(&5).unwrap();
+    let mut evictions = Vec::new();
+    e.clear_with_evictions(&mut evictions);
+    let mut cleared: Vec<u64> = evictions.iter().map(|e| e.key).collect();
+    cleared.sort_unstable();
+    assert_eq!(cleared, vec![2, 3, 4, 5]);
+    assert!(evictions
+        .iter()
+        .all(|e| e.reason == EvictionReason::Cleared));
+    assert!(e.is_empty());
+    assert_eq!(e.recent.len(), 0);
+    assert_eq!(e.frequent.len(), 0);
+    assert_eq!(e.ghosts.len(), 0);
+    assert!(e.ghost_map.is_empty());
+}
+
+//=============================================================================
+// TwoQueueCache
+//-----------------------------------------------------------------------------
+#[test]
+fn test_twoqueuecache_hot_set_survives_scan() {
+    let mut c: TwoQueueCache<u64, u64> = SimpleCache::with_engine(TwoQueueCacheEngine::new(100));
+    assert_eq!(run_hot_set_scan(&mut c), 1.0);
+    assert_eq!(c.len(), 100);
+
+    // The same workload flushes the hot set of a LRU cache
+    let mut c = SimpleCache::with_engine(LRUCacheEngine::new(100));
+    assert_eq!(run_hot_set_scan(&mut c), 0.0);
+}
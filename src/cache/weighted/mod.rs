@@ -0,0 +1,312 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements a cache engine bounded by the total weight of its
+//! entries instead of the number of entries. The weight of each entry is
+//! computed by a user supplied function, usually its size in bytes.
+use super::list::IndexedList;
+use super::{read_engine, CacheEngine, CacheEviction, EvictionReason, SimpleCache};
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+#[cfg(test)]
+mod tests;
+
+/// Type of the function used to compute the weight of an entry.
+pub type CacheWeigher<K, V> = Box<dyn Fn(&K, &V) -> usize + Send + Sync>;
+
+//=============================================================================
+// WeightedCacheEntry
+//-----------------------------------------------------------------------------
+/// An entry of the [`WeightedCacheEngine`].
+struct WeightedCacheEntry<K, V> {
+    key: K,
+    value: Arc<V>,
+    weight: usize,
+}
+
+//=============================================================================
+// WeightedCacheEngine
+//-----------------------------------------------------------------------------
+/// This struct implements a [`CacheEngine`] that limits the sum of the weights
+/// of its entries. When the total weight exceeds the maximum weight, it drops
+/// the least recently used entries until the total weight is within the limit.
+///
+/// An entry heavier than the maximum weight is never kept in the cache. The
+/// total weight never overflows, even if the weigher returns huge weights.
+///
+/// This struct is not thread safe and must have its concurrency protected by
+/// an external [`std::sync::RwLock`] or other synchronization primitive.
+pub struct WeightedCacheEngine<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> {
+    map: HashMap<K, usize>,
+    list: IndexedList<WeightedCacheEntry<K, V>>,
+    weigher: CacheWeigher<K, V>,
+    max_weight: usize,
+    total_weight: usize,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> WeightedCacheEngine<K, V> {
+    /// Creates a new `WeightedCacheEngine`.
+    ///
+    /// Arguments:
+    /// - `max_weight`: Maximum total weight of the entries;
+    /// - `weigher`: The function used to compute the weight of each entry;
+    pub fn new<W: Fn(&K, &V) -> usize + Send + Sync + 'static>(
+        max_weight: usize,
+        weigher: W,
+    ) -> Self {
+        Self {
+            map: HashMap::new(),
+            list: IndexedList::new(),
+            weigher: Box::new(weigher),
+            max_weight,
+            total_weight: 0,
+        }
+    }
+
+    /// Returns the maximum total weight of the entries.
+    pub fn max_weight(&self) -> usize {
+        self.max_weight
+    }
+
+    /// Returns the current total weight of the entries.
+    pub fn total_weight(&self) -> usize {
+        self.total_weight
+    }
+
+    /// Removes the least recently used entry.
+    ///
+    /// Arguments:
+    /// - `evictions`: The vector that will receive the removed entry;
+    ///
+    /// Returns true if an entry was removed or false if the cache is empty.
+    fn remove_oldest(&mut self, evictions: &mut Vec<CacheEviction<K, V>>) -> bool {
+        match self.list.pop_back() {
+            Some(entry) => {
+                self.map.remove(&entry.key);
+                self.total_weight -= entry.weight;
+                evictions.push(CacheEviction::new(
+                    entry.key,
+                    entry.value,
+                    EvictionReason::Capacity,
+                ));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the least recently used entries until the total weight is
+    /// within the limit.
+    ///
+    /// Arguments:
+    /// - `evictions`: The vector that will receive the removed entries;
+    fn remove_overweight(&mut self, evictions: &mut Vec<CacheEviction<K, V>>) {
+        while self.total_weight > self.max_weight {
+            if !self.remove_oldest(evictions) {
+                break;
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> CacheEngine<K, V>
+    for WeightedCacheEngine<K, V>
+{
+    fn get<Q>(&mut self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        self.list.move_to_front(index);
+        Some(Arc::clone(&self.list.get(index).value))
+    }
+
+    fn insert_with_evictions(
+        &mut self,
+        key: K,
+        value: &Arc<V>,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
+        let weight = (self.weigher)(&key, value);
+        if let Some(&index) = self.map.get(&key) {
+            let entry = self.list.get_mut(index);
+            self.total_weight -= entry.weight;
+            let old = std::mem::replace(&mut entry.value, Arc::clone(value));
+            entry.weight = weight;
+            self.list.move_to_front(index);
+            evictions.push(CacheEviction::new(key, old, EvictionReason::Replaced));
+        } else {
+            let index = self.list.push_front(WeightedCacheEntry {
+                key: key.clone(),
+                value: Arc::clone(value),
+                weight,
+            });
+            self.map.insert(key, index);
+        }
+        // The new entry is not counted yet, so the oldest entries are removed
+        // until its weight fits into the total without overflowing it.
+        self.total_weight = loop {
+            match self.total_weight.checked_add(weight) {
+                Some(total) => break total,
+                None => {
+                    self.remove_oldest(evictions);
+                }
+            }
+        };
+        self.remove_overweight(evictions);
+    }
+
+    fn remove<Q>(&mut self, key: &Q) -> Option<CacheEviction<K, V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let index = self.map.remove(key)?;
+        let entry = self.list.remove(index);
+        self.total_weight -= entry.weight;
+        Some(CacheEviction::new(
+            entry.key,
+            entry.value,
+            EvictionReason::Removed,
+        ))
+    }
+
+    fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.contains_key(key)
+    }
+
+    fn peek<Q>(&self, key: &Q) -> Option<Arc<V>>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        let index = *self.map.get(key)?;
+        Some(Arc::clone(&self.list.get(index).value))
+    }
+
+    fn entries(&self) -> Vec<(K, Arc<V>)> {
+        self.list
+            .iter()
+            .map(|entry| (entry.key.clone(), Arc::clone(&entry.value)))
+            .collect()
+    }
+
+    fn retain_with_evictions<F>(
+        &mut self,
+        mut predicate: F,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) where
+        F: FnMut(&K, &V) -> bool,
+    {
+        for entry in self
+            .list
+            .remove_if(|entry| !predicate(&entry.key, &entry.value))
+        {
+            self.map.remove(&entry.key);
+            self.total_weight -= entry.weight;
+            evictions.push(CacheEviction::new(
+                entry.key,
+                entry.value,
+                EvictionReason::Removed,
+            ));
+        }
+    }
+
+    /// The capacity of this engine is the maximum total weight of the entries.
+    fn capacity(&self) -> usize {
+        self.max_weight
+    }
+
+    fn set_capacity_with_evictions(
+        &mut self,
+        capacity: usize,
+        evictions: &mut Vec<CacheEviction<K, V>>,
+    ) {
+        self.max_weight = capacity;
+        self.remove_overweight(evictions);
+    }
+
+    fn clear(&mut self) {
+        self.map.clear();
+        self.list.clear();
+        self.total_weight = 0;
+    }
+
+    fn clear_with_evictions(&mut self, evictions: &mut Vec<CacheEviction<K, V>>) {
+        for entry in self.list.drain() {
+            evictions.push(CacheEviction::new(
+                entry.key,
+                entry.value,
+                EvictionReason::Cleared,
+            ));
+        }
+        self.map.clear();
+        self.total_weight = 0;
+    }
+
+    fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.list.len() == 0
+    }
+}
+
+//=============================================================================
+// WeightedCache
+//-----------------------------------------------------------------------------
+/// A thread safe [`SimpleCache`] that uses the [`WeightedCacheEngine`]. It can
+/// be created with
+/// `SimpleCache::with_engine(WeightedCacheEngine::new(max_weight, weigher))`.
+pub type WeightedCache<K, V> = SimpleCache<K, V, WeightedCacheEngine<K, V>>;
+
+impl<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> WeightedCache<K, V> {
+    /// Returns the maximum total weight of the entries.
+    pub fn max_weight(&self) -> usize {
+        let s = read_engine(&self.engine);
+        s.max_weight()
+    }
+
+    /// Returns the current total weight of the entries.
+    pub fn total_weight(&self) -> usize {
+        let s = read_engine(&self.engine);
+        s.total_weight()
+    }
+}
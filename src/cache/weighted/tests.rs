@@ -0,0 +1,334 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use crate::cache::ValueCache;
+
+fn create_engine(max_weight: usize) -> WeightedCacheEngine<u64, Vec<u8>> {
+    WeightedCacheEngine::new(max_weight, |_, v: &Vec<u8>| v.len())
+}
+
+//=============================================================================
+// WeightedCacheEngine
+//-----------------------------------------------------------------------------
+#[test]
+fn test_weightedcacheengine_impl_new() {
+    let e = create_engine(100);
+    assert_eq!(e.max_weight(), 100);
+    assert_eq!(e.total_weight(), 0);
+    assert!(e.is_empty());
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_insert() {
+    let mut e = create_engine(100);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(vec![0; 10]));
+        assert_eq!(e.total_weight(), ((key + 1) * 10) as usize);
+    }
+    assert_eq!(e.len(), 10);
+
+    // Replacing updates the weight
+    e.insert(0, &Arc::new(vec![0; 5]));
+    assert_eq!(e.total_weight(), 95);
+    assert_eq!(e.len(), 10);
+
+    // Adding 30 bytes evicts the oldest entries: 1, 2 and 3
+    e.insert(10, &Arc::new(vec![0; 30]));
+    assert_eq!(e.total_weight(), 95);
+    assert_eq!(e.len(), 8);
+    for key in 1..4 {
+        assert!(e.get(&key).is_none());
+    }
+    assert!(e.get(&0).is_some());
+    assert!(e.get(&10).is_some());
+    assert_eq!(e.map.len(), e.list.len());
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_insert_recency() {
+    let mut e = create_engine(30);
+
+    e.insert(1, &Arc::new(vec![0; 10]));
+    e.insert(2, &Arc::new(vec![0; 10]));
+    e.insert(3, &Arc::new(vec![0; 10]));
+    e.get(&1).unwrap();
+    e.insert(4, &Arc::new(vec![0; 10]));
+    assert!(e.get(&2).is_none());
+    assert!(e.get(&1).is_some());
+    assert!(e.get(&3).is_some());
+    assert!(e.get(&4).is_some());
+    assert_eq!(e.total_weight(), 30);
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_insert_too_heavy() {
+    let mut e = create_engine(30);
+
+    e.insert(1, &Arc::new(vec![0; 10]));
+    e.insert(2, &Arc::new(vec![0; 31]));
+    assert!(e.is_empty());
+    assert_eq!(e.total_weight(), 0);
+
+    e.insert(3, &Arc::new(vec![0; 30]));
+    assert_eq!(e.len(), 1);
+    assert_eq!(e.total_weight(), 30);
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_insert_huge_weight() {
+    let half = usize::MAX / 2 + 1;
+    let mut e: WeightedCacheEngine<u64, usize> =
+        WeightedCacheEngine::new(usize::MAX, |_, v: &usize| *v);
+
+    let mut evictions = Vec::new();
+    e.insert_with_evictions(1, &Arc::new(half), &mut evictions);
+    e.insert_with_evictions(2, &Arc::new(half), &mut evictions);
+    assert_eq!(evictions.len(), 1);
+    assert_eq!(evictions[0].key, 1);
+    assert_eq!(evictions[0].reason, EvictionReason::Capacity);
+    assert!(e.contains_key(&2));
+    assert_eq!(e.total_weight(), half);
+
+    e.insert_with_evictions(3, &Arc::new(usize::MAX), &mut evictions);
+    assert_eq!(e.len(), 1);
+    assert!(e.contains_key(&3));
+    assert_eq!(e.total_weight(), usize::MAX);
+
+    let mut e: WeightedCacheEngine<u64, usize> = WeightedCacheEngine::new(30, |_, v: &usize| *v);
+    e.insert(1, &Arc::new(10));
+    e.insert(2, &Arc::new(usize::MAX));
+    assert!(e.is_empty());
+    assert_eq!(e.total_weight(), 0);
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_insert_with_evictions() {
+    let mut e = create_engine(30);
+
+    let mut evictions = Vec::new();
+    e.insert_with_evictions(1, &Arc::new(vec![0; 10]), &mut evictions);
+    e.insert_with_evictions(2, &Arc::new(vec![0; 10]), &mut evictions);
+    e.insert_with_evictions(1, &Arc::new(vec![1; 10]), &mut evictions);
+    assert_eq!(evictions.len(), 1);
+    assert_eq!(evictions[0].key, 1);
+    assert_eq!(*evictions[0].value, vec![0; 10]);
+    assert_eq!(evictions[0].reason, EvictionReason::Replaced);
+
+    evictions.clear();
+    e.insert_with_evictions(3, &Arc::new(vec![0; 30]), &mut evictions);
+    assert_eq!(evictions.len(), 2);
+    assert_eq!(evictions[0].key, 2);
+    assert_eq!(evictions[0].reason, EvictionReason::Capacity);
+    assert_eq!(evictions[1].key, 1);
+    assert_eq!(evictions[1].reason, EvictionReason::Capacity);
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_remove() {
+    let mut e = create_engine(100);
+
+    e.insert(1, &Arc::new(vec![0; 10]));
+    e.insert(2, &Arc::new(vec![0; 20]));
+    let removed = e.remove(&2).unwrap();
+    assert_eq!(removed.key, 2);
+    assert_eq!(removed.reason, EvictionReason::Removed);
+    assert_eq!(e.total_weight(), 10);
+    assert!(e.remove(&2).is_none());
+    assert_eq!(e.len(), 1);
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_peek_contains_key() {
+    let mut e = create_engine(30);
+
+    for key in 1..4 {
+        e.insert(key, &Arc::new(vec![key as u8; 10]));
+    }
+    // peek() and contains_key() do not change the recency
+    assert_eq!(*e.peek(&1).unwrap(), vec![1; 10]);
+    assert!(e.contains_key(&1));
+    e.insert(4, &Arc::new(vec![0; 10]));
+    assert!(!e.contains_key(&1));
+    assert!(e.peek(&1).is_none());
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_string_keys() {
+    let mut e: WeightedCacheEngine<String, Vec<u8>> =
+        WeightedCacheEngine::new(20, |k: &String, v: &Vec<u8>| k.len() + v.len());
+
+    e.insert(String::from("aaaaa"), &Arc::new(vec![0; 5]));
+    e.insert(String::from("bbbbb"), &Arc::new(vec![0; 5]));
+    assert_eq!(e.total_weight(), 20);
+    assert_eq!(*e.get("aaaaa").unwrap(), vec![0; 5]);
+    e.insert(String::from("c"), &Arc::new(vec![0; 1]));
+    assert!(!e.contains_key("bbbbb"));
+    assert!(e.peek("aaaaa").is_some());
+    e.remove("aaaaa").unwrap();
+    assert_eq!(e.total_weight(), 2);
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_entries() {
+    let mut e = create_engine(100);
+
+    for key in 0..3 {
+        e.insert(key, &Arc::new(vec![key as u8; 10]));
+    }
+    e.get(&0).unwrap();
+    let entries: Vec<(u64, Vec<u8>)> = e
+        .entries()
+        .into_iter()
+        .map(|(k, v)| (k, v.to_vec()))
+        .collect();
+    assert_eq!(
+        entries,
+        vec![(1, vec![1; 10]), (2, vec![2; 10]), (0, vec![0; 10])]
+    );
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_retain_with_evictions() {
+    let mut e = create_engine(100);
+
+    for key in 0..4 {
+        e.insert(key, &Arc::new(vec![key as u8; 10 * (key as usize + 1)]));
+    }
+    assert_eq!(e.total_weight(), 100);
+    let mut evictions = Vec::new();
+    e.retain_with_evictions(|_, v| v.len() < 30, &mut evictions);
+    let removed: Vec<(u64, EvictionReason)> = evictions.iter().map(|e| (e.key, e.reason)).collect();
+    assert_eq!(
+        removed,
+        vec![(2, EvictionReason::Removed), (3, EvictionReason::Removed)]
+    );
+    assert_eq!(e.keys(), vec![0, 1]);
+    assert_eq!(e.total_weight(), 30);
+
+    // The released weight can be used again
+    e.insert(4, &Arc::new(vec![4; 70]));
+    assert_eq!(e.len(), 3);
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_set_capacity_with_evictions() {
+    let mut e = create_engine(100);
+
+    for key in 0..4 {
+        e.insert(key, &Arc::new(vec![key as u8; 20]));
+    }
+    assert_eq!(e.capacity(), 100);
+    let mut evictions = Vec::new();
+    e.set_capacity_with_evictions(50, &mut evictions);
+    assert_eq!(e.max_weight(), 50);
+    let removed: Vec<(u64, EvictionReason)> = evictions.iter().map(|e| (e.key, e.reason)).collect();
+    assert_eq!(
+        removed,
+        vec![(0, EvictionReason::Capacity), (1, EvictionReason::Capacity)]
+    );
+    assert_eq!(e.total_weight(), 40);
+    assert_eq!(e.keys(), vec![2, 3]);
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_clear_with_evictions() {
+    let mut e = create_engine(100);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(vec![0; 10]));
+    }
+    let mut evictions = Vec::new();
+    e.clear_with_evictions(&mut evictions);
+    assert!(e.is_empty());
+    assert_eq!(e.total_weight(), 0);
+    assert_eq!(e.map.len(), 0);
+    // From the oldest to the most recent
+    assert_eq!(evictions.len(), 10);
+    for (key, eviction) in evictions.iter().enumerate() {
+        assert_eq!(eviction.key, key as u64);
+        assert_eq!(eviction.reason, EvictionReason::Cleared);
+    }
+}
+
+#[test]
+fn test_weightedcacheengine_cacheengine_clear() {
+    let mut e = create_engine(100);
+
+    for key in 0..10 {
+        e.insert(key, &Arc::new(vec![0; 10]));
+    }
+    e.clear();
+    assert!(e.is_empty());
+    assert_eq!(e.total_weight(), 0);
+    assert_eq!(e.map.len(), 0);
+}
+
+//=============================================================================
+// WeightedCache
+//-----------------------------------------------------------------------------
+#[test]
+fn test_weightedcache_impl() {
+    let c: WeightedCache<u64, Vec<u8>> = SimpleCache::with_engine(create_engine(100));
+    assert_eq!(c.max_weight(), 100);
+    assert_eq!(c.total_weight(), 0);
+
+    for key in 0..20 {
+        c.insert(key, &Arc::new(vec![0; 10]));
+    }
+    assert_eq!(c.len(), 10);
+    assert_eq!(c.total_weight(), 100);
+    c.clear();
+    assert_eq!(c.total_weight(), 0);
+}
+
+#[test]
+fn test_weightedcache_weigher_panic() {
+    let c: WeightedCache<u64, Vec<u8>> =
+        SimpleCache::with_engine(WeightedCacheEngine::new(100, |_, v: &Vec<u8>| {
+            assert!(!v.is_empty(), "Empty values are not allowed.");
+            v.len()
+        }));
+    c.insert(1, &Arc::new(vec![0; 10]));
+
+    let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        c.insert(2, &Arc::new(Vec::new()));
+    }));
+    assert!(ret.is_err());
+
+    // The poisoned engine is cleared and the cache keeps working
+    assert_eq!(c.total_weight(), 0);
+    assert!(c.is_empty());
+    c.insert(3, &Arc::new(vec![0; 20]));
+    assert_eq!(c.total_weight(), 20);
+    assert_eq!(c.len(), 1);
+}
@@ -0,0 +1,70 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module provides the portable parts of the file locks. The whole file
+//! is locked only by `fd-lock`, so neither the lock conversions nor the
+//! byte-range locks are supported.
+use super::{FileLockMode, FileLockRange};
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+
+pub fn lock_mirror_core(
+    _file: &File,
+    _mode: FileLockMode,
+    range: Option<FileLockRange>,
+    _wait: bool,
+) -> Result<()> {
+    match range {
+        Some(_) => Err(Error::new(
+            ErrorKind::Unsupported,
+            "Byte-range locks are not supported on this platform.",
+        )),
+        None => Ok(()),
+    }
+}
+
+pub fn convert_file_lock_core(_file: &File, _mode: FileLockMode) -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "Lock conversion is not supported on this platform.",
+    ))
+}
+
+pub fn convert_file_range_lock_core(
+    _file: &File,
+    _mode: FileLockMode,
+    _range: FileLockRange,
+) -> Result<()> {
+    Err(Error::new(
+        ErrorKind::Unsupported,
+        "Lock conversion is not supported on this platform.",
+    ))
+}
@@ -0,0 +1,195 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module provides the Linux specific parts of the file locks. The whole
+//! file is locked by `fd-lock` with `flock()`, just like on the other Unix
+//! platforms and on the previous versions of this library, so they all
+//! exclude each other.
+//!
+//! Every lock is also mirrored by an open file description lock
+//! (`F_OFD_SETLK`). Unlike `flock()`, those locks can be converted between the
+//! shared and exclusive modes atomically and can lock byte ranges. The mirror
+//! is always acquired before `flock()`, so two actors never wait for each
+//! other in the opposite order. It is released when the lock file is closed.
+use super::{FileLockMode, FileLockRange, LockLostError};
+use libc::{
+    c_int, c_short, fcntl, flock, off_t, F_OFD_SETLK, F_OFD_SETLKW, F_RDLCK, F_UNLCK, F_WRLCK,
+    LOCK_EX, LOCK_NB, LOCK_SH, SEEK_SET,
+};
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{Error, ErrorKind, Result};
+use std::os::unix::io::AsRawFd;
+
+/// The whole file is locked when l_start and l_len are 0.
+const WHOLE_FILE: (off_t, off_t) = (0, 0);
+
+/// Calls `flock()` over the file, retrying if it is interrupted by a signal.
+///
+/// Arguments:
+/// - `file`: The file;
+/// - `operation`: The operation;
+fn flock_core(file: &File, operation: c_int) -> Result<()> {
+    loop {
+        if unsafe { flock(file.as_raw_fd(), operation) } == 0 {
+            return Ok(());
+        }
+        let e = Error::last_os_error();
+        if e.kind() != ErrorKind::Interrupted {
+            return Err(e);
+        }
+    }
+}
+
+/// Sets a lock over a region of the file, retrying if it is interrupted by a
+/// signal.
+///
+/// Arguments:
+/// - `file`: The file;
+/// - `lock_type`: The type of the lock;
+/// - `command`: The `fcntl()` command;
+/// - `region`: The offset and the length of the region;
+fn fcntl_lock_core(
+    file: &File,
+    lock_type: c_int,
+    command: c_int,
+    region: (off_t, off_t),
+) -> Result<()> {
+    // l_pid must be 0 for open file description locks.
+    let mut lock: libc::flock = unsafe { std::mem::zeroed() };
+    lock.l_type = lock_type as c_short;
+    lock.l_whence = SEEK_SET as c_short;
+    lock.l_start = region.0;
+    lock.l_len = region.1;
+    loop {
+        if unsafe { fcntl(file.as_raw_fd(), command, &lock) } == 0 {
+            return Ok(());
+        }
+        let e = Error::last_os_error();
+        match e.raw_os_error() {
+            Some(libc::EINTR) => (),
+            // Conflicting locks may also be reported as EACCES
+            Some(libc::EACCES) if command == F_OFD_SETLK => {
+                return Err(Error::from(ErrorKind::WouldBlock));
+            }
+            _ => return Err(e),
+        }
+    }
+}
+
+/// Returns the lock type used by a given mode.
+fn lock_type(mode: FileLockMode) -> c_int {
+    match mode {
+        FileLockMode::Shared => F_RDLCK,
+        FileLockMode::Exclusive => F_WRLCK,
+    }
+}
+
+/// Converts a range into the region used by `fcntl()`. The whole range must
+/// be representable by `off_t`.
+fn region(range: FileLockRange) -> Result<(off_t, off_t)> {
+    let end = range.offset.checked_add(range.len);
+    match (
+        off_t::try_from(range.offset),
+        off_t::try_from(range.len),
+        end,
+    ) {
+        (Ok(offset), Ok(len), Some(end)) if off_t::try_from(end).is_ok() => Ok((offset, len)),
+        _ => Err(Error::new(
+            ErrorKind::InvalidInput,
+            "The locked range is too large.",
+        )),
+    }
+}
+
+/// Sets the shared `flock()` over the whole file without waiting and converts
+/// the mirror into a shared lock. It is used to restore the shared lock after
+/// `flock()` released the previous one.
+///
+/// If others took the `flock()` in the meantime, the lock is lost. The mirror
+/// is released too and it fails with a [`LockLostError`].
+///
+/// Arguments:
+/// - `file`: The file;
+fn restore_shared_lock(file: &File) -> Result<()> {
+    if flock_core(file, LOCK_SH | LOCK_NB).is_err() {
+        let _ = fcntl_lock_core(file, F_UNLCK, F_OFD_SETLK, WHOLE_FILE);
+        return Err(LockLostError::new_error());
+    }
+    fcntl_lock_core(file, F_RDLCK, F_OFD_SETLK, WHOLE_FILE)
+}
+
+pub fn lock_mirror_core(
+    file: &File,
+    mode: FileLockMode,
+    range: Option<FileLockRange>,
+    wait: bool,
+) -> Result<()> {
+    let region = match range {
+        Some(range) => region(range)?,
+        None => WHOLE_FILE,
+    };
+    let command = if wait { F_OFD_SETLKW } else { F_OFD_SETLK };
+    fcntl_lock_core(file, lock_type(mode), command, region)
+}
+
+pub fn convert_file_lock_core(file: &File, mode: FileLockMode) -> Result<()> {
+    // Setting a lock over an existing lock of the same description replaces
+    // it atomically, so the mirror is converted first when upgrading and last
+    // when downgrading. This way, the other actors that use the mirror never
+    // see the lock released.
+    match mode {
+        FileLockMode::Exclusive => {
+            fcntl_lock_core(file, F_WRLCK, F_OFD_SETLK, WHOLE_FILE)?;
+            match flock_core(file, LOCK_EX | LOCK_NB) {
+                Ok(()) => Ok(()),
+                Err(e) => {
+                    // A failed conversion may release the shared flock(), so
+                    // it is acquired again. Only the actors that do not use
+                    // the mirror may take the lock in the meantime.
+                    restore_shared_lock(file)?;
+                    Err(e)
+                }
+            }
+        }
+        // flock() releases the exclusive lock before it sets the shared one
+        FileLockMode::Shared => restore_shared_lock(file),
+    }
+}
+
+pub fn convert_file_range_lock_core(
+    file: &File,
+    mode: FileLockMode,
+    range: FileLockRange,
+) -> Result<()> {
+    // The shared flock() of the region does not change
+    fcntl_lock_core(file, lock_type(mode), F_OFD_SETLK, region(range)?)
+}
@@ -0,0 +1,813 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements the advisory file locks used by the shared files and
+//! directories of [`super`]. Each acquisition opens the lock file again, so
+//! the guards own their locks and a failed attempt can be retried until a
+//! timeout without holding any borrow of the lock.
+//!
+//! The whole file is locked by the crate `fd-lock` (`flock()` on Unix and
+//! `LockFileEx()` over the first byte of the lock file on Windows), so the
+//! locks remain compatible with the previous versions of this library. On
+//! Linux, every lock is also mirrored by an open file description lock, which
+//! can be converted atomically between the shared and exclusive modes and can
+//! lock byte ranges. The other platforms support neither.
+//!
+//! Every actor that acquires the exclusive lock records a [`SharedLockOwner`]
+//! in the lock file. On Unix, unused lock files can be removed safely by
+//! [`FileLock::remove_unused()`] because a lock acquired over a lock file
+//! that is no longer linked to its path is discarded and acquired again over
+//! a new lock file.
+#[cfg(not(target_os = "linux"))]
+mod impl_default;
+#[cfg(target_os = "linux")]
+mod impl_linux;
+#[cfg(test)]
+mod tests;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+#[cfg(not(target_os = "linux"))]
+use impl_default::*;
+#[cfg(target_os = "linux")]
+use impl_linux::*;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom};
+use std::marker::PhantomData;
+use std::mem::ManuallyDrop;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Initial interval between two attempts to acquire a lock with a timeout.
+const MIN_BACKOFF: Duration = Duration::from_millis(1);
+
+/// Maximum interval between two attempts to acquire a lock with a timeout.
+const MAX_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Offset of the owner record in the lock file. The first byte is skipped
+/// because `fd-lock` locks it on Windows, where the locks are mandatory.
+const OWNER_RECORD_OFFSET: u64 = 1;
+
+/// Size of the owner record written in the lock file. The record is padded
+/// with new lines, so it always overwrites the previous one.
+const OWNER_RECORD_SIZE: usize = 256;
+
+/// Maximum number of characters of the host name kept in the owner record.
+const MAX_HOSTNAME_LEN: usize = 128;
+
+//=============================================================================
+// FileLockMode
+//-----------------------------------------------------------------------------
+/// The modes of a [`FileLock`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FileLockMode {
+    /// Shared lock. Multiple actors may hold it at the same time.
+    Shared,
+    /// Exclusive lock. Only one actor may hold it at any given time.
+    Exclusive,
+}
+
+//=============================================================================
+// FileLockRange
+//-----------------------------------------------------------------------------
+/// A region of a [`FileLock`]. Byte-range locks are supported only on Linux.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct FileLockRange {
+    /// The offset of the first byte of the region.
+    pub offset: u64,
+    /// The length of the region in bytes.
+    pub len: u64,
+}
+
+//=============================================================================
+// LockLostError
+//-----------------------------------------------------------------------------
+/// The error reported when a [`FileLockGuard`] lost its lock because a failed
+/// conversion could not restore it.
+#[derive(Debug)]
+struct LockLostError;
+
+impl LockLostError {
+    /// Returns a new IO error that wraps this error.
+    fn new_error() -> Error {
+        Error::other(LockLostError)
+    }
+
+    /// Verifies if the given IO error wraps this error.
+    ///
+    /// Arguments:
+    /// - `e`: The error;
+    fn is(e: &Error) -> bool {
+        e.get_ref().is_some_and(|e| e.is::<LockLostError>())
+    }
+}
+
+impl std::fmt::Display for LockLostError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The lock was lost during its conversion.")
+    }
+}
+
+impl std::error::Error for LockLostError {}
+
+//=============================================================================
+// SharedLockOwner
+//-----------------------------------------------------------------------------
+/// This struct holds the information recorded in a lock file by the latest
+/// actor that acquired the exclusive lock. It is intended to help the diagnostic of
+/// contention and must not be used to control the access to the lock.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SharedLockOwner {
+    /// The ID of the process that acquired the lock.
+    pub pid: u32,
+    /// The name of the host that runs the process.
+    pub hostname: String,
+    /// When the lock was acquired.
+    pub acquired: DateTime<Utc>,
+}
+
+impl SharedLockOwner {
+    /// Creates the owner record of the current process.
+    fn current() -> Self {
+        Self {
+            pid: std::process::id(),
+            hostname: hostname().chars().take(MAX_HOSTNAME_LEN).collect(),
+            acquired: Utc::now(),
+        }
+    }
+
+    /// Serializes this record. The result always has
+    /// [`OWNER_RECORD_SIZE`] bytes.
+    fn serialize(&self) -> Vec<u8> {
+        let mut record = format!(
+            "pid={}\nhost={}\nacquired={}\n",
+            self.pid,
+            self.hostname,
+            self.acquired.to_rfc3339_opts(SecondsFormat::Micros, true),
+        )
+        .into_bytes();
+        record.resize(OWNER_RECORD_SIZE, b'\n');
+        record
+    }
+
+    /// Parses a record created by [`Self::serialize()`].
+    ///
+    /// Arguments:
+    /// - `record`: The record;
+    ///
+    /// Returns the owner or an error with the kind [`ErrorKind::InvalidData`]
+    /// if the record is invalid.
+    fn parse(record: &[u8]) -> Result<Self> {
+        let invalid = || Error::new(ErrorKind::InvalidData, "Invalid lock owner record.");
+        let record = std::str::from_utf8(record).map_err(|_| invalid())?;
+        let mut pid = None;
+        let mut hostname = None;
+        let mut acquired = None;
+        for line in record.lines().filter(|line| !line.is_empty()) {
+            match line.split_once('=').ok_or_else(invalid)? {
+                ("pid", value) => pid = Some(value.parse::<u32>().map_err(|_| invalid())?),
+                ("host", value) => hostname = Some(String::from(value)),
+                ("acquired", value) => {
+                    let time = DateTime::parse_from_rfc3339(value).map_err(|_| invalid())?;
+                    acquired = Some(time.with_timezone(&Utc));
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        match (pid, hostname, acquired) {
+            (Some(pid), Some(hostname), Some(acquired)) => Ok(Self {
+                pid,
+                hostname,
+                acquired,
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Returns the name of the current host or an empty string if it is not
+/// available.
+#[cfg(unix)]
+fn hostname() -> String {
+    let mut buff = [0u8; 256];
+    if unsafe { libc::gethostname(buff.as_mut_ptr() as *mut libc::c_char, buff.len()) } != 0 {
+        return String::new();
+    }
+    let len = buff.iter().position(|c| *c == 0).unwrap_or(buff.len());
+    String::from_utf8_lossy(&buff[..len]).into_owned()
+}
+
+/// Returns the name of the current host or an empty string if it is not
+/// available.
+#[cfg(not(unix))]
+fn hostname() -> String {
+    std::env::var("COMPUTERNAME").unwrap_or_default()
+}
+
+/// Writes the owner record into the lock file.
+///
+/// Arguments:
+/// - `file`: The lock file;
+/// - `record`: The serialized record;
+#[cfg(unix)]
+fn write_owner_record(file: &File, record: &[u8]) -> Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(record, OWNER_RECORD_OFFSET)
+}
+
+/// Writes the owner record into the lock file.
+///
+/// Arguments:
+/// - `file`: The lock file;
+/// - `record`: The serialized record;
+#[cfg(windows)]
+fn write_owner_record(file: &File, mut record: &[u8]) -> Result<()> {
+    use std::os::windows::fs::FileExt;
+    let mut offset = OWNER_RECORD_OFFSET;
+    while !record.is_empty() {
+        match file.seek_write(record, offset) {
+            Ok(0) => return Err(Error::from(ErrorKind::WriteZero)),
+            Ok(n) => {
+                record = &record[n..];
+                offset += n as u64;
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Records the current process as the owner of the exclusive lock. The record
+/// is informative only, so failures are ignored.
+///
+/// Arguments:
+/// - `file`: The lock file;
+fn record_owner(file: &File) {
+    let _ = write_owner_record(file, &SharedLockOwner::current().serialize());
+}
+
+/// Reads the owner record from the lock file.
+///
+/// Arguments:
+/// - `file`: The lock file;
+///
+/// Returns the owner or an error with the kind [`ErrorKind::InvalidData`] if
+/// the record is invalid.
+fn read_owner_record(file: &mut File) -> Result<SharedLockOwner> {
+    let mut record = Vec::new();
+    file.seek(SeekFrom::Start(OWNER_RECORD_OFFSET))?;
+    file.take(OWNER_RECORD_SIZE as u64)
+        .read_to_end(&mut record)?;
+    SharedLockOwner::parse(&record)
+}
+
+/// Verifies if the lock file is no longer linked to its path. It happens when
+/// the lock file is removed or replaced after it was opened.
+///
+/// Arguments:
+/// - `file`: The lock file;
+/// - `path`: The path to the lock file;
+#[cfg(unix)]
+fn is_stale(file: &File, path: &Path) -> Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+    let current = file.metadata()?;
+    match std::fs::metadata(path) {
+        Ok(linked) => Ok(linked.dev() != current.dev() || linked.ino() != current.ino()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(true),
+        Err(e) => Err(e),
+    }
+}
+
+/// Verifies if the lock file is no longer linked to its path. Files cannot be
+/// identified on this platform, so it always returns false. It is safe because
+/// [`FileLock::remove_unused()`] is not supported on this platform.
+#[cfg(not(unix))]
+fn is_stale(_file: &File, _path: &Path) -> Result<bool> {
+    Ok(false)
+}
+
+/// Opens an existing lock file.
+///
+/// Arguments:
+/// - `path`: The path to the lock file;
+///
+/// Returns `None` if the lock file does not exist.
+fn open_existing(path: &Path) -> Result<Option<File>> {
+    match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Calls a function until it succeeds, fails with an error other than
+/// [`ErrorKind::WouldBlock`] or the deadline is reached. The attempts are
+/// retried with an exponential backoff. It is used to implement the lock
+/// timeouts over the attempts to acquire a lock without waiting.
+///
+/// Arguments:
+/// - `deadline`: When the attempts stop;
+/// - `attempt`: The function that attempts to acquire the lock;
+///
+/// Returns the result of the last attempt or an error with the kind
+/// [`ErrorKind::TimedOut`] if the deadline is reached.
+pub(crate) fn retry_until<T, F>(deadline: Instant, mut attempt: F) -> Result<T>
+where
+    F: FnMut() -> Result<T>,
+{
+    let mut backoff = MIN_BACKOFF;
+    loop {
+        match attempt() {
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            ret => return ret,
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(Error::new(
+                ErrorKind::TimedOut,
+                "Unable to acquire the lock before the timeout.",
+            ));
+        }
+        std::thread::sleep(backoff.min(deadline - now));
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+//=============================================================================
+// LockFileGuard
+//-----------------------------------------------------------------------------
+/// The guard of the `fd-lock` lock held by a [`FileLockGuard`]. Its lifetime
+/// is erased because it borrows the lock owned by the same [`FileLockGuard`].
+///
+/// On Linux, the conversion of a [`FileLockGuard`] does not change the variant
+/// of this guard. It does not matter because both variants release the lock
+/// in the same way.
+enum LockFileGuard {
+    Read(fd_lock::RwLockReadGuard<'static, File>),
+    Write(fd_lock::RwLockWriteGuard<'static, File>),
+}
+
+impl LockFileGuard {
+    /// Locks the whole lock file with `fd-lock`.
+    ///
+    /// Arguments:
+    /// - `lock`: The lock. It must not be shared;
+    /// - `mode`: The lock mode;
+    /// - `wait`: Wait for the lock if it is held by others. Otherwise it fails
+    ///   with [`ErrorKind::WouldBlock`];
+    ///
+    /// # Safety
+    ///
+    /// The returned guard borrows the contents of `lock`, so it must be
+    /// dropped before `lock`. The contents of `lock` must not be accessed
+    /// while the guard exists.
+    unsafe fn new(
+        lock: &mut Arc<fd_lock::RwLock<File>>,
+        mode: FileLockMode,
+        wait: bool,
+    ) -> Result<Self> {
+        let lock: *mut fd_lock::RwLock<File> =
+            Arc::get_mut(lock).expect("The lock file must not be shared.");
+        // SAFETY: The pointer comes from Arc::get_mut(), so nothing else can
+        // access the lock. The caller guarantees that the returned guard is
+        // dropped before `lock` and that `lock` is not accessed while the
+        // guard exists, so the 'static borrow never dangles nor aliases.
+        let lock = &mut *lock;
+        Ok(match (mode, wait) {
+            (FileLockMode::Shared, true) => Self::Read(lock.read()?),
+            (FileLockMode::Shared, false) => Self::Read(lock.try_read()?),
+            (FileLockMode::Exclusive, true) => Self::Write(lock.write()?),
+            (FileLockMode::Exclusive, false) => Self::Write(lock.try_write()?),
+        })
+    }
+
+    /// Returns the locked file.
+    fn file(&self) -> &File {
+        match self {
+            Self::Read(guard) => guard,
+            Self::Write(guard) => guard,
+        }
+    }
+}
+
+//=============================================================================
+// FileLock
+//-----------------------------------------------------------------------------
+/// This struct implements an advisory lock over a file. Each acquisition opens
+/// the lock file again, so two acquisitions always exclude each other even if
+/// they use the same instance.
+pub(crate) struct FileLock {
+    path: PathBuf,
+}
+
+impl FileLock {
+    /// Opens a lock file, creating it if required.
+    ///
+    /// Arguments:
+    /// - `path`: The path to the lock file;
+    pub fn open(path: &Path) -> Result<Self> {
+        drop(Self::open_file(path)?);
+        Ok(Self {
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Opens or creates the lock file. The file is opened for read and write
+    /// because some platforms require it to set both lock modes.
+    ///
+    /// Arguments:
+    /// - `path`: The path to the lock file;
+    fn open_file(path: &Path) -> Result<File> {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+    }
+
+    /// Returns the path to the lock file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Acquires the lock over a lock file opened by [`Self::open_file()`].
+    ///
+    /// Arguments:
+    /// - `file`: The lock file;
+    /// - `mode`: The lock mode;
+    /// - `wait`: Wait for the lock if it is held by others;
+    /// - `range`: The locked region or `None` to lock the whole file;
+    ///
+    /// Returns the guard or `None` if the lock file was removed or replaced
+    /// after it was opened. In this case, the lock is released.
+    fn lock_opened<'a>(
+        &self,
+        file: File,
+        mode: FileLockMode,
+        wait: bool,
+        range: Option<FileLockRange>,
+    ) -> Result<Option<FileLockGuard<'a>>> {
+        let guard = FileLockGuard::new(file, mode, wait, range)?;
+        if is_stale(guard.file(), &self.path)? {
+            return Ok(None);
+        }
+        if mode == FileLockMode::Exclusive && range.is_none() {
+            record_owner(guard.file());
+        }
+        Ok(Some(guard))
+    }
+
+    /// Acquires the lock over the current lock file. If the lock file was
+    /// removed in the meantime, the lock is released and acquired again over
+    /// a new lock file.
+    ///
+    /// The guard owns its lock, so its lifetime is not bound to this
+    /// instance. The public methods bind it to restrict its use.
+    ///
+    /// Arguments:
+    /// - `mode`: The lock mode;
+    /// - `wait`: Wait for the lock if it is held by others;
+    /// - `range`: The locked region or `None` to lock the whole file;
+    fn acquire<'a>(
+        &self,
+        mode: FileLockMode,
+        wait: bool,
+        range: Option<FileLockRange>,
+    ) -> Result<FileLockGuard<'a>> {
+        loop {
+            let file = Self::open_file(&self.path)?;
+            if let Some(guard) = self.lock_opened(file, mode, wait, range)? {
+                return Ok(guard);
+            }
+        }
+    }
+
+    /// Acquires the lock, waiting as long as necessary.
+    ///
+    /// Arguments:
+    /// - `mode`: The lock mode;
+    ///
+    /// Returns the guard that releases the lock when dropped.
+    pub fn lock(&self, mode: FileLockMode) -> Result<FileLockGuard<'_>> {
+        self.acquire(mode, true, None)
+    }
+
+    /// Attempts to acquire the lock. It fails with [`ErrorKind::WouldBlock`]
+    /// without waiting if the lock cannot be acquired.
+    ///
+    /// Arguments:
+    /// - `mode`: The lock mode;
+    ///
+    /// Returns the guard that releases the lock when dropped.
+    pub fn try_lock(&self, mode: FileLockMode) -> Result<FileLockGuard<'_>> {
+        self.acquire(mode, false, None)
+    }
+
+    /// Verifies if a region can be locked.
+    ///
+    /// Arguments:
+    /// - `range`: The region;
+    fn check_range(range: FileLockRange) -> Result<()> {
+        if range.len == 0 {
+            Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The locked range cannot be empty.",
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Acquires the lock over a region of the file, waiting as long as
+    /// necessary. Regions of the same file are independent of each other but
+    /// conflict with the locks over the whole file. It is supported only on
+    /// Linux and fails with [`ErrorKind::Unsupported`] on other platforms.
+    ///
+    /// Arguments:
+    /// - `mode`: The lock mode;
+    /// - `range`: The locked region. It must not be empty;
+    ///
+    /// Returns the guard that releases the lock when dropped.
+    pub fn lock_range(
+        &self,
+        mode: FileLockMode,
+        range: FileLockRange,
+    ) -> Result<FileLockGuard<'_>> {
+        Self::check_range(range)?;
+        self.acquire(mode, true, Some(range))
+    }
+
+    /// Attempts to acquire the lock over a region of the file. It fails with
+    /// [`ErrorKind::WouldBlock`] without waiting if the lock cannot be
+    /// acquired. See [`Self::lock_range()`] for further details.
+    ///
+    /// Arguments:
+    /// - `mode`: The lock mode;
+    /// - `range`: The locked region. It must not be empty;
+    ///
+    /// Returns the guard that releases the lock when dropped.
+    pub fn try_lock_range(
+        &self,
+        mode: FileLockMode,
+        range: FileLockRange,
+    ) -> Result<FileLockGuard<'_>> {
+        Self::check_range(range)?;
+        self.acquire(mode, false, Some(range))
+    }
+
+    /// Acquires the lock and consumes this instance. Since the guard owns its
+    /// lock, it remains valid after this instance is dropped.
+    ///
+    /// Arguments:
+    /// - `mode`: The lock mode;
+    /// - `wait`: Wait for the lock if it is held by others. Otherwise it fails
+    ///   with [`ErrorKind::WouldBlock`];
+    ///
+    /// Returns the guard that releases the lock when dropped.
+    pub fn into_guard(self, mode: FileLockMode, wait: bool) -> Result<FileLockGuard<'static>> {
+        self.acquire(mode, wait, None)
+    }
+
+    /// Attempts to acquire the lock until the timeout expires. The attempts
+    /// use `try_read()` or `try_write()` of `fd-lock` and are retried with an
+    /// exponential backoff.
+    ///
+    /// Arguments:
+    /// - `mode`: The lock mode;
+    /// - `timeout`: The maximum amount of time to wait for the lock;
+    ///
+    /// Returns the guard that releases the lock when dropped. It fails with
+    /// [`ErrorKind::TimedOut`] if the lock cannot be acquired in time.
+    pub fn lock_timeout(&self, mode: FileLockMode, timeout: Duration) -> Result<FileLockGuard<'_>> {
+        match Instant::now().checked_add(timeout) {
+            Some(deadline) => retry_until(deadline, || self.acquire(mode, false, None)),
+            None => self.lock(mode),
+        }
+    }
+
+    /// Attempts to acquire the lock until the deadline and consumes this
+    /// instance. See [`Self::lock_timeout()`] and [`Self::into_guard()`] for
+    /// further details.
+    ///
+    /// Arguments:
+    /// - `mode`: The lock mode;
+    /// - `deadline`: When the attempts stop;
+    ///
+    /// Returns the guard that releases the lock when dropped. It fails with
+    /// [`ErrorKind::TimedOut`] if the lock cannot be acquired in time.
+    pub fn into_guard_until(
+        self,
+        mode: FileLockMode,
+        deadline: Instant,
+    ) -> Result<FileLockGuard<'static>> {
+        retry_until(deadline, || self.acquire(mode, false, None))
+    }
+
+    /// Returns the owner recorded in a lock file if the exclusive lock is
+    /// currently held by someone. In order to verify it, this method briefly
+    /// attempts to acquire the shared lock, so a concurrent attempt to
+    /// acquire the exclusive lock without waiting may fail in the meantime.
+    ///
+    /// Arguments:
+    /// - `path`: The path to the lock file;
+    ///
+    /// Returns:
+    /// - `Ok(Some(x))`: The latest actor that acquired the exclusive lock;
+    /// - `Ok(None)`: If nobody holds the exclusive lock or the lock file does
+    ///   not exist;
+    /// - `Err(e)`: If the lock file cannot be read or its record is invalid;
+    pub fn owner(path: &Path) -> Result<Option<SharedLockOwner>> {
+        let lock = match open_existing(path)? {
+            Some(file) => fd_lock::RwLock::new(file),
+            None => return Ok(None),
+        };
+        match lock.try_read() {
+            Ok(_) => return Ok(None),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => (),
+            Err(e) => return Err(e),
+        }
+        read_owner_record(&mut lock.into_inner()).map(Some)
+    }
+
+    /// Removes a lock file if nobody holds the lock. The file is removed
+    /// while the exclusive lock is held, so the actors that opened it in the
+    /// meantime will move to a new lock file when they acquire the lock.
+    ///
+    /// It requires the identification of the lock files, so it is supported
+    /// only on Unix. It fails with [`ErrorKind::Unsupported`] on the other
+    /// platforms.
+    ///
+    /// Arguments:
+    /// - `path`: The path to the lock file;
+    ///
+    /// Returns `Ok(true)` if the file was removed or `Ok(false)` if it is in
+    /// use or does not exist.
+    #[cfg(unix)]
+    pub fn remove_unused(path: &Path) -> Result<bool> {
+        let file = match open_existing(path)? {
+            Some(file) => file,
+            None => return Ok(false),
+        };
+        let guard = match FileLockGuard::new(file, FileLockMode::Exclusive, false, None) {
+            Ok(guard) => guard,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(e) => return Err(e),
+        };
+        match is_stale(guard.file(), path)? {
+            false => std::fs::remove_file(path).map(|_| true),
+            true => Ok(false),
+        }
+    }
+
+    /// Removes a lock file if nobody holds the lock. The lock files cannot be
+    /// identified on this platform, so an actor that opened the lock file
+    /// before its removal would not detect it. Because of that, it always
+    /// fails with [`ErrorKind::Unsupported`].
+    ///
+    /// Arguments:
+    /// - `_path`: The path to the lock file;
+    #[cfg(not(unix))]
+    pub fn remove_unused(_path: &Path) -> Result<bool> {
+        Err(Error::new(
+            ErrorKind::Unsupported,
+            "Lock files cannot be removed safely on this platform.",
+        ))
+    }
+}
+
+//=============================================================================
+// FileLockGuard
+//-----------------------------------------------------------------------------
+/// An RAII implementation of a lock acquired from a [`FileLock`]. When this
+/// structure is dropped (falls out of scope), the lock is released.
+///
+/// The guard owns the lock file it locked, so it is released properly even
+/// if the lock file is replaced in the meantime.
+pub(crate) struct FileLockGuard<'a> {
+    // It borrows the contents of `_lock_file`, so it is dropped explicitly by
+    // the Drop implementation, before the fields are dropped.
+    guard: ManuallyDrop<LockFileGuard>,
+    // An Arc is used instead of a Box because moving a Box asserts the unique
+    // access to its contents, which are borrowed by `guard`.
+    _lock_file: Arc<fd_lock::RwLock<File>>,
+    range: Option<FileLockRange>,
+    // Set when a failed conversion could not restore the lock.
+    lost: bool,
+    _lock: PhantomData<&'a FileLock>,
+}
+
+impl<'a> FileLockGuard<'a> {
+    /// Locks an open lock file. On Linux, the mirror is acquired before the
+    /// lock of `fd-lock`. It is released when the lock file is closed.
+    ///
+    /// Arguments:
+    /// - `file`: The lock file;
+    /// - `mode`: The lock mode;
+    /// - `wait`: Wait for the lock if it is held by others;
+    /// - `range`: The locked region or `None` to lock the whole file;
+    fn new(
+        file: File,
+        mode: FileLockMode,
+        wait: bool,
+        range: Option<FileLockRange>,
+    ) -> Result<Self> {
+        lock_mirror_core(&file, mode, range, wait)?;
+        // Region locks also hold the shared lock of the whole file
+        let whole_file_mode = match range {
+            Some(_) => FileLockMode::Shared,
+            None => mode,
+        };
+        let mut lock_file = Arc::new(fd_lock::RwLock::new(file));
+        // SAFETY: The guard borrows the contents of lock_file and both are
+        // moved into the same FileLockGuard. Moving the Arc does not move its
+        // contents. The Drop implementation of FileLockGuard drops the guard
+        // before the fields, so lock_file outlives it. This struct never
+        // accesses lock_file, so the borrow is never aliased.
+        let guard = unsafe { LockFileGuard::new(&mut lock_file, whole_file_mode, wait)? };
+        Ok(Self {
+            guard: ManuallyDrop::new(guard),
+            _lock_file: lock_file,
+            range,
+            lost: false,
+            _lock: PhantomData,
+        })
+    }
+
+    /// Returns the lock file.
+    fn file(&self) -> &File {
+        self.guard.file()
+    }
+
+    /// Atomically converts the lock into another mode without waiting. The
+    /// current lock is kept if the conversion fails.
+    ///
+    /// Actors that use only `flock()` may take the lock while a failed
+    /// conversion of the whole file restores it. In that case, the lock is
+    /// lost and this guard is poisoned: it no longer holds any lock and all
+    /// later conversions fail.
+    ///
+    /// Arguments:
+    /// - `mode`: The new lock mode;
+    ///
+    /// Returns `Ok(())` on success. It fails with [`ErrorKind::WouldBlock`] if
+    /// the lock is held by others, with [`ErrorKind::Unsupported`] if the
+    /// platform cannot convert locks atomically or with [`ErrorKind::Other`]
+    /// if the lock was lost.
+    pub fn try_convert(&mut self, mode: FileLockMode) -> Result<()> {
+        if self.lost {
+            return Err(LockLostError::new_error());
+        }
+        match self.range {
+            Some(range) => convert_file_range_lock_core(self.file(), mode, range)?,
+            None => {
+                if let Err(e) = convert_file_lock_core(self.file(), mode) {
+                    self.lost = LockLostError::is(&e);
+                    return Err(e);
+                }
+                if mode == FileLockMode::Exclusive {
+                    record_owner(self.file());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Drop for FileLockGuard<'a> {
+    fn drop(&mut self) {
+        // SAFETY: The guard is dropped only once, here. It borrows the
+        // contents of _lock_file, which is dropped after this method returns,
+        // along with the other fields.
+        unsafe { ManuallyDrop::drop(&mut self.guard) };
+    }
+}
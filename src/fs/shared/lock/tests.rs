@@ -0,0 +1,634 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use il2_test_utils::testdir::TestDirUtils;
+
+fn lock1_path(test_dir: &TestDirUtils) -> std::path::PathBuf {
+    std::path::PathBuf::from(test_dir.get_test_file_path("target.lock"))
+}
+
+fn create_locks(test_dir: &TestDirUtils) -> (FileLock, FileLock) {
+    let lock_file = test_dir.create_test_file("target.lock", b"").unwrap();
+    (
+        FileLock::open(Path::new(&lock_file)).unwrap(),
+        FileLock::open(Path::new(&lock_file)).unwrap(),
+    )
+}
+
+/// Locks a file with a plain `flock()`, just like the previous versions of
+/// this library.
+#[cfg(unix)]
+fn plain_flock(path: &Path, operation: libc::c_int) -> Result<File> {
+    use std::os::unix::io::AsRawFd;
+    let file = OpenOptions::new().read(true).write(true).open(path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } == 0 {
+        Ok(file)
+    } else {
+        Err(Error::last_os_error())
+    }
+}
+
+//=============================================================================
+// SharedLockOwner
+//-----------------------------------------------------------------------------
+#[test]
+fn test_sharedlockowner_impl_current() {
+    let owner = SharedLockOwner::current();
+    assert_eq!(owner.pid, std::process::id());
+    assert_eq!(owner.hostname, hostname());
+    assert!((Utc::now() - owner.acquired).num_seconds() < 10);
+}
+
+#[test]
+fn test_sharedlockowner_impl_serialize_parse() {
+    let owner = SharedLockOwner {
+        pid: 1234,
+        hostname: String::from("host.example.com"),
+        acquired: DateTime::parse_from_rfc3339("2021-07-01T12:34:56.789012Z")
+            .unwrap()
+            .with_timezone(&Utc),
+    };
+    let record = owner.serialize();
+    assert_eq!(record.len(), OWNER_RECORD_SIZE);
+    assert!(record
+        .starts_with(b"pid=1234\nhost=host.example.com\nacquired=2021-07-01T12:34:56.789012Z\n"));
+    assert_eq!(SharedLockOwner::parse(&record).unwrap(), owner);
+
+    let owner = SharedLockOwner {
+        hostname: String::new(),
+        ..owner
+    };
+    assert_eq!(SharedLockOwner::parse(&owner.serialize()).unwrap(), owner);
+}
+
+#[test]
+fn test_sharedlockowner_impl_parse_invalid() {
+    let invalid: [&[u8]; 7] = [
+        b"",
+        b"pid=1\nhost=h\n",
+        b"pid=x\nhost=h\nacquired=2021-07-01T12:34:56Z\n",
+        b"pid=1\nhost=h\nacquired=yesterday\n",
+        b"pid=1\nhost=h\nacquired=2021-07-01T12:34:56Z\nmode=shared\n",
+        b"pid=1\nhost=h\nacquired=2021-07-01T12:34:56Z\nother\n",
+        b"pid=1\nhost=\xff\nacquired=2021-07-01T12:34:56Z\n",
+    ];
+    for record in invalid.iter() {
+        let e = SharedLockOwner::parse(record).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn test_retry_until() {
+    // Only WouldBlock is retried
+    let mut attempts = 0;
+    let ret = retry_until(Instant::now() + Duration::from_secs(10), || {
+        attempts += 1;
+        match attempts {
+            3 => Ok(attempts),
+            _ => Err(Error::from(ErrorKind::WouldBlock)),
+        }
+    });
+    assert_eq!(ret.unwrap(), 3);
+    let e = retry_until(Instant::now() + Duration::from_secs(10), || {
+        Err::<(), _>(Error::from(ErrorKind::NotFound))
+    })
+    .err()
+    .unwrap();
+    assert_eq!(e.kind(), ErrorKind::NotFound);
+
+    // At least one attempt is made after the deadline
+    let mut attempts = 0;
+    let e = retry_until(Instant::now(), || {
+        attempts += 1;
+        Err::<(), _>(Error::from(ErrorKind::WouldBlock))
+    })
+    .err()
+    .unwrap();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert_eq!(attempts, 1);
+}
+
+//=============================================================================
+// FileLock
+//-----------------------------------------------------------------------------
+#[test]
+fn test_filelock_impl_lock() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_lock").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+
+    let shared1 = lock1.lock(FileLockMode::Shared).unwrap();
+    let shared2 = lock2.lock(FileLockMode::Shared).unwrap();
+    drop(shared1);
+    drop(shared2);
+
+    let exclusive1 = lock1.lock(FileLockMode::Exclusive).unwrap();
+    let e = lock2.try_lock(FileLockMode::Shared).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(exclusive1);
+    drop(lock2.lock(FileLockMode::Exclusive).unwrap());
+}
+
+#[test]
+fn test_filelock_impl_try_lock() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_try_lock").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+
+    let shared1 = lock1.try_lock(FileLockMode::Shared).unwrap();
+    drop(lock2.try_lock(FileLockMode::Shared).unwrap());
+    let e = lock2.try_lock(FileLockMode::Exclusive).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(shared1);
+
+    let exclusive2 = lock2.try_lock(FileLockMode::Exclusive).unwrap();
+    assert!(lock1.try_lock(FileLockMode::Shared).is_err());
+    assert!(lock1.try_lock(FileLockMode::Exclusive).is_err());
+    drop(exclusive2);
+    drop(lock1.try_lock(FileLockMode::Exclusive).unwrap());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_filelock_impl_lock_plain_flock() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_lock_plain_flock").unwrap();
+    let (lock1, _) = create_locks(&test_dir);
+    let path = lock1_path(&test_dir);
+
+    let plain = plain_flock(&path, libc::LOCK_EX | libc::LOCK_NB).unwrap();
+    let e = lock1.try_lock(FileLockMode::Shared).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    let e = lock1.try_lock(FileLockMode::Exclusive).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(plain);
+
+    let plain = plain_flock(&path, libc::LOCK_SH | libc::LOCK_NB).unwrap();
+    drop(lock1.try_lock(FileLockMode::Shared).unwrap());
+    let e = lock1.try_lock(FileLockMode::Exclusive).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(plain);
+
+    let shared = lock1.lock(FileLockMode::Shared).unwrap();
+    drop(plain_flock(&path, libc::LOCK_SH | libc::LOCK_NB).unwrap());
+    let e = plain_flock(&path, libc::LOCK_EX | libc::LOCK_NB)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(shared);
+
+    let exclusive = lock1.lock(FileLockMode::Exclusive).unwrap();
+    let e = plain_flock(&path, libc::LOCK_SH | libc::LOCK_NB)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(exclusive);
+    drop(plain_flock(&path, libc::LOCK_EX | libc::LOCK_NB).unwrap());
+}
+
+#[test]
+fn test_filelock_impl_lock_timeout() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_lock_timeout").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+
+    let exclusive1 = lock1
+        .lock_timeout(FileLockMode::Exclusive, Duration::ZERO)
+        .unwrap();
+    for mode in [FileLockMode::Shared, FileLockMode::Exclusive].iter() {
+        let start = Instant::now();
+        let e = lock2
+            .lock_timeout(*mode, Duration::from_millis(30))
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::TimedOut);
+        assert!(start.elapsed() >= Duration::from_millis(30));
+    }
+
+    // The lock is released by another thread while waiting
+    let lock2 = std::sync::Arc::new(lock2);
+    let t_lock2 = std::sync::Arc::clone(&lock2);
+    let t = std::thread::spawn(move || {
+        t_lock2
+            .lock_timeout(FileLockMode::Exclusive, Duration::from_secs(10))
+            .map(|_| ())
+    });
+    std::thread::sleep(Duration::from_millis(50));
+    drop(exclusive1);
+    t.join().unwrap().unwrap();
+
+    // Timeouts that cannot be represented wait forever
+    drop(
+        lock2
+            .lock_timeout(FileLockMode::Shared, Duration::MAX)
+            .unwrap(),
+    );
+}
+
+#[test]
+fn test_filelock_impl_into_guard_until() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_into_guard_until").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+    let path = lock1_path(&test_dir);
+
+    let exclusive = lock1
+        .into_guard_until(FileLockMode::Exclusive, Instant::now())
+        .unwrap();
+    let start = Instant::now();
+    let e = lock2
+        .into_guard_until(FileLockMode::Shared, start + Duration::from_millis(30))
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(30));
+    drop(exclusive);
+    drop(
+        FileLock::open(&path)
+            .unwrap()
+            .into_guard_until(FileLockMode::Exclusive, Instant::now())
+            .unwrap(),
+    );
+}
+
+#[test]
+fn test_filelock_impl_owner() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_owner").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+    let path = lock1_path(&test_dir);
+
+    assert!(FileLock::owner(&path).unwrap().is_none());
+    assert!(
+        FileLock::owner(Path::new(&test_dir.get_test_file_path("missing")))
+            .unwrap()
+            .is_none()
+    );
+
+    let exclusive = lock1.lock(FileLockMode::Exclusive).unwrap();
+    let owner = FileLock::owner(&path).unwrap().unwrap();
+    assert_eq!(owner.pid, std::process::id());
+    assert_eq!(owner.hostname, hostname());
+    // The probe does not disturb the holder
+    assert!(lock2.try_lock(FileLockMode::Shared).is_err());
+    drop(exclusive);
+    assert!(FileLock::owner(&path).unwrap().is_none());
+
+    // Shared locks are neither recorded nor reported
+    std::fs::write(&path, b"").unwrap();
+    let shared = lock2.lock(FileLockMode::Shared).unwrap();
+    assert!(FileLock::owner(&path).unwrap().is_none());
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+    drop(shared);
+
+    // Invalid record
+    let _exclusive = lock2.lock(FileLockMode::Exclusive).unwrap();
+    std::fs::write(&path, b"garbage").unwrap();
+    let e = FileLock::owner(&path).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+#[cfg(unix)]
+fn test_filelock_impl_remove_unused() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_remove_unused").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+    let path = lock1_path(&test_dir);
+
+    let shared = lock1.lock(FileLockMode::Shared).unwrap();
+    assert!(!FileLock::remove_unused(&path).unwrap());
+    assert!(path.exists());
+    drop(shared);
+    assert!(FileLock::remove_unused(&path).unwrap());
+    assert!(!path.exists());
+    assert!(!FileLock::remove_unused(&path).unwrap());
+
+    // Both locks still exclude each other
+    let exclusive = lock1.lock(FileLockMode::Exclusive).unwrap();
+    assert!(lock2.try_lock(FileLockMode::Shared).is_err());
+    drop(exclusive);
+    let shared = lock2.lock(FileLockMode::Shared).unwrap();
+    assert!(lock1.try_lock(FileLockMode::Exclusive).is_err());
+    drop(shared);
+}
+
+#[test]
+#[cfg(not(unix))]
+fn test_filelock_impl_remove_unused() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_remove_unused").unwrap();
+    let (_lock1, _lock2) = create_locks(&test_dir);
+    let path = lock1_path(&test_dir);
+
+    let e = FileLock::remove_unused(&path).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+    assert!(path.exists());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_filelock_impl_remove_unused_race() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_remove_unused_race").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+    let path = lock1_path(&test_dir);
+
+    // The lock file is removed after it was opened but before it was locked
+    let file = FileLock::open_file(&path).unwrap();
+    assert!(FileLock::remove_unused(&path).unwrap());
+    assert!(lock1
+        .lock_opened(file, FileLockMode::Exclusive, false, None)
+        .unwrap()
+        .is_none());
+
+    // Only one actor holds the exclusive lock
+    let exclusive = lock1.try_lock(FileLockMode::Exclusive).unwrap();
+    let file = FileLock::open_file(&path).unwrap();
+    assert_eq!(
+        lock2
+            .lock_opened(file, FileLockMode::Exclusive, false, None)
+            .err()
+            .unwrap()
+            .kind(),
+        ErrorKind::WouldBlock
+    );
+    assert!(lock2.try_lock(FileLockMode::Exclusive).is_err());
+    drop(exclusive);
+    drop(lock2.try_lock(FileLockMode::Exclusive).unwrap());
+}
+
+#[test]
+#[cfg(unix)]
+fn test_filelock_impl_remove_unused_reopen() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_remove_unused_reopen").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+    let path = lock1_path(&test_dir);
+
+    assert!(FileLock::remove_unused(&path).unwrap());
+    // The lock file is created again by the next acquisition
+    let exclusive = lock1.lock(FileLockMode::Exclusive).unwrap();
+    assert!(path.exists());
+    assert!(FileLock::owner(&path).unwrap().is_some());
+    // A new instance sees the new lock file
+    let lock3 = FileLock::open(&path).unwrap();
+    assert!(lock3.try_lock(FileLockMode::Shared).is_err());
+    assert!(lock2.try_lock(FileLockMode::Shared).is_err());
+    drop(exclusive);
+    let shared = lock2.lock(FileLockMode::Shared).unwrap();
+    assert!(lock3.try_lock(FileLockMode::Exclusive).is_err());
+    drop(lock3.try_lock(FileLockMode::Shared).unwrap());
+    drop(shared);
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_filelock_impl_lock_range() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_lock_range").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+    let range = |offset, len| FileLockRange { offset, len };
+
+    let shared1 = lock1
+        .lock_range(FileLockMode::Shared, range(0, 10))
+        .unwrap();
+    let exclusive1 = lock1
+        .lock_range(FileLockMode::Exclusive, range(20, 10))
+        .unwrap();
+    // Independent regions
+    drop(
+        lock2
+            .try_lock_range(FileLockMode::Exclusive, range(10, 10))
+            .unwrap(),
+    );
+    drop(
+        lock2
+            .try_lock_range(FileLockMode::Shared, range(5, 10))
+            .unwrap(),
+    );
+    // Overlapping regions
+    let e = lock2
+        .try_lock_range(FileLockMode::Exclusive, range(9, 2))
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    let e = lock2
+        .try_lock_range(FileLockMode::Shared, range(29, 100))
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    // Whole file locks
+    assert!(lock2.try_lock(FileLockMode::Shared).is_err());
+    drop(exclusive1);
+    drop(lock2.try_lock(FileLockMode::Shared).unwrap());
+    assert!(lock2.try_lock(FileLockMode::Exclusive).is_err());
+    drop(shared1);
+    let exclusive2 = lock2.try_lock(FileLockMode::Exclusive).unwrap();
+    let e = lock1
+        .try_lock_range(FileLockMode::Shared, range(100, 1))
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(exclusive2);
+
+    // Invalid ranges
+    for r in &[range(0, 0), range(u64::MAX, 1), range(1, u64::MAX)] {
+        let e = lock1
+            .try_lock_range(FileLockMode::Shared, *r)
+            .err()
+            .unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_filelock_impl_lock_range_plain_flock() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_lock_range_plain_flock").unwrap();
+    let (lock1, _) = create_locks(&test_dir);
+    let path = lock1_path(&test_dir);
+    let range = FileLockRange { offset: 0, len: 10 };
+
+    // Region locks are seen as shared locks of the whole file
+    let exclusive = lock1.lock_range(FileLockMode::Exclusive, range).unwrap();
+    drop(plain_flock(&path, libc::LOCK_SH | libc::LOCK_NB).unwrap());
+    let e = plain_flock(&path, libc::LOCK_EX | libc::LOCK_NB)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(exclusive);
+
+    let plain = plain_flock(&path, libc::LOCK_EX | libc::LOCK_NB).unwrap();
+    let e = lock1
+        .try_lock_range(FileLockMode::Shared, range)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(plain);
+    drop(lock1.try_lock_range(FileLockMode::Shared, range).unwrap());
+}
+
+#[test]
+#[cfg(not(target_os = "linux"))]
+fn test_filelock_impl_lock_range() {
+    let test_dir = TestDirUtils::new("test_filelock_impl_lock_range").unwrap();
+    let (lock1, _) = create_locks(&test_dir);
+
+    let range = FileLockRange { offset: 0, len: 10 };
+    let e = lock1.lock_range(FileLockMode::Shared, range).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+    let e = lock1
+        .try_lock_range(FileLockMode::Exclusive, range)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+}
+
+//=============================================================================
+// FileLockGuard
+//-----------------------------------------------------------------------------
+#[test]
+#[cfg(target_os = "linux")]
+fn test_filelockguard_impl_try_convert() {
+    let test_dir = TestDirUtils::new("test_filelockguard_impl_try_convert").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+    let lock3 = FileLock::open(&lock1_path(&test_dir)).unwrap();
+
+    let mut guard1 = lock1.lock(FileLockMode::Shared).unwrap();
+    let guard2 = lock2.lock(FileLockMode::Shared).unwrap();
+    let e = guard1.try_convert(FileLockMode::Exclusive).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(guard2);
+    // The shared lock was kept
+    assert!(lock3.try_lock(FileLockMode::Exclusive).is_err());
+    drop(lock3.try_lock(FileLockMode::Shared).unwrap());
+
+    // The upgrade records the owner
+    let path = lock1_path(&test_dir);
+    assert!(FileLock::owner(&path).unwrap().is_none());
+    guard1.try_convert(FileLockMode::Exclusive).unwrap();
+    assert!(lock3.try_lock(FileLockMode::Shared).is_err());
+    assert_eq!(
+        FileLock::owner(&path).unwrap().unwrap().pid,
+        std::process::id()
+    );
+    guard1.try_convert(FileLockMode::Shared).unwrap();
+    drop(lock3.try_lock(FileLockMode::Shared).unwrap());
+    assert!(lock3.try_lock(FileLockMode::Exclusive).is_err());
+    drop(guard1);
+    drop(lock3.try_lock(FileLockMode::Exclusive).unwrap());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_filelockguard_impl_try_convert_plain_flock() {
+    let test_dir = TestDirUtils::new("test_filelockguard_impl_try_convert_plain_flock").unwrap();
+    let (lock1, _) = create_locks(&test_dir);
+    let path = lock1_path(&test_dir);
+
+    let mut guard = lock1.lock(FileLockMode::Shared).unwrap();
+    let plain = plain_flock(&path, libc::LOCK_SH | libc::LOCK_NB).unwrap();
+    let e = guard.try_convert(FileLockMode::Exclusive).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(plain);
+    // The shared lock was kept
+    drop(plain_flock(&path, libc::LOCK_SH | libc::LOCK_NB).unwrap());
+    let e = plain_flock(&path, libc::LOCK_EX | libc::LOCK_NB)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+
+    guard.try_convert(FileLockMode::Exclusive).unwrap();
+    let e = plain_flock(&path, libc::LOCK_SH | libc::LOCK_NB)
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    guard.try_convert(FileLockMode::Shared).unwrap();
+    drop(plain_flock(&path, libc::LOCK_SH | libc::LOCK_NB).unwrap());
+    assert!(plain_flock(&path, libc::LOCK_EX | libc::LOCK_NB).is_err());
+    drop(guard);
+    drop(plain_flock(&path, libc::LOCK_EX | libc::LOCK_NB).unwrap());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_filelockguard_impl_try_convert_lost() {
+    use super::super::tests::ChildProcess;
+    use std::os::unix::io::AsRawFd;
+
+    let test_dir = TestDirUtils::new("test_filelockguard_impl_try_convert_lost").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+    let path = lock1_path(&test_dir);
+
+    for &(mode, new_mode) in &[
+        (FileLockMode::Shared, FileLockMode::Exclusive),
+        (FileLockMode::Exclusive, FileLockMode::Shared),
+    ] {
+        let mut guard = lock1.lock(mode).unwrap();
+        // Releases the flock() of the guard, so the child takes the lock as
+        // if it won the race against the conversion.
+        assert_eq!(
+            unsafe { libc::flock(guard.file().as_raw_fd(), libc::LOCK_UN) },
+            0
+        );
+        let mut child = ChildProcess::spawn(&path, "hold_plain_write");
+        assert_eq!(child.result(), "ok");
+
+        // The conversion does not wait for the child
+        let e = guard.try_convert(new_mode).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::Other);
+        assert!(LockLostError::is(&e));
+        // The guard is poisoned
+        let e = guard.try_convert(mode).err().unwrap();
+        assert!(LockLostError::is(&e));
+        child.finish();
+        // The guard no longer holds the mirror either
+        drop(lock2.try_lock(FileLockMode::Exclusive).unwrap());
+        drop(guard);
+    }
+}
+
+#[test]
+#[cfg(not(target_os = "linux"))]
+fn test_filelockguard_impl_try_convert() {
+    let test_dir = TestDirUtils::new("test_filelockguard_impl_try_convert").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+
+    let mut guard1 = lock1.lock(FileLockMode::Shared).unwrap();
+    let e = guard1.try_convert(FileLockMode::Exclusive).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+    // The shared lock was kept
+    assert!(lock2.try_lock(FileLockMode::Exclusive).is_err());
+}
+
+#[test]
+fn test_filelockguard_drop() {
+    let test_dir = TestDirUtils::new("test_filelockguard_drop").unwrap();
+    let (lock1, lock2) = create_locks(&test_dir);
+
+    {
+        let _guard = lock1.lock(FileLockMode::Exclusive).unwrap();
+        assert!(lock2.try_lock(FileLockMode::Exclusive).is_err());
+    }
+    drop(lock2.try_lock(FileLockMode::Exclusive).unwrap());
+}
@@ -36,13 +36,24 @@
 //! This lock can be used to coordinate access to the file but should not be
 //! used as a mean to guarantee security restrictions to t
 
+mod lock;
+mod sync_file;
 #[cfg(test)]
 mod tests;
 
+pub use lock::SharedLockOwner;
+pub use sync_file::{SyncSharedFile, SyncSharedFileReadLockGuard, SyncSharedFileWriteLockGuard};
+
+use crate::mem::SecretBytes;
+use lock::{FileLock, FileLockGuard, FileLockMode, FileLockRange};
+use std::convert::TryFrom;
 use std::ffi::{OsStr, OsString};
 use std::fs::{DirBuilder, File, OpenOptions};
 use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
-use std::path::Path;
+use std::ops::Range;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use zeroize::Zeroize;
 
 //=============================================================================
 // SharedFileLockNameBuilder
@@ -125,6 +136,89 @@ impl SharedFileLockNameBuilder for DefaultSharedFileLockNameBuilder {
     }
 }
 
+//=============================================================================
+// SyncMode
+//-----------------------------------------------------------------------------
+/// The modes used to flush the protected file to the disk.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SyncMode {
+    /// Flushes the contents of the file only. See [`File::sync_data()`].
+    Data,
+    /// Flushes the contents and the metadata of the file. See
+    /// [`File::sync_all()`].
+    All,
+}
+
+/// Reads the whole contents of a file, starting from its beginning.
+///
+/// Arguments:
+/// - `file`: The file;
+///
+/// Returns the contents of the file. The file cursor is left at its end.
+fn read_file_to_vec(file: &mut File) -> Result<Vec<u8>> {
+    let len = file.metadata()?.len();
+    let mut contents = Vec::with_capacity(len as usize);
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_end(&mut contents)?;
+    Ok(contents)
+}
+
+/// Fills the buffer of a [`SecretBytes`] with the data from a reader until
+/// the buffer is full or the reader reaches its end. If the reader fails, the
+/// data read so far is zeroized before the error is returned.
+///
+/// Arguments:
+/// - `reader`: The reader;
+/// - `secret`: The secret that will receive the data;
+///
+/// Returns the number of bytes read.
+fn read_into_secret<R: Read>(reader: &mut R, secret: &mut SecretBytes) -> Result<usize> {
+    let buffer = secret.mut_buffer();
+    let mut total = 0;
+    while total < buffer.len() {
+        match reader.read(&mut buffer[total..]) {
+            Ok(0) => break,
+            Ok(n) => total += n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => {
+                buffer[..total].zeroize();
+                return Err(e);
+            }
+        }
+    }
+    Ok(total)
+}
+
+/// Reads the whole contents of a file into a new [`SecretBytes`], starting
+/// from its beginning. The data is read directly into the secret, so no
+/// other copy of it is left in memory by this function.
+///
+/// Arguments:
+/// - `file`: The file;
+/// - `locked`: Locks the secret in memory;
+///
+/// Returns the contents of the file. The file cursor is left at its end. It
+/// fails with [`ErrorKind::InvalidData`] if the file grows while it is read.
+fn read_file_to_secret(file: &mut File, locked: bool) -> Result<SecretBytes> {
+    let len = usize::try_from(file.metadata()?.len())
+        .map_err(|_| Error::new(ErrorKind::InvalidData, "The file is too large."))?;
+    let mut secret = SecretBytes::new(len, locked);
+    file.seek(SeekFrom::Start(0))?;
+    let read = read_into_secret(file, &mut secret)?;
+    secret.set_len(read);
+    // The file must end here
+    let mut probe = [0u8; 1];
+    let probed = file.read(&mut probe);
+    probe[..].zeroize();
+    if probed? != 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            "The file grew while it was read.",
+        ));
+    }
+    Ok(secret)
+}
+
 //=============================================================================
 // SharedFileReadLockGuard
 //-----------------------------------------------------------------------------
@@ -138,7 +232,7 @@ impl SharedFileLockNameBuilder for DefaultSharedFileLockNameBuilder {
 /// See [`SharedFile`] for further details about how it works.
 pub struct SharedFileReadLockGuard<'a> {
     file: &'a mut File,
-    _lock: fd_lock::RwLockReadGuard<'a, File>,
+    lock: FileLockGuard<'a>,
 }
 
 impl<'a> SharedFileReadLockGuard<'a> {
@@ -146,6 +240,68 @@ impl<'a> SharedFileReadLockGuard<'a> {
     pub fn file(&self) -> &File {
         self.file
     }
+
+    /// Reads the whole contents of the file. It always reads from the
+    /// beginning of the file regardless of the current position and leaves
+    /// the position at the end of the file.
+    ///
+    /// Returns the contents of the file.
+    pub fn read_to_vec(&mut self) -> Result<Vec<u8>> {
+        read_file_to_vec(self.file)
+    }
+
+    /// Reads the whole contents of the file directly into a [`SecretBytes`].
+    /// It always reads from the beginning of the file and leaves the position
+    /// at its end.
+    ///
+    /// Arguments:
+    /// - `locked`: Locks the secret in memory;
+    ///
+    /// Returns the contents of the file.
+    pub fn read_secret(&mut self, locked: bool) -> Result<SecretBytes> {
+        read_file_to_secret(self.file, locked)
+    }
+
+    /// Returns the current size of the file in bytes.
+    pub fn len(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Returns true if the file is empty.
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Attempts to atomically upgrade this shared read lock into an exclusive
+    /// write lock without waiting. The lock is never released during the
+    /// conversion, so no other actor can modify the file between the read and
+    /// the write.
+    ///
+    /// This operation is implemented with the `fcntl()` lock conversion of the
+    /// open file description lock that mirrors the `flock()` lock, so it is
+    /// supported only on Linux. Actors that use only `flock()`, such as the
+    /// previous versions of this library, are excluded as usual but may take
+    /// the lock while a failed upgrade restores the shared lock. The upgrade
+    /// does not wait for them, so the lock is lost instead.
+    ///
+    /// Returns:
+    /// - `Ok(x)`: The write lock that grants access to the file;
+    /// - `Err((r, e))`: The original read lock `r` and the error `e`. `e` has
+    ///   the kind [`ErrorKind::WouldBlock`] if other actors hold the lock,
+    ///   [`ErrorKind::Unsupported`] if the platform cannot convert locks or
+    ///   [`ErrorKind::Other`] if the lock was lost. In the last case, `r` no
+    ///   longer holds any lock and must be dropped;
+    pub fn try_upgrade(
+        mut self,
+    ) -> std::result::Result<SharedFileWriteLockGuard<'a>, (Self, Error)> {
+        match self.lock.try_convert(FileLockMode::Exclusive) {
+            Ok(()) => Ok(SharedFileWriteLockGuard {
+                file: self.file,
+                lock: self.lock,
+            }),
+            Err(e) => Err((self, e)),
+        }
+    }
 }
 
 impl<'a> Read for SharedFileReadLockGuard<'a> {
@@ -174,7 +330,7 @@ impl<'a> Seek for SharedFileReadLockGuard<'a> {
 /// See [`SharedFile`] for further details about how it works.
 pub struct SharedFileWriteLockGuard<'a> {
     file: &'a mut File,
-    _lock: fd_lock::RwLockWriteGuard<'a, File>,
+    lock: FileLockGuard<'a>,
 }
 
 impl<'a> SharedFileWriteLockGuard<'a> {
@@ -187,6 +343,121 @@ impl<'a> SharedFileWriteLockGuard<'a> {
     pub fn mut_file(&mut self) -> &mut File {
         self.file
     }
+
+    /// Reads the whole contents of the file. It always reads from the
+    /// beginning of the file regardless of the current position and leaves
+    /// the position at the end of the file.
+    ///
+    /// Returns the contents of the file.
+    pub fn read_to_vec(&mut self) -> Result<Vec<u8>> {
+        read_file_to_vec(self.file)
+    }
+
+    /// Replaces the contents of the file. The file is rewritten from its
+    /// beginning and truncated to the size of the new contents. The position
+    /// is left at the end of the file.
+    ///
+    /// This method is not atomic. See [`SharedFile::replace_contents()`] if
+    /// the file must never be left partially written.
+    ///
+    /// Arguments:
+    /// - `contents`: The new contents of the file;
+    pub fn replace_with(&mut self, contents: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(contents)?;
+        self.file.set_len(contents.len() as u64)
+    }
+
+    /// Reads the whole contents of the file directly into a [`SecretBytes`].
+    /// It always reads from the beginning of the file and leaves the position
+    /// at its end.
+    ///
+    /// Arguments:
+    /// - `locked`: Locks the secret in memory;
+    ///
+    /// Returns the contents of the file.
+    pub fn read_secret(&mut self, locked: bool) -> Result<SecretBytes> {
+        read_file_to_secret(self.file, locked)
+    }
+
+    /// Replaces the contents of the file with a secret. The secret is written
+    /// directly from its buffer without intermediate copies. See
+    /// [`Self::replace_with()`] for further details.
+    ///
+    /// Arguments:
+    /// - `secret`: The new contents of the file;
+    pub fn write_secret(&mut self, secret: &SecretBytes) -> Result<()> {
+        self.replace_with(secret.value())
+    }
+
+    /// Appends data to the end of the file regardless of the current
+    /// position. The position is left at the end of the file.
+    ///
+    /// Arguments:
+    /// - `data`: The data to be appended;
+    pub fn append(&mut self, data: &[u8]) -> Result<()> {
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(data)
+    }
+
+    /// Returns the current size of the file in bytes.
+    pub fn len(&self) -> Result<u64> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    /// Returns true if the file is empty.
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+
+    /// Truncates or extends the file to the given size. Extended files are
+    /// filled with zeroes. The position is left at the end of the file.
+    ///
+    /// Arguments:
+    /// - `len`: The new size of the file in bytes;
+    pub fn truncate(&mut self, len: u64) -> Result<()> {
+        self.file.set_len(len)?;
+        self.file.seek(SeekFrom::Start(len))?;
+        Ok(())
+    }
+
+    /// Flushes the file to the disk.
+    ///
+    /// Arguments:
+    /// - `mode`: What must be flushed;
+    pub fn sync(&mut self, mode: SyncMode) -> Result<()> {
+        match mode {
+            SyncMode::Data => self.file.sync_data(),
+            SyncMode::All => self.file.sync_all(),
+        }
+    }
+
+    /// Atomically downgrades this exclusive write lock into a shared read
+    /// lock. The lock is never released during the conversion, so no other
+    /// actor can modify the file before the read.
+    ///
+    /// This operation is implemented with the `fcntl()` lock conversion of the
+    /// open file description lock that mirrors the `flock()` lock, so it is
+    /// supported only on Linux. `flock()` itself releases the exclusive lock
+    /// before it sets the shared one, so actors that use only `flock()` may
+    /// take the lock in the meantime. The downgrade does not wait for them, so
+    /// the lock is lost instead.
+    ///
+    /// Returns:
+    /// - `Ok(x)`: The read lock that grants access to the file;
+    /// - `Err((w, e))`: The original write lock `w` and the error `e`. `e` has
+    ///   the kind [`ErrorKind::Unsupported`] if the platform cannot convert
+    ///   locks or [`ErrorKind::Other`] if the lock was lost. In the last case,
+    ///   `w` no longer holds any lock and must be dropped;
+    pub fn downgrade(mut self) -> std::result::Result<SharedFileReadLockGuard<'a>, (Self, Error)> {
+        match self.lock.try_convert(FileLockMode::Shared) {
+            Ok(()) => Ok(SharedFileReadLockGuard {
+                file: self.file,
+                lock: self.lock,
+            }),
+            Err(e) => Err((self, e)),
+        }
+    }
 }
 
 impl<'a> Read for SharedFileWriteLockGuard<'a> {
@@ -210,6 +481,176 @@ impl<'a> Seek for SharedFileWriteLockGuard<'a> {
     }
 }
 
+//=============================================================================
+// SharedFileRegion
+//-----------------------------------------------------------------------------
+/// The locked region of a byte-range lock guard and the current position
+/// inside it.
+struct SharedFileRegion {
+    range: FileLockRange,
+    position: u64,
+}
+
+impl SharedFileRegion {
+    /// Creates a new `SharedFileRegion` positioned at its beginning.
+    ///
+    /// Arguments:
+    /// - `range`: The locked region;
+    fn new(range: FileLockRange) -> Self {
+        Self { range, position: 0 }
+    }
+
+    /// Returns the locked region as a range of absolute offsets.
+    fn range(&self) -> Range<u64> {
+        self.range.offset..self.range.offset + self.range.len
+    }
+
+    /// Moves the file to the current position and limits the size of an
+    /// operation to the end of the region.
+    ///
+    /// Arguments:
+    /// - `file`: The protected file;
+    /// - `requested`: The number of bytes requested by the operation;
+    ///
+    /// Returns the number of bytes that can be used by the operation.
+    fn prepare(&self, file: &mut File, requested: usize) -> Result<usize> {
+        let available = self.range.len.saturating_sub(self.position);
+        let count = std::cmp::min(requested as u64, available) as usize;
+        if count > 0 {
+            file.seek(SeekFrom::Start(self.range.offset + self.position))?;
+        }
+        Ok(count)
+    }
+
+    /// Moves the current position. Positions are relative to the beginning
+    /// of the region and [`SeekFrom::End`] is relative to its end.
+    ///
+    /// Arguments:
+    /// - `pos`: The new position;
+    ///
+    /// Returns the new position relative to the beginning of the region.
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(n) => self.range.len.checked_add_signed(n),
+            SeekFrom::Current(n) => self.position.checked_add_signed(n),
+        };
+        match position {
+            Some(position) if position <= u64::MAX - self.range.offset => {
+                self.position = position;
+                Ok(position)
+            }
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Invalid seek to a negative or overflowing position.",
+            )),
+        }
+    }
+}
+
+//=============================================================================
+// SharedFileRangeReadLockGuard
+//-----------------------------------------------------------------------------
+/// An RAII implementation of an “advisory lock” of a shared read to a region
+/// of the protected file. When this structure is dropped (falls out of scope),
+/// the shared read lock is released.
+///
+/// It exposes the traits [`Read`] and [`Seek`] limited to the locked region.
+/// All positions are relative to the beginning of the region and the reads
+/// stop at its end.
+///
+/// See [`SharedFile::lock_range_read()`] for further details.
+pub struct SharedFileRangeReadLockGuard<'a> {
+    file: &'a mut File,
+    region: SharedFileRegion,
+    _lock: FileLockGuard<'a>,
+}
+
+impl<'a> SharedFileRangeReadLockGuard<'a> {
+    /// Returns the locked region as a range of offsets of the file.
+    pub fn range(&self) -> Range<u64> {
+        self.region.range()
+    }
+}
+
+impl<'a> Read for SharedFileRangeReadLockGuard<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let count = self.region.prepare(self.file, buf.len())?;
+        if count == 0 {
+            return Ok(0);
+        }
+        let n = self.file.read(&mut buf[..count])?;
+        self.region.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a> Seek for SharedFileRangeReadLockGuard<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.region.seek(pos)
+    }
+}
+
+//=============================================================================
+// SharedFileRangeWriteLockGuard
+//-----------------------------------------------------------------------------
+/// An RAII implementation of an “advisory lock” of a exclusive read and write
+/// to a region of the protected file. When this structure is dropped (falls
+/// out of scope), the exclusive lock is released.
+///
+/// It exposes the traits [`Read`], [`Write`] and [`Seek`] limited to the
+/// locked region. All positions are relative to the beginning of the region.
+/// Writes stop at the end of the region, so [`Write::write_all()`] fails with
+/// [`ErrorKind::WriteZero`] if the data does not fit.
+///
+/// See [`SharedFile::lock_range_write()`] for further details.
+pub struct SharedFileRangeWriteLockGuard<'a> {
+    file: &'a mut File,
+    region: SharedFileRegion,
+    _lock: FileLockGuard<'a>,
+}
+
+impl<'a> SharedFileRangeWriteLockGuard<'a> {
+    /// Returns the locked region as a range of offsets of the file.
+    pub fn range(&self) -> Range<u64> {
+        self.region.range()
+    }
+}
+
+impl<'a> Read for SharedFileRangeWriteLockGuard<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let count = self.region.prepare(self.file, buf.len())?;
+        if count == 0 {
+            return Ok(0);
+        }
+        let n = self.file.read(&mut buf[..count])?;
+        self.region.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<'a> Write for SharedFileRangeWriteLockGuard<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let count = self.region.prepare(self.file, buf.len())?;
+        if count == 0 {
+            return Ok(0);
+        }
+        let n = self.file.write(&buf[..count])?;
+        self.region.position += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+}
+
+impl<'a> Seek for SharedFileRangeWriteLockGuard<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.region.seek(pos)
+    }
+}
+
 //=============================================================================
 // SharedFile
 //-----------------------------------------------------------------------------
@@ -217,8 +658,12 @@ impl<'a> Seek for SharedFileWriteLockGuard<'a> {
 /// lock file to control the shared read access to the file as well as an
 /// exclusive read and write access to it.
 ///
-/// Internally, it uses the crate `fd-lock` to control the access to the lock
-/// file while protecting the access tot the actual file.
+/// Internally, it uses the advisory locks provided by the OS (`flock()` on
+/// Unix and `LockFileEx()` on Windows) to control the access to the lock file
+/// while protecting the access tot the actual file. These are the same locks
+/// used by the previous versions of this library. On Linux, they are also
+/// mirrored by open file description locks (`F_OFD_SETLK`), which implement
+/// the lock conversion and the byte-range locks.
 ///
 /// The protected file is always opened with shared read and write and create
 /// options.
@@ -235,10 +680,22 @@ impl<'a> Seek for SharedFileWriteLockGuard<'a> {
 /// Because of that, it is recommended to create multiple instances of this
 /// struct pointing to the same file. The access control will be guaranteed
 /// by the use of the lock file instead of the traditional thread sync
-/// mechanisms.
+/// mechanisms. Alternatively, [`SyncSharedFile`] can be shared among threads
+/// directly.
+///
+/// ## Lock files
+///
+/// Whenever the exclusive lock is acquired, the process ID, the host name and
+/// the time of the acquisition are recorded in the lock file. They can be
+/// retrieved by [`Self::lock_owner()`] or [`lock_file_owner()`] in order to
+/// diagnose contention. Lock files are never removed automatically, but on
+/// Unix the ones nobody holds can be removed safely by
+/// [`remove_orphaned_lock_file()`] or [`remove_orphaned_lock_files()`].
 pub struct SharedFile {
-    lock: fd_lock::RwLock<File>,
+    lock: FileLock,
     file: File,
+    path: PathBuf,
+    options: OpenOptions,
 }
 
 impl SharedFile {
@@ -301,8 +758,10 @@ impl SharedFile {
         lock_file: &Path,
     ) -> Result<Self> {
         Ok(Self {
-            lock: fd_lock::RwLock::new(File::create(lock_file)?),
+            lock: FileLock::open(lock_file)?,
             file: options.open(file)?,
+            path: file.to_path_buf(),
+            options: options.clone(),
         })
     }
 
@@ -318,8 +777,10 @@ impl SharedFile {
     ///
     /// Returns read lock that grants access to the file.
     pub fn read(&mut self) -> Result<SharedFileReadLockGuard<'_>> {
+        let lock = self.lock.lock(FileLockMode::Shared)?;
+        Self::refresh_file(&mut self.file, &self.path, &self.options)?;
         Ok(SharedFileReadLockGuard {
-            _lock: self.lock.read()?,
+            lock,
             file: &mut self.file,
         })
     }
@@ -328,8 +789,10 @@ impl SharedFile {
     ///
     /// Returns read/write lock that grants access to the file.
     pub fn write(&mut self) -> Result<SharedFileWriteLockGuard<'_>> {
+        let lock = self.lock.lock(FileLockMode::Exclusive)?;
+        Self::refresh_file(&mut self.file, &self.path, &self.options)?;
         Ok(SharedFileWriteLockGuard {
-            _lock: self.lock.write()?,
+            lock,
             file: &mut self.file,
         })
     }
@@ -339,8 +802,10 @@ impl SharedFile {
     ///
     /// Returns read lock that grants access to the file.
     pub fn try_read(&mut self) -> Result<SharedFileReadLockGuard<'_>> {
+        let lock = self.lock.try_lock(FileLockMode::Shared)?;
+        Self::refresh_file(&mut self.file, &self.path, &self.options)?;
         Ok(SharedFileReadLockGuard {
-            _lock: self.lock.try_read()?,
+            lock,
             file: &mut self.file,
         })
     }
@@ -350,11 +815,577 @@ impl SharedFile {
     ///
     /// Returns read/write lock that grants access to the file.
     pub fn try_write(&mut self) -> Result<SharedFileWriteLockGuard<'_>> {
+        let lock = self.lock.try_lock(FileLockMode::Exclusive)?;
+        Self::refresh_file(&mut self.file, &self.path, &self.options)?;
         Ok(SharedFileWriteLockGuard {
-            _lock: self.lock.try_write()?,
+            lock,
             file: &mut self.file,
         })
     }
+
+    /// Attempts to lock the file for shared read until the timeout expires.
+    /// The lock is retried with an exponential backoff.
+    ///
+    /// Arguments:
+    /// - `timeout`: The maximum amount of time to wait for the lock;
+    ///
+    /// Returns read lock that grants access to the file. It fails with
+    /// [`ErrorKind::TimedOut`] if the lock cannot be acquired in time.
+    pub fn read_timeout(&mut self, timeout: Duration) -> Result<SharedFileReadLockGuard<'_>> {
+        let lock = self.lock.lock_timeout(FileLockMode::Shared, timeout)?;
+        Self::refresh_file(&mut self.file, &self.path, &self.options)?;
+        Ok(SharedFileReadLockGuard {
+            lock,
+            file: &mut self.file,
+        })
+    }
+
+    /// Attempts to lock the file for exclusive write and read until the
+    /// timeout expires. The lock is retried with an exponential backoff.
+    ///
+    /// Arguments:
+    /// - `timeout`: The maximum amount of time to wait for the lock;
+    ///
+    /// Returns read/write lock that grants access to the file. It fails with
+    /// [`ErrorKind::TimedOut`] if the lock cannot be acquired in time.
+    pub fn write_timeout(&mut self, timeout: Duration) -> Result<SharedFileWriteLockGuard<'_>> {
+        let lock = self.lock.lock_timeout(FileLockMode::Exclusive, timeout)?;
+        Self::refresh_file(&mut self.file, &self.path, &self.options)?;
+        Ok(SharedFileWriteLockGuard {
+            lock,
+            file: &mut self.file,
+        })
+    }
+
+    /// Locks a region of the file for shared read, waiting as long as
+    /// necessary. Multiple actors may read and write independent regions of
+    /// the file at the same time.
+    ///
+    /// The region locks are set over the same lock file of the whole file
+    /// locks, so they conflict with them. A region read lock conflicts only
+    /// with a write lock that overlaps it or with [`Self::write()`].
+    ///
+    /// Byte-range locks are implemented with the `fcntl()` open file
+    /// description locks and are supported only on Linux. Every region lock
+    /// also holds a shared `flock()` over the lock file, so actors that use
+    /// only `flock()`, such as the previous versions of this library, see it
+    /// as a read lock of the whole file.
+    ///
+    /// Arguments:
+    /// - `offset`: The offset of the region;
+    /// - `len`: The length of the region. It must not be 0;
+    ///
+    /// Returns the read lock that grants access to the region. It fails with
+    /// [`ErrorKind::InvalidInput`] if the region is empty or too large and
+    /// with [`ErrorKind::Unsupported`] on other platforms.
+    pub fn lock_range_read(
+        &mut self,
+        offset: u64,
+        len: u64,
+    ) -> Result<SharedFileRangeReadLockGuard<'_>> {
+        let range = FileLockRange { offset, len };
+        let lock = self.lock.lock_range(FileLockMode::Shared, range)?;
+        Self::refresh_file(&mut self.file, &self.path, &self.options)?;
+        Ok(SharedFileRangeReadLockGuard {
+            file: &mut self.file,
+            region: SharedFileRegion::new(range),
+            _lock: lock,
+        })
+    }
+
+    /// Locks a region of the file for exclusive write and read, waiting as
+    /// long as necessary. It conflicts with any lock that overlaps the
+    /// region and with the locks over the whole file.
+    ///
+    /// See [`Self::lock_range_read()`] for further details.
+    ///
+    /// Arguments:
+    /// - `offset`: The offset of the region;
+    /// - `len`: The length of the region. It must not be 0;
+    ///
+    /// Returns the write lock that grants access to the region. It fails with
+    /// [`ErrorKind::InvalidInput`] if the region is empty or too large and
+    /// with [`ErrorKind::Unsupported`] on other platforms.
+    pub fn lock_range_write(
+        &mut self,
+        offset: u64,
+        len: u64,
+    ) -> Result<SharedFileRangeWriteLockGuard<'_>> {
+        let range = FileLockRange { offset, len };
+        let lock = self.lock.lock_range(FileLockMode::Exclusive, range)?;
+        Self::refresh_file(&mut self.file, &self.path, &self.options)?;
+        Ok(SharedFileRangeWriteLockGuard {
+            file: &mut self.file,
+            region: SharedFileRegion::new(range),
+            _lock: lock,
+        })
+    }
+
+    /// Attempts to lock a region of the file for shared read. It fails with
+    /// [`ErrorKind::WouldBlock`] without waiting if the lock cannot be
+    /// acquired.
+    ///
+    /// See [`Self::lock_range_read()`] for further details.
+    ///
+    /// Arguments:
+    /// - `offset`: The offset of the region;
+    /// - `len`: The length of the region. It must not be 0;
+    ///
+    /// Returns the read lock that grants access to the region.
+    pub fn try_lock_range_read(
+        &mut self,
+        offset: u64,
+        len: u64,
+    ) -> Result<SharedFileRangeReadLockGuard<'_>> {
+        let range = FileLockRange { offset, len };
+        let lock = self.lock.try_lock_range(FileLockMode::Shared, range)?;
+        Self::refresh_file(&mut self.file, &self.path, &self.options)?;
+        Ok(SharedFileRangeReadLockGuard {
+            file: &mut self.file,
+            region: SharedFileRegion::new(range),
+            _lock: lock,
+        })
+    }
+
+    /// Attempts to lock a region of the file for exclusive write and read.
+    /// It fails with [`ErrorKind::WouldBlock`] without waiting if the lock
+    /// cannot be acquired.
+    ///
+    /// See [`Self::lock_range_read()`] for further details.
+    ///
+    /// Arguments:
+    /// - `offset`: The offset of the region;
+    /// - `len`: The length of the region. It must not be 0;
+    ///
+    /// Returns the write lock that grants access to the region.
+    pub fn try_lock_range_write(
+        &mut self,
+        offset: u64,
+        len: u64,
+    ) -> Result<SharedFileRangeWriteLockGuard<'_>> {
+        let range = FileLockRange { offset, len };
+        let lock = self.lock.try_lock_range(FileLockMode::Exclusive, range)?;
+        Self::refresh_file(&mut self.file, &self.path, &self.options)?;
+        Ok(SharedFileRangeWriteLockGuard {
+            file: &mut self.file,
+            region: SharedFileRegion::new(range),
+            _lock: lock,
+        })
+    }
+
+    /// Returns the path to the lock file.
+    pub fn lock_file(&self) -> &Path {
+        self.lock.path()
+    }
+
+    /// Returns the latest actor that acquired the exclusive lock if it is
+    /// currently held by someone, including this instance. See
+    /// [`lock_file_owner()`] for further details.
+    pub fn lock_owner(&self) -> Result<Option<SharedLockOwner>> {
+        FileLock::owner(self.lock.path())
+    }
+
+    /// Atomically replaces the contents of the file while holding the
+    /// exclusive lock. The new contents are written into a temporary file in
+    /// the same directory, which is flushed to the disk and renamed over the
+    /// protected file. Finally the directory itself is flushed.
+    ///
+    /// If the writer fails or panics, the temporary file is removed and the
+    /// protected file is left untouched.
+    ///
+    /// Other instances of `SharedFile` that point to the same file will see
+    /// the new contents the next time they acquire the lock. On Unix and
+    /// Windows, they reopen the file only when the identity of the file on the
+    /// path differs from the one they opened. On the other platforms, the
+    /// file cannot be identified, so they reopen it whenever they acquire the
+    /// lock.
+    ///
+    /// Arguments:
+    /// - `writer`: The function that writes the new contents of the file;
+    ///
+    /// Returns `Ok(())` on success or an IO error to indicate what went wrong.
+    pub fn replace_contents<F>(&mut self, writer: F) -> Result<()>
+    where
+        F: FnOnce(&mut File) -> Result<()>,
+    {
+        let _lock = self.lock.lock(FileLockMode::Exclusive)?;
+        let mut replacement = SharedFileReplacement::new(&self.path)?;
+        writer(&mut replacement.file)?;
+        replacement.commit(&self.path)?;
+        self.file = Self::reopen_options(&self.options).open(&self.path)?;
+        Ok(())
+    }
+
+    /// Returns the options used to reopen the protected file after it was
+    /// replaced. They are the original options without truncation or the
+    /// exclusive creation.
+    ///
+    /// Arguments:
+    /// - `options`: The original options;
+    fn reopen_options(options: &OpenOptions) -> OpenOptions {
+        let mut options = options.clone();
+        options.truncate(false).create_new(false);
+        options
+    }
+
+    /// Reopens the protected file if it was replaced by another instance
+    /// since it was opened. It must be called while holding the lock.
+    ///
+    /// Arguments:
+    /// - `file`: The currently opened file;
+    /// - `path`: The path to the protected file;
+    /// - `options`: The original options;
+    #[cfg(unix)]
+    fn refresh_file(file: &mut File, path: &Path, options: &OpenOptions) -> Result<()> {
+        use std::os::unix::fs::MetadataExt;
+
+        let current = file.metadata()?;
+        let on_disk = match std::fs::metadata(path) {
+            Ok(metadata) => metadata,
+            // Nothing to reopen
+            Err(_) => return Ok(()),
+        };
+        if current.dev() != on_disk.dev() || current.ino() != on_disk.ino() {
+            *file = Self::reopen_options(options).open(path)?;
+        }
+        Ok(())
+    }
+
+    /// Reopens the protected file if it was replaced by another instance
+    /// since it was opened. It must be called while holding the lock.
+    ///
+    /// Arguments:
+    /// - `file`: The currently opened file;
+    /// - `path`: The path to the protected file;
+    /// - `options`: The original options;
+    #[cfg(windows)]
+    fn refresh_file(file: &mut File, path: &Path, options: &OpenOptions) -> Result<()> {
+        let on_disk = match File::open(path) {
+            Ok(on_disk) => on_disk,
+            // Nothing to reopen
+            Err(_) => return Ok(()),
+        };
+        if Self::file_identity(file)? != Self::file_identity(&on_disk)? {
+            *file = Self::reopen_options(options).open(path)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the identity of the given file. It is made of the serial number
+    /// of its volume and its file index, as returned by
+    /// `GetFileInformationByHandle()`.
+    ///
+    /// Arguments:
+    /// - `file`: The file;
+    #[cfg(windows)]
+    fn file_identity(file: &File) -> Result<(u32, u32, u32)> {
+        use std::os::windows::io::AsRawHandle;
+        use windows::Win32::Foundation::HANDLE;
+        use windows::Win32::Storage::FileSystem::{
+            GetFileInformationByHandle, BY_HANDLE_FILE_INFORMATION,
+        };
+
+        let mut info = BY_HANDLE_FILE_INFORMATION::default();
+        let handle = HANDLE(file.as_raw_handle() as isize);
+        if unsafe { GetFileInformationByHandle(handle, &mut info) }.as_bool() {
+            Ok((
+                info.dwVolumeSerialNumber,
+                info.nFileIndexHigh,
+                info.nFileIndexLow,
+            ))
+        } else {
+            Err(Error::last_os_error())
+        }
+    }
+
+    /// Reopens the protected file if it was replaced by another instance
+    /// since it was opened. It must be called while holding the lock.
+    ///
+    /// This platform does not expose a file identity, so the file is always
+    /// reopened and its cursor goes back to the start.
+    ///
+    /// Arguments:
+    /// - `file`: The currently opened file;
+    /// - `path`: The path to the protected file;
+    /// - `options`: The original options;
+    #[cfg(not(any(unix, windows)))]
+    fn refresh_file(file: &mut File, path: &Path, options: &OpenOptions) -> Result<()> {
+        if std::fs::metadata(path).is_err() {
+            // Nothing to reopen
+            return Ok(());
+        }
+        *file = Self::reopen_options(options).open(path)?;
+        Ok(())
+    }
+}
+
+//=============================================================================
+// SharedFileReplacement
+//-----------------------------------------------------------------------------
+/// The temporary file used by [`SharedFile::replace_contents()`]. It is
+/// removed when dropped unless it was committed.
+struct SharedFileReplacement {
+    file: File,
+    path: PathBuf,
+    committed: bool,
+}
+
+impl SharedFileReplacement {
+    /// Suffix of the temporary file.
+    const TEMP_FILE_SUFFIX: &'static str = ".tmp~";
+
+    /// Creates a new temporary file in the same directory of the target file.
+    /// The permissions of the target file are copied if it exists.
+    ///
+    /// Arguments:
+    /// - `target`: The path to the target file;
+    fn new(target: &Path) -> Result<Self> {
+        let file_name = match target.file_name() {
+            Some(name) => name,
+            None => {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    "Unable to extract the file name.",
+                ))
+            }
+        };
+        let mut temp_name = OsString::from(".");
+        temp_name.push(file_name);
+        temp_name.push(format!(".{:016x}", rand::random::<u64>()));
+        temp_name.push(Self::TEMP_FILE_SUFFIX);
+        let path = target.with_file_name(temp_name);
+        let file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+        let replacement = Self {
+            file,
+            path,
+            committed: false,
+        };
+        if let Ok(metadata) = std::fs::metadata(target) {
+            replacement.file.set_permissions(metadata.permissions())?;
+        }
+        Ok(replacement)
+    }
+
+    /// Flushes the temporary file to the disk, renames it over the target file
+    /// and flushes the directory.
+    ///
+    /// Arguments:
+    /// - `target`: The path to the target file;
+    fn commit(&mut self, target: &Path) -> Result<()> {
+        self.file.sync_all()?;
+        std::fs::rename(&self.path, target)?;
+        self.committed = true;
+        match target.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => sync_directory(dir),
+            _ => sync_directory(Path::new(".")),
+        }
+    }
+}
+
+impl Drop for SharedFileReplacement {
+    fn drop(&mut self) {
+        if !self.committed {
+            let _ = std::fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Flushes the entries of a directory to the disk.
+///
+/// Arguments:
+/// - `dir`: The directory;
+#[cfg(unix)]
+fn sync_directory(dir: &Path) -> Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Flushes the entries of a directory to the disk. Directories cannot be
+/// opened as files on this platform, so it does nothing.
+#[cfg(not(unix))]
+fn sync_directory(_dir: &Path) -> Result<()> {
+    Ok(())
+}
+
+//=============================================================================
+// SharedDirectoryScope
+//-----------------------------------------------------------------------------
+/// This struct implements the file operations performed inside the protected
+/// directory by the [`SharedDirectory`] lock guards.
+///
+/// All names are resolved relative to the protected directory. Names that
+/// could escape it, such as absolute paths, `..` or symbolic links, are
+/// rejected, as is the lock file itself.
+///
+/// The names are checked by [`Self::resolve()`] and the resulting path is
+/// used later by a separate call, which resolves every component again. An
+/// actor that changes the directory without holding the lock may replace a
+/// component with a symbolic link in between, so these checks hold only
+/// against the actors that cooperate by holding the lock.
+struct SharedDirectoryScope<'a> {
+    directory: &'a Path,
+    lock_file: &'a Path,
+}
+
+impl<'a> SharedDirectoryScope<'a> {
+    /// Creates a new `SharedDirectoryScope`.
+    ///
+    /// Arguments:
+    /// - `directory`: The protected directory;
+    /// - `lock_file`: The lock file that protects the directory;
+    fn new(directory: &'a Path, lock_file: &'a Path) -> Self {
+        Self {
+            directory,
+            lock_file,
+        }
+    }
+
+    /// Verifies if the given path points to the lock file.
+    ///
+    /// Arguments:
+    /// - `path`: The path to be verified;
+    ///
+    /// Returns true if the path points to the lock file or false otherwise.
+    fn is_lock_file(&self, path: &Path) -> bool {
+        if path == self.lock_file {
+            return true;
+        }
+        if path.file_name() != self.lock_file.file_name() {
+            return false;
+        }
+        match (path.canonicalize(), self.lock_file.canonicalize()) {
+            (Ok(a), Ok(b)) => a == b,
+            _ => false,
+        }
+    }
+
+    /// Resolves the given name into a path inside the protected directory.
+    ///
+    /// Arguments:
+    /// - `name`: The relative path of the entry inside the protected directory;
+    ///
+    /// Returns the resolved path. It fails with [`ErrorKind::InvalidInput`] if
+    /// the name is empty, is not a plain relative path, crosses a symbolic
+    /// link or points to the lock file. The symbolic links are checked only
+    /// when this method is called, not when the path is used.
+    fn resolve(&self, name: &Path) -> Result<PathBuf> {
+        let mut path = self.directory.to_path_buf();
+        let mut empty = true;
+        for component in name.components() {
+            match component {
+                Component::Normal(c) => path.push(c),
+                _ => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{:?} escapes the protected directory.", name),
+                    ))
+                }
+            }
+            empty = false;
+            if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+                if metadata.file_type().is_symlink() {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("{:?} is a symbolic link.", path),
+                    ));
+                }
+            }
+        }
+        if empty {
+            return Err(Error::new(ErrorKind::InvalidInput, "The name is empty."));
+        }
+        if self.is_lock_file(&path) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "The lock file cannot be accessed.",
+            ));
+        }
+        Ok(path)
+    }
+
+    /// Opens the file with the given options. The last component of the path
+    /// is never followed if it is a symbolic link.
+    ///
+    /// Arguments:
+    /// - `name`: The relative path of the file inside the protected directory;
+    /// - `options`: The options used to open the file;
+    ///
+    /// Returns the opened file.
+    fn open_with(&self, name: &Path, options: &mut OpenOptions) -> Result<File> {
+        let path = self.resolve(name)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.custom_flags(libc::O_NOFOLLOW);
+        }
+        options.open(path)
+    }
+
+    /// Lists the entries of the protected directory, except the lock file.
+    ///
+    /// Returns the names of the entries sorted in ascending order.
+    fn list(&self) -> Result<Vec<OsString>> {
+        let mut names = Vec::new();
+        for entry in std::fs::read_dir(self.directory)? {
+            let entry = entry?;
+            if !self.is_lock_file(&entry.path()) {
+                names.push(entry.file_name());
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Opens an existing file for read.
+    ///
+    /// Arguments:
+    /// - `name`: The relative path of the file inside the protected directory;
+    fn open(&self, name: &Path) -> Result<File> {
+        self.open_with(name, OpenOptions::new().read(true))
+    }
+
+    /// Opens an existing file for read and write.
+    ///
+    /// Arguments:
+    /// - `name`: The relative path of the file inside the protected directory;
+    fn open_write(&self, name: &Path) -> Result<File> {
+        self.open_with(name, OpenOptions::new().read(true).write(true))
+    }
+
+    /// Creates a file for read and write. It is truncated if it already
+    /// exists.
+    ///
+    /// Arguments:
+    /// - `name`: The relative path of the file inside the protected directory;
+    fn create(&self, name: &Path) -> Result<File> {
+        self.open_with(
+            name,
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true),
+        )
+    }
+
+    /// Removes a file.
+    ///
+    /// Arguments:
+    /// - `name`: The relative path of the file inside the protected directory;
+    fn remove(&self, name: &Path) -> Result<()> {
+        std::fs::remove_file(self.resolve(name)?)
+    }
+
+    /// Renames a file or directory, replacing the target if it is a file.
+    ///
+    /// Arguments:
+    /// - `from`: The relative path of the current entry;
+    /// - `to`: The relative path of the new entry;
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        std::fs::rename(self.resolve(from)?, self.resolve(to)?)
+    }
 }
 
 //=============================================================================
@@ -363,8 +1394,43 @@ impl SharedFile {
 /// An RAII implementation of an “advisory lock” of a shared read to the
 /// protected directory. When this structure is dropped (falls out of scope), the
 /// shared read lock is released.
+///
+/// All names used by this guard are relative to the protected directory.
+/// Absolute paths, `..`, symbolic links and the lock file itself are rejected
+/// with [`ErrorKind::InvalidInput`].
+///
+/// Those checks protect the directory only against the actors that hold its
+/// lock. The names are checked before each operation and resolved again by
+/// it, so an actor that ignores the lock may swap a component for a symbolic
+/// link in between. They must not be used as a security boundary.
 pub struct SharedDirectoryReadLockGuard<'a> {
-    _lock: fd_lock::RwLockReadGuard<'a, File>,
+    scope: SharedDirectoryScope<'a>,
+    _lock: FileLockGuard<'a>,
+}
+
+impl<'a> SharedDirectoryReadLockGuard<'a> {
+    /// Returns the path to the protected directory.
+    pub fn directory(&self) -> &Path {
+        self.scope.directory
+    }
+
+    /// Lists the entries of the protected directory. The lock file is never
+    /// listed.
+    ///
+    /// Returns the names of the entries sorted in ascending order.
+    pub fn list(&self) -> Result<Vec<OsString>> {
+        self.scope.list()
+    }
+
+    /// Opens an existing file inside the protected directory for read.
+    ///
+    /// Arguments:
+    /// - `name`: The relative path of the file inside the protected directory;
+    ///
+    /// Returns the opened file.
+    pub fn open<P: AsRef<Path>>(&self, name: P) -> Result<File> {
+        self.scope.open(name.as_ref())
+    }
 }
 
 //=============================================================================
@@ -373,8 +1439,73 @@ pub struct SharedDirectoryReadLockGuard<'a> {
 /// An RAII implementation of an “advisory lock” of a exclusive read and write
 /// to the protected directory. When this structure is dropped (falls out of scope),
 /// the shared read lock is released.
+///
+/// All names used by this guard are relative to the protected directory.
+/// Absolute paths, `..`, symbolic links and the lock file itself are rejected
+/// with [`ErrorKind::InvalidInput`].
+///
+/// Those checks protect the directory only against the actors that hold its
+/// lock. The names are checked before each operation and resolved again by
+/// it, so an actor that ignores the lock may swap a component for a symbolic
+/// link in between. They must not be used as a security boundary.
 pub struct SharedDirectoryWriteLockGuard<'a> {
-    _lock: fd_lock::RwLockWriteGuard<'a, File>,
+    scope: SharedDirectoryScope<'a>,
+    _lock: FileLockGuard<'a>,
+}
+
+impl<'a> SharedDirectoryWriteLockGuard<'a> {
+    /// Returns the path to the protected directory.
+    pub fn directory(&self) -> &Path {
+        self.scope.directory
+    }
+
+    /// Lists the entries of the protected directory. The lock file is never
+    /// listed.
+    ///
+    /// Returns the names of the entries sorted in ascending order.
+    pub fn list(&self) -> Result<Vec<OsString>> {
+        self.scope.list()
+    }
+
+    /// Opens an existing file inside the protected directory for read and
+    /// write.
+    ///
+    /// Arguments:
+    /// - `name`: The relative path of the file inside the protected directory;
+    ///
+    /// Returns the opened file.
+    pub fn open<P: AsRef<Path>>(&self, name: P) -> Result<File> {
+        self.scope.open_write(name.as_ref())
+    }
+
+    /// Creates a file inside the protected directory for read and write. The
+    /// file is truncated if it already exists.
+    ///
+    /// Arguments:
+    /// - `name`: The relative path of the file inside the protected directory;
+    ///
+    /// Returns the created file.
+    pub fn create<P: AsRef<Path>>(&self, name: P) -> Result<File> {
+        self.scope.create(name.as_ref())
+    }
+
+    /// Removes a file from the protected directory.
+    ///
+    /// Arguments:
+    /// - `name`: The relative path of the file inside the protected directory;
+    pub fn remove<P: AsRef<Path>>(&self, name: P) -> Result<()> {
+        self.scope.remove(name.as_ref())
+    }
+
+    /// Renames an entry inside the protected directory. The target is
+    /// replaced if it is an existing file.
+    ///
+    /// Arguments:
+    /// - `from`: The relative path of the current entry;
+    /// - `to`: The relative path of the new entry;
+    pub fn rename<P: AsRef<Path>, Q: AsRef<Path>>(&self, from: P, to: Q) -> Result<()> {
+        self.scope.rename(from.as_ref(), to.as_ref())
+    }
 }
 
 //=============================================================================
@@ -383,8 +1514,10 @@ pub struct SharedDirectoryWriteLockGuard<'a> {
 /// This struct implements an “advisory lock” of a directory by using an
 /// auxiliary lock file to control the shared read access to it.
 ///
-/// Internally, it uses the crate `fd-lock` to control the access to the lock
-/// file while protecting the access tot the actual file.
+/// Internally, it uses the advisory locks provided by the OS (`flock()` on
+/// Unix and `LockFileEx()` on Windows) to control the access to the lock file
+/// while protecting the access tot the actual file. These are the same locks
+/// used by the previous versions of this library.
 ///
 /// ## Locking the same file in multiple threads
 ///
@@ -400,7 +1533,7 @@ pub struct SharedDirectoryWriteLockGuard<'a> {
 /// by the use of the lock file instead of the traditional thread sync
 /// mechanisms.
 pub struct SharedDirectory {
-    lock: fd_lock::RwLock<File>,
+    lock: FileLock,
     dir_name: OsString,
 }
 
@@ -493,7 +1626,7 @@ impl SharedDirectory {
             ));
         }
         Ok(Self {
-            lock: fd_lock::RwLock::new(File::create(lock_file)?),
+            lock: FileLock::open(lock_file)?,
             dir_name: directory.as_os_str().to_os_string(),
         })
     }
@@ -503,12 +1636,25 @@ impl SharedDirectory {
         Path::new(&self.dir_name)
     }
 
+    /// Returns the path to the lock file.
+    pub fn lock_file(&self) -> &Path {
+        self.lock.path()
+    }
+
+    /// Returns the latest actor that acquired the exclusive lock if it is
+    /// currently held by someone, including this instance. See
+    /// [`lock_file_owner()`] for further details.
+    pub fn lock_owner(&self) -> Result<Option<SharedLockOwner>> {
+        FileLock::owner(self.lock.path())
+    }
+
     /// Locks the file for shared read.
     ///
     /// Returns read lock that grants access to the file.
     pub fn read(&mut self) -> Result<SharedDirectoryReadLockGuard<'_>> {
         Ok(SharedDirectoryReadLockGuard {
-            _lock: self.lock.read()?,
+            scope: SharedDirectoryScope::new(Path::new(&self.dir_name), self.lock.path()),
+            _lock: self.lock.lock(FileLockMode::Shared)?,
         })
     }
 
@@ -517,7 +1663,8 @@ impl SharedDirectory {
     /// Returns read/write lock that grants access to the file.
     pub fn write(&mut self) -> Result<SharedDirectoryWriteLockGuard<'_>> {
         Ok(SharedDirectoryWriteLockGuard {
-            _lock: self.lock.write()?,
+            scope: SharedDirectoryScope::new(Path::new(&self.dir_name), self.lock.path()),
+            _lock: self.lock.lock(FileLockMode::Exclusive)?,
         })
     }
 
@@ -527,7 +1674,8 @@ impl SharedDirectory {
     /// Returns read lock that grants access to the file.
     pub fn try_read(&mut self) -> Result<SharedDirectoryReadLockGuard<'_>> {
         Ok(SharedDirectoryReadLockGuard {
-            _lock: self.lock.try_read()?,
+            scope: SharedDirectoryScope::new(Path::new(&self.dir_name), self.lock.path()),
+            _lock: self.lock.try_lock(FileLockMode::Shared)?,
         })
     }
 
@@ -537,7 +1685,133 @@ impl SharedDirectory {
     /// Returns read/write lock that grants access to the file.
     pub fn try_write(&mut self) -> Result<SharedDirectoryWriteLockGuard<'_>> {
         Ok(SharedDirectoryWriteLockGuard {
-            _lock: self.lock.try_write()?,
+            scope: SharedDirectoryScope::new(Path::new(&self.dir_name), self.lock.path()),
+            _lock: self.lock.try_lock(FileLockMode::Exclusive)?,
+        })
+    }
+
+    /// Attempts to lock the directory for shared read until the timeout
+    /// expires. The lock is retried with an exponential backoff.
+    ///
+    /// Arguments:
+    /// - `timeout`: The maximum amount of time to wait for the lock;
+    ///
+    /// Returns read lock that grants access to the directory. It fails with
+    /// [`ErrorKind::TimedOut`] if the lock cannot be acquired in time.
+    pub fn read_timeout(&mut self, timeout: Duration) -> Result<SharedDirectoryReadLockGuard<'_>> {
+        Ok(SharedDirectoryReadLockGuard {
+            scope: SharedDirectoryScope::new(Path::new(&self.dir_name), self.lock.path()),
+            _lock: self.lock.lock_timeout(FileLockMode::Shared, timeout)?,
+        })
+    }
+
+    /// Attempts to lock the directory for exclusive write and read until the
+    /// timeout expires. The lock is retried with an exponential backoff.
+    ///
+    /// Arguments:
+    /// - `timeout`: The maximum amount of time to wait for the lock;
+    ///
+    /// Returns read/write lock that grants access to the directory. It fails
+    /// with [`ErrorKind::TimedOut`] if the lock cannot be acquired in time.
+    pub fn write_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<SharedDirectoryWriteLockGuard<'_>> {
+        Ok(SharedDirectoryWriteLockGuard {
+            scope: SharedDirectoryScope::new(Path::new(&self.dir_name), self.lock.path()),
+            _lock: self.lock.lock_timeout(FileLockMode::Exclusive, timeout)?,
         })
     }
 }
+
+//=============================================================================
+// Lock file maintenance
+//-----------------------------------------------------------------------------
+/// Returns the owner recorded in a lock file used by [`SharedFile`] or
+/// [`SharedDirectory`] if the exclusive lock is currently held by someone.
+///
+/// Every actor records its process ID, host name and the time of the
+/// acquisition when it acquires the exclusive lock, so the result is the
+/// actor that holds it. The shared locks are not recorded. When the exclusive
+/// lock is free, this function briefly acquires the shared lock in order to
+/// verify it, so a concurrent `try_write()` may fail in the meantime.
+///
+/// Arguments:
+/// - `lock_file`: The path to the lock file;
+///
+/// Returns:
+/// - `Ok(Some(x))`: The actor that holds the exclusive lock;
+/// - `Ok(None)`: If nobody holds the exclusive lock or the lock file does not
+///   exist;
+/// - `Err(e)`: If the lock file cannot be read or its contents are invalid;
+pub fn lock_file_owner(lock_file: &Path) -> Result<Option<SharedLockOwner>> {
+    FileLock::owner(lock_file)
+}
+
+/// Removes a lock file used by [`SharedFile`] or [`SharedDirectory`] if
+/// nobody holds the lock.
+///
+/// It is safe to remove the lock file while other instances still have it
+/// open. Whenever they acquire the lock again, they will detect that the lock
+/// file was removed and will move to a new one. This detection requires the
+/// identification of the files, so this function is supported only on Unix.
+///
+/// Arguments:
+/// - `lock_file`: The path to the lock file;
+///
+/// Returns `Ok(true)` if the file was removed or `Ok(false)` if it is in use
+/// or does not exist. It fails with [`ErrorKind::Unsupported`] on the other
+/// platforms.
+pub fn remove_orphaned_lock_file(lock_file: &Path) -> Result<bool> {
+    FileLock::remove_unused(lock_file)
+}
+
+/// Removes all lock files inside a directory that are not held by anyone. It
+/// only considers the files named by [`DefaultSharedFileLockNameBuilder`] and
+/// [`SharedDirectory::DEFAULT_LOCK_FILE_NAME`].
+///
+/// See [`remove_orphaned_lock_file()`] for further details.
+///
+/// Arguments:
+/// - `directory`: The directory that contains the lock files;
+///
+/// Returns the list of lock files removed. It fails with
+/// [`ErrorKind::Unsupported`] on the platforms other than Unix.
+pub fn remove_orphaned_lock_files(directory: &Path) -> Result<Vec<PathBuf>> {
+    if cfg!(not(unix)) {
+        return Err(Error::new(
+            ErrorKind::Unsupported,
+            "Lock files cannot be removed safely on this platform.",
+        ));
+    }
+    let mut removed = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        if !entry.file_type()?.is_file() || !is_default_lock_file_name(&entry.file_name()) {
+            continue;
+        }
+        let path = entry.path();
+        if remove_orphaned_lock_file(&path)? {
+            removed.push(path);
+        }
+    }
+    Ok(removed)
+}
+
+/// Verifies if a file name is one of the default lock file names.
+///
+/// Arguments:
+/// - `file_name`: The file name;
+fn is_default_lock_file_name(file_name: &OsStr) -> bool {
+    match file_name.to_str() {
+        Some(name) => {
+            name == SharedDirectory::DEFAULT_LOCK_FILE_NAME
+                || (name.len()
+                    > DefaultSharedFileLockNameBuilder::LOCK_FILE_PREFIX.len()
+                        + DefaultSharedFileLockNameBuilder::LOCK_FILE_SUFFIX.len()
+                    && name.starts_with(DefaultSharedFileLockNameBuilder::LOCK_FILE_PREFIX)
+                    && name.ends_with(DefaultSharedFileLockNameBuilder::LOCK_FILE_SUFFIX))
+        }
+        None => false,
+    }
+}
@@ -0,0 +1,424 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+//! This module implements [`SyncSharedFile`], a thread safe variant of
+//! [`SharedFile`].
+use super::lock::{retry_until, FileLock, FileLockGuard, FileLockMode};
+use super::{
+    DefaultSharedFileLockNameBuilder, SharedFile, SharedFileLockNameBuilder, SharedLockOwner,
+};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind, Read, Result, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{
+    Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError, Weak,
+};
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+mod tests;
+
+//=============================================================================
+// In-process registry
+//-----------------------------------------------------------------------------
+/// The in-process locks of all lock files in use by this process, indexed by
+/// the canonical path of the lock file.
+type LockRegistry = HashMap<PathBuf, Weak<RwLock<()>>>;
+
+/// Returns the in-process lock associated with a given lock file. All
+/// instances of [`SyncSharedFile`] that use the same lock file share the same
+/// in-process lock.
+///
+/// Arguments:
+/// - `lock_file`: The canonical path of the lock file;
+fn registry_lock(lock_file: &Path) -> Arc<RwLock<()>> {
+    static REGISTRY: Mutex<Option<LockRegistry>> = Mutex::new(None);
+    let mut registry = REGISTRY.lock().unwrap_or_else(PoisonError::into_inner);
+    let registry = registry.get_or_insert_with(HashMap::new);
+    if let Some(lock) = registry.get(lock_file).and_then(Weak::upgrade) {
+        return lock;
+    }
+    // Discard the entries that are no longer in use
+    registry.retain(|_, lock| lock.strong_count() > 0);
+    let lock = Arc::new(RwLock::new(()));
+    registry.insert(lock_file.to_path_buf(), Arc::downgrade(&lock));
+    lock
+}
+
+/// Creates the error returned when the in-process lock is held by others.
+fn would_block() -> Error {
+    Error::new(
+        ErrorKind::WouldBlock,
+        "The lock is held by another thread of this process.",
+    )
+}
+
+//=============================================================================
+// SyncSharedFileReadLockGuard
+//-----------------------------------------------------------------------------
+/// An RAII implementation of an “advisory lock” of a shared read to the
+/// protected file acquired from a [`SyncSharedFile`]. When this structure is
+/// dropped (falls out of scope), the shared read lock is released.
+///
+/// Each guard has its own instance of the protected file, so its offset is
+/// not shared with other guards.
+pub struct SyncSharedFileReadLockGuard<'a> {
+    file: File,
+    _lock: FileLockGuard<'static>,
+    _guard: RwLockReadGuard<'a, ()>,
+}
+
+impl<'a> SyncSharedFileReadLockGuard<'a> {
+    /// Returns a reference to the protected file.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+}
+
+impl<'a> Read for SyncSharedFileReadLockGuard<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl<'a> Seek for SyncSharedFileReadLockGuard<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+//=============================================================================
+// SyncSharedFileWriteLockGuard
+//-----------------------------------------------------------------------------
+/// An RAII implementation of an “advisory lock” of a exclusive read and write
+/// to the protected file acquired from a [`SyncSharedFile`]. When this
+/// structure is dropped (falls out of scope), the exclusive lock is released.
+pub struct SyncSharedFileWriteLockGuard<'a> {
+    file: File,
+    _lock: FileLockGuard<'static>,
+    _guard: RwLockWriteGuard<'a, ()>,
+}
+
+impl<'a> SyncSharedFileWriteLockGuard<'a> {
+    /// Returns a reference to the protected file.
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Returns a mutable reference to the protected file.
+    pub fn mut_file(&mut self) -> &mut File {
+        &mut self.file
+    }
+}
+
+impl<'a> Read for SyncSharedFileWriteLockGuard<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl<'a> Write for SyncSharedFileWriteLockGuard<'a> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.file.flush()
+    }
+}
+
+impl<'a> Seek for SyncSharedFileWriteLockGuard<'a> {
+    fn seek(&mut self, pos: SeekFrom) -> Result<u64> {
+        self.file.seek(pos)
+    }
+}
+
+//=============================================================================
+// SyncSharedFile
+//-----------------------------------------------------------------------------
+/// This struct implements a thread safe variant of [`SharedFile`]. It can be
+/// shared among multiple threads because the locks are acquired through
+/// shared references.
+///
+/// The access is controlled in two stages. First, the threads of the same
+/// process are coordinated by an in-process [`RwLock`] shared by all
+/// instances that use the same lock file, identified by its canonical path.
+/// After that, the advisory lock of the lock file coordinates the access with
+/// other processes and with the instances of [`SharedFile`].
+///
+/// Each guard opens its own instance of the protected file and of the lock
+/// file, so the file offsets and the advisory locks are never shared among
+/// threads.
+pub struct SyncSharedFile {
+    local: Arc<RwLock<()>>,
+    lock_file: PathBuf,
+    path: PathBuf,
+    options: OpenOptions,
+}
+
+impl SyncSharedFile {
+    /// Creates a new `SyncSharedFile`. The name of the lock file will be
+    /// determine automatically based on the name of the original file.
+    ///
+    /// The shared file is opened with the [`SharedFile::default_options()`].
+    ///
+    /// Arguments:
+    /// - `file`: The file to be protected;
+    ///
+    /// Returns the new instance of an IO error to indicate what went wrong.
+    pub fn new(file: &Path) -> Result<Self> {
+        let options = SharedFile::default_options();
+        Self::with_options(file, &options)
+    }
+
+    /// Creates a new `SyncSharedFile`. The name of the lock file will be
+    /// determine automatically based on the name of the original file.
+    ///
+    /// Arguments:
+    /// - `file`: The file to be protected;
+    /// - `options`: [`OpenOptions`] used to open the file;
+    ///
+    /// Returns the new instance of an IO error to indicate what went wrong.
+    pub fn with_options(file: &Path, options: &OpenOptions) -> Result<Self> {
+        let lock_file_builder = DefaultSharedFileLockNameBuilder;
+        Self::with_option_builder(file, options, &lock_file_builder)
+    }
+
+    /// Creates a new `SyncSharedFile`. The name of the lock file will be
+    /// determine by the specified [`SharedFileLockNameBuilder`].
+    ///
+    /// Arguments:
+    /// - `file`: The file to be protected;
+    /// - `options`: [`OpenOptions`] used to open the file;
+    /// - `lock_file_builder`: The lock file builder to use;
+    ///
+    /// Returns the new instance of an IO error to indicate what went wrong.
+    pub fn with_option_builder(
+        file: &Path,
+        options: &OpenOptions,
+        lock_file_builder: &dyn SharedFileLockNameBuilder,
+    ) -> Result<Self> {
+        let lock_file = lock_file_builder.create_lock_file_path(file)?;
+        Self::with_option_lock_file(file, options, Path::new(lock_file.as_os_str()))
+    }
+
+    /// Creates a new `SyncSharedFile`. The protected file is opened once with
+    /// the given options in order to create it if required. The guards open
+    /// it again without truncation or exclusive creation.
+    ///
+    /// Arguments:
+    /// - `file`: The file to be protected;
+    /// - `options`: [`OpenOptions`] used to open the file;
+    /// - `lock_file`: The lock file to use;
+    ///
+    /// Returns the new instance of an IO error to indicate what went wrong.
+    pub fn with_option_lock_file(
+        file: &Path,
+        options: &OpenOptions,
+        lock_file: &Path,
+    ) -> Result<Self> {
+        // Both files must exist before the lock file can be canonicalized
+        drop(FileLock::open(lock_file)?);
+        drop(options.open(file)?);
+        let lock_file = std::fs::canonicalize(lock_file)?;
+        Ok(Self {
+            local: registry_lock(&lock_file),
+            lock_file,
+            path: file.to_path_buf(),
+            options: SharedFile::reopen_options(options),
+        })
+    }
+
+    /// Returns the canonical path to the lock file.
+    pub fn lock_file(&self) -> &Path {
+        &self.lock_file
+    }
+
+    /// Returns the latest actor that acquired the lock if the lock is
+    /// currently held by someone, including this instance. See
+    /// [`super::lock_file_owner()`] for further details.
+    pub fn lock_owner(&self) -> Result<Option<SharedLockOwner>> {
+        FileLock::owner(&self.lock_file)
+    }
+
+    /// Acquires the advisory lock and opens the protected file. It must be
+    /// called while holding the in-process lock.
+    ///
+    /// Arguments:
+    /// - `mode`: The lock mode;
+    /// - `wait`: Wait for the lock if it is held by others;
+    fn lock_and_open(
+        &self,
+        mode: FileLockMode,
+        wait: bool,
+    ) -> Result<(File, FileLockGuard<'static>)> {
+        let lock = FileLock::open(&self.lock_file)?.into_guard(mode, wait)?;
+        Ok((self.options.open(&self.path)?, lock))
+    }
+
+    /// Attempts to acquire the advisory lock until the deadline and opens the
+    /// protected file. It must be called while holding the in-process lock.
+    ///
+    /// Arguments:
+    /// - `mode`: The lock mode;
+    /// - `deadline`: When the attempts stop;
+    fn lock_and_open_until(
+        &self,
+        mode: FileLockMode,
+        deadline: Instant,
+    ) -> Result<(File, FileLockGuard<'static>)> {
+        let lock = FileLock::open(&self.lock_file)?.into_guard_until(mode, deadline)?;
+        Ok((self.options.open(&self.path)?, lock))
+    }
+
+    /// Attempts to acquire the in-process lock for shared read without
+    /// waiting.
+    fn try_local_read(&self) -> Result<RwLockReadGuard<'_, ()>> {
+        match self.local.try_read() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::Poisoned(e)) => Ok(e.into_inner()),
+            Err(TryLockError::WouldBlock) => Err(would_block()),
+        }
+    }
+
+    /// Attempts to acquire the in-process lock for exclusive write without
+    /// waiting.
+    fn try_local_write(&self) -> Result<RwLockWriteGuard<'_, ()>> {
+        match self.local.try_write() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::Poisoned(e)) => Ok(e.into_inner()),
+            Err(TryLockError::WouldBlock) => Err(would_block()),
+        }
+    }
+
+    /// Locks the file for shared read.
+    ///
+    /// Returns read lock that grants access to the file.
+    pub fn read(&self) -> Result<SyncSharedFileReadLockGuard<'_>> {
+        let guard = self.local.read().unwrap_or_else(PoisonError::into_inner);
+        let (file, lock) = self.lock_and_open(FileLockMode::Shared, true)?;
+        Ok(SyncSharedFileReadLockGuard {
+            file,
+            _lock: lock,
+            _guard: guard,
+        })
+    }
+
+    /// Locks the file for exclusive write and read.
+    ///
+    /// Returns read/write lock that grants access to the file.
+    pub fn write(&self) -> Result<SyncSharedFileWriteLockGuard<'_>> {
+        let guard = self.local.write().unwrap_or_else(PoisonError::into_inner);
+        let (file, lock) = self.lock_and_open(FileLockMode::Exclusive, true)?;
+        Ok(SyncSharedFileWriteLockGuard {
+            file,
+            _lock: lock,
+            _guard: guard,
+        })
+    }
+
+    /// Attempts to lock the file for shared read. It fails with
+    /// [`ErrorKind::WouldBlock`] without waiting if the lock is held by
+    /// another thread or process.
+    ///
+    /// Returns read lock that grants access to the file.
+    pub fn try_read(&self) -> Result<SyncSharedFileReadLockGuard<'_>> {
+        let guard = self.try_local_read()?;
+        let (file, lock) = self.lock_and_open(FileLockMode::Shared, false)?;
+        Ok(SyncSharedFileReadLockGuard {
+            file,
+            _lock: lock,
+            _guard: guard,
+        })
+    }
+
+    /// Attempts to lock the file for exclusive write and read. It fails with
+    /// [`ErrorKind::WouldBlock`] without waiting if the lock is held by
+    /// another thread or process.
+    ///
+    /// Returns read/write lock that grants access to the file.
+    pub fn try_write(&self) -> Result<SyncSharedFileWriteLockGuard<'_>> {
+        let guard = self.try_local_write()?;
+        let (file, lock) = self.lock_and_open(FileLockMode::Exclusive, false)?;
+        Ok(SyncSharedFileWriteLockGuard {
+            file,
+            _lock: lock,
+            _guard: guard,
+        })
+    }
+
+    /// Attempts to lock the file for shared read until the timeout expires.
+    /// Both the in-process lock and the advisory lock are retried with an
+    /// exponential backoff within the same timeout.
+    ///
+    /// Arguments:
+    /// - `timeout`: The maximum amount of time to wait for the lock;
+    ///
+    /// Returns read lock that grants access to the file. It fails with
+    /// [`ErrorKind::TimedOut`] if the lock cannot be acquired in time.
+    pub fn read_timeout(&self, timeout: Duration) -> Result<SyncSharedFileReadLockGuard<'_>> {
+        let deadline = match Instant::now().checked_add(timeout) {
+            Some(deadline) => deadline,
+            None => return self.read(),
+        };
+        let guard = retry_until(deadline, || self.try_local_read())?;
+        let (file, lock) = self.lock_and_open_until(FileLockMode::Shared, deadline)?;
+        Ok(SyncSharedFileReadLockGuard {
+            file,
+            _lock: lock,
+            _guard: guard,
+        })
+    }
+
+    /// Attempts to lock the file for exclusive write and read until the
+    /// timeout expires. Both the in-process lock and the advisory lock are
+    /// retried with an exponential backoff within the same timeout.
+    ///
+    /// Arguments:
+    /// - `timeout`: The maximum amount of time to wait for the lock;
+    ///
+    /// Returns read/write lock that grants access to the file. It fails with
+    /// [`ErrorKind::TimedOut`] if the lock cannot be acquired in time.
+    pub fn write_timeout(&self, timeout: Duration) -> Result<SyncSharedFileWriteLockGuard<'_>> {
+        let deadline = match Instant::now().checked_add(timeout) {
+            Some(deadline) => deadline,
+            None => return self.write(),
+        };
+        let guard = retry_until(deadline, || self.try_local_write())?;
+        let (file, lock) = self.lock_and_open_until(FileLockMode::Exclusive, deadline)?;
+        Ok(SyncSharedFileWriteLockGuard {
+            file,
+            _lock: lock,
+            _guard: guard,
+        })
+    }
+}
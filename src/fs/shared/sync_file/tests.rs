@@ -0,0 +1,378 @@
+/*
+ * BSD 3-Clause License
+ *
+ * Copyright (c) 2019-2020, InterlockLedger Network
+ * All rights reserved.
+ *
+ * Redistribution and use in source and binary forms, with or without
+ * modification, are permitted provided that the following conditions are met:
+ *
+ * * Redistributions of source code must retain the above copyright notice, this
+ *   list of conditions and the following disclaimer.
+ *
+ * * Redistributions in binary form must reproduce the above copyright notice,
+ *   this list of conditions and the following disclaimer in the documentation
+ *   and/or other materials provided with the distribution.
+ *
+ * * Neither the name of the copyright holder nor the names of its
+ *   contributors may be used to endorse or promote products derived from
+ *   this software without specific prior written permission.
+ *
+ * THIS SOFTWARE IS PROVIDED BY THE COPYRIGHT HOLDERS AND CONTRIBUTORS "AS IS"
+ * AND ANY EXPRESS OR IMPLIED WARRANTIES, INCLUDING, BUT NOT LIMITED TO, THE
+ * IMPLIED WARRANTIES OF MERCHANTABILITY AND FITNESS FOR A PARTICULAR PURPOSE ARE
+ * DISCLAIMED. IN NO EVENT SHALL THE COPYRIGHT HOLDER OR CONTRIBUTORS BE LIABLE
+ * FOR ANY DIRECT, INDIRECT, INCIDENTAL, SPECIAL, EXEMPLARY, OR CONSEQUENTIAL
+ * DAMAGES (INCLUDING, BUT NOT LIMITED TO, PROCUREMENT OF SUBSTITUTE GOODS OR
+ * SERVICES; LOSS OF USE, DATA, OR PROFITS; OR BUSINESS INTERRUPTION) HOWEVER
+ * CAUSED AND ON ANY THEORY OF LIABILITY, WHETHER IN CONTRACT, STRICT LIABILITY,
+ * OR TORT (INCLUDING NEGLIGENCE OR OTHERWISE) ARISING IN ANY WAY OUT OF THE USE
+ * OF THIS SOFTWARE, EVEN IF ADVISED OF THE POSSIBILITY OF SUCH DAMAGE.
+ */
+use super::*;
+use crate::fs::shared::tests::{
+    increment_counter, read_counter, run_child, ChildProcess, CHILD_INCREMENTS,
+};
+use il2_test_utils::testdir::TestDirUtils;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Barrier;
+use std::time::{Duration, Instant};
+
+//=============================================================================
+// In-process registry
+//-----------------------------------------------------------------------------
+#[test]
+fn test_registry_lock() {
+    let test_dir = TestDirUtils::new("test_registry_lock").unwrap();
+    let a = std::fs::canonicalize(test_dir.create_test_file("a", b"").unwrap()).unwrap();
+    let b = std::fs::canonicalize(test_dir.create_test_file("b", b"").unwrap()).unwrap();
+
+    let lock_a = registry_lock(&a);
+    assert!(Arc::ptr_eq(&lock_a, &registry_lock(&a)));
+    let lock_b = registry_lock(&b);
+    assert!(!Arc::ptr_eq(&lock_a, &lock_b));
+
+    // A new lock is created once the previous one is dropped
+    let weak_a = Arc::downgrade(&lock_a);
+    drop(lock_a);
+    assert!(weak_a.upgrade().is_none());
+    let lock_a = registry_lock(&a);
+    assert!(lock_a.try_write().is_ok());
+}
+
+//=============================================================================
+// SyncSharedFile
+//-----------------------------------------------------------------------------
+#[test]
+fn test_syncsharedfile_impl_new() {
+    let test_dir = TestDirUtils::new("test_syncsharedfile_impl_new").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let test_file_path = Path::new(&test_file);
+
+    let shared1 = SyncSharedFile::new(test_file_path).unwrap();
+    assert!(test_file_path.is_file());
+    assert_eq!(
+        shared1.lock_file(),
+        std::fs::canonicalize(test_dir.get_test_file_path(".protected.lock~")).unwrap()
+    );
+
+    // The same lock file reached by another path shares the in-process lock
+    let other_path = test_dir.test_dir().join(".").join("protected");
+    let shared2 = SyncSharedFile::new(&other_path).unwrap();
+    assert!(Arc::ptr_eq(&shared1.local, &shared2.local));
+    assert_eq!(shared1.lock_file(), shared2.lock_file());
+
+    let other_file = test_dir.get_test_file_path("other");
+    let shared3 = SyncSharedFile::new(Path::new(&other_file)).unwrap();
+    assert!(!Arc::ptr_eq(&shared1.local, &shared3.local));
+
+    // Truncation is applied only when the instance is created
+    let mut options = SharedFile::default_options();
+    options.truncate(true);
+    std::fs::write(&test_file, b"123").unwrap();
+    let shared4 = SyncSharedFile::with_options(test_file_path, &options).unwrap();
+    shared4.write().unwrap().write_all(b"456").unwrap();
+    let mut buff = String::new();
+    shared4.read().unwrap().read_to_string(&mut buff).unwrap();
+    assert_eq!(buff, "456");
+}
+
+#[test]
+fn test_syncsharedfile_sync() {
+    fn assert_sync<T: Send + Sync>() {}
+    assert_sync::<SyncSharedFile>();
+}
+
+#[test]
+fn test_syncsharedfile_impl_read_write() {
+    let test_dir = TestDirUtils::new("test_syncsharedfile_impl_read_write").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let shared = SyncSharedFile::new(Path::new(&test_file)).unwrap();
+
+    let mut write = shared.write().unwrap();
+    write.write_all(b"12345").unwrap();
+    assert_eq!(write.file().metadata().unwrap().len(), 5);
+    write.mut_file().sync_all().unwrap();
+    assert_eq!(
+        shared.try_read().err().unwrap().kind(),
+        ErrorKind::WouldBlock
+    );
+    assert_eq!(
+        shared.try_write().err().unwrap().kind(),
+        ErrorKind::WouldBlock
+    );
+    drop(write);
+
+    // Each guard has its own offset
+    let mut read1 = shared.read().unwrap();
+    let mut read2 = shared.try_read().unwrap();
+    let mut buff = [0u8; 2];
+    read1.read_exact(&mut buff).unwrap();
+    assert_eq!(&buff, b"12");
+    read2.seek(SeekFrom::Start(3)).unwrap();
+    read2.read_exact(&mut buff).unwrap();
+    assert_eq!(&buff, b"45");
+    read1.read_exact(&mut buff).unwrap();
+    assert_eq!(&buff, b"34");
+    assert_eq!(read1.file().metadata().unwrap().len(), 5);
+    assert_eq!(
+        shared.try_write().err().unwrap().kind(),
+        ErrorKind::WouldBlock
+    );
+    drop(read1);
+    drop(read2);
+    drop(shared.try_write().unwrap());
+}
+
+#[test]
+fn test_syncsharedfile_impl_read_write_timeout() {
+    let test_dir = TestDirUtils::new("test_syncsharedfile_impl_read_write_timeout").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let test_file_path = Path::new(&test_file);
+    let shared = Arc::new(SyncSharedFile::new(test_file_path).unwrap());
+    let mut other = SharedFile::new(test_file_path).unwrap();
+
+    // Free locks are acquired immediately
+    drop(shared.write_timeout(Duration::from_secs(1)).unwrap());
+    let read1 = shared.read_timeout(Duration::from_secs(1)).unwrap();
+    drop(shared.read_timeout(Duration::from_secs(1)).unwrap());
+
+    // Blocked by the in-process lock
+    let start = Instant::now();
+    let e = shared
+        .write_timeout(Duration::from_millis(50))
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    drop(read1);
+
+    // Blocked by the advisory lock
+    let write = other.write().unwrap();
+    let start = Instant::now();
+    let e = shared
+        .read_timeout(Duration::from_millis(50))
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    drop(write);
+    // The in-process lock was released
+    drop(shared.try_write().unwrap());
+
+    // Released while waiting
+    let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+    let shared2 = Arc::clone(&shared);
+    let t = std::thread::spawn(move || {
+        let mut write = shared2.write().unwrap();
+        write.write_all(b"123").unwrap();
+        locked_tx.send(()).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+    });
+    locked_rx.recv().unwrap();
+    let e = shared.read_timeout(Duration::ZERO).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    let mut read = shared.read_timeout(Duration::from_secs(10)).unwrap();
+    let mut buff = Vec::<u8>::new();
+    read.read_to_end(&mut buff).unwrap();
+    assert_eq!(buff.as_slice(), b"123");
+    drop(read);
+    t.join().unwrap();
+}
+
+#[test]
+fn test_syncsharedfile_impl_sharedfile() {
+    let test_dir = TestDirUtils::new("test_syncsharedfile_impl_sharedfile").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let test_file_path = Path::new(&test_file);
+    let shared = SyncSharedFile::new(test_file_path).unwrap();
+    let mut other = SharedFile::new(test_file_path).unwrap();
+
+    // The OS lock still excludes the other instances inside the process
+    let read = shared.read().unwrap();
+    drop(other.try_read().unwrap());
+    assert_eq!(
+        other.try_write().err().unwrap().kind(),
+        ErrorKind::WouldBlock
+    );
+    drop(read);
+
+    let write = other.write().unwrap();
+    assert_eq!(
+        shared.try_read().err().unwrap().kind(),
+        ErrorKind::WouldBlock
+    );
+    assert_eq!(
+        shared.try_write().err().unwrap().kind(),
+        ErrorKind::WouldBlock
+    );
+    drop(write);
+    drop(shared.try_write().unwrap());
+}
+
+#[test]
+fn test_syncsharedfile_impl_lock_owner() {
+    let test_dir = TestDirUtils::new("test_syncsharedfile_impl_lock_owner").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let shared = SyncSharedFile::new(Path::new(&test_file)).unwrap();
+
+    assert!(shared.lock_owner().unwrap().is_none());
+    let write = shared.write().unwrap();
+    let owner = shared.lock_owner().unwrap().unwrap();
+    assert_eq!(owner.pid, std::process::id());
+    drop(write);
+    assert!(shared.lock_owner().unwrap().is_none());
+}
+
+#[test]
+fn test_syncsharedfile_threads() {
+    let test_dir = TestDirUtils::new("test_syncsharedfile_threads").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let shared = Arc::new(SyncSharedFile::new(Path::new(&test_file)).unwrap());
+    const THREADS: usize = 4;
+
+    // All readers hold the lock at the same time
+    let barrier = Arc::new(Barrier::new(THREADS));
+    let readers: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let shared = Arc::clone(&shared);
+            let barrier = Arc::clone(&barrier);
+            std::thread::spawn(move || {
+                let read = shared.read().unwrap();
+                barrier.wait();
+                assert_eq!(
+                    shared.try_write().err().unwrap().kind(),
+                    ErrorKind::WouldBlock
+                );
+                barrier.wait();
+                drop(read);
+            })
+        })
+        .collect();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    // Only one writer at a time
+    let active = Arc::new(AtomicUsize::new(0));
+    let writers: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let shared = Arc::clone(&shared);
+            let active = Arc::clone(&active);
+            std::thread::spawn(move || {
+                for _ in 0..CHILD_INCREMENTS {
+                    let mut write = shared.write().unwrap();
+                    assert_eq!(active.fetch_add(1, Ordering::SeqCst), 0);
+                    increment_counter(&mut write).unwrap();
+                    assert_eq!(active.fetch_sub(1, Ordering::SeqCst), 1);
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    assert_eq!(
+        read_counter(&mut shared.read().unwrap()).unwrap(),
+        THREADS as u64 * CHILD_INCREMENTS
+    );
+}
+
+#[test]
+fn test_syncsharedfile_threads_processes() {
+    let test_dir = TestDirUtils::new("test_syncsharedfile_threads_processes").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let test_file_path = Path::new(&test_file);
+    let shared = Arc::new(SyncSharedFile::new(test_file_path).unwrap());
+    const THREADS: usize = 4;
+    const PROCESSES: usize = 2;
+
+    // Threads and processes increment the same counter
+    let children: Vec<_> = (0..PROCESSES)
+        .map(|_| ChildProcess::spawn(test_file_path, "increment"))
+        .collect();
+    let threads: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let shared = Arc::clone(&shared);
+            std::thread::spawn(move || {
+                for _ in 0..CHILD_INCREMENTS {
+                    increment_counter(&mut shared.write().unwrap()).unwrap();
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    for mut child in children {
+        assert_eq!(child.result(), "ok");
+        child.finish();
+    }
+    assert_eq!(
+        read_counter(&mut shared.read().unwrap()).unwrap(),
+        (THREADS + PROCESSES) as u64 * CHILD_INCREMENTS
+    );
+
+    // Readers in threads block writers in other processes
+    let barrier = Arc::new(Barrier::new(THREADS + 1));
+    let readers: Vec<_> = (0..THREADS)
+        .map(|_| {
+            let shared = Arc::clone(&shared);
+            let barrier = Arc::clone(&barrier);
+            std::thread::spawn(move || {
+                let read = shared.read().unwrap();
+                barrier.wait();
+                barrier.wait();
+                drop(read);
+            })
+        })
+        .collect();
+    barrier.wait();
+    assert_eq!(run_child(test_file_path, "try_write"), "WouldBlock");
+    assert_eq!(run_child(test_file_path, "try_read"), "ok");
+    barrier.wait();
+    for reader in readers {
+        reader.join().unwrap();
+    }
+
+    // A writer in another process blocks the threads
+    let mut writer = ChildProcess::spawn(test_file_path, "hold_write");
+    assert_eq!(writer.result(), "ok");
+    assert_eq!(
+        shared.try_read().err().unwrap().kind(),
+        ErrorKind::WouldBlock
+    );
+    let done = Arc::new(AtomicBool::new(false));
+    let t_shared = Arc::clone(&shared);
+    let t_done = Arc::clone(&done);
+    let reader = std::thread::spawn(move || {
+        let read = t_shared.read().unwrap();
+        t_done.store(true, Ordering::SeqCst);
+        drop(read);
+    });
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(!done.load(Ordering::SeqCst));
+    writer.finish();
+    reader.join().unwrap();
+    assert!(done.load(Ordering::SeqCst));
+}
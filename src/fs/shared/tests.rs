@@ -33,7 +33,188 @@ use super::*;
 use il2_test_utils::testdir::TestDirUtils;
 use std::ffi::{OsStr, OsString};
 use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader};
 use std::path::Path;
+use std::process::{Child, ChildStdout, Command, Stdio};
+use std::time::Instant;
+
+//=============================================================================
+// Child processes
+//-----------------------------------------------------------------------------
+/// Environment variable with the protected file used by the child process.
+const CHILD_FILE_ENV: &str = "IL2_UTILS_TEST_CHILD_FILE";
+
+/// Environment variable with the action performed by the child process.
+const CHILD_ACTION_ENV: &str = "IL2_UTILS_TEST_CHILD_ACTION";
+
+/// Prefix of the lines that carry the results of the child process.
+const CHILD_RESULT_PREFIX: &str = "child-result:";
+
+/// Number of increments performed by the action `increment`.
+pub(super) const CHILD_INCREMENTS: u64 = 50;
+
+/// Increments the counter stored in the protected file. The counter is
+/// stored as a fixed length decimal number, so the file never shrinks.
+///
+/// Arguments:
+/// - `file`: The protected file. It must be locked for write;
+///
+/// Returns the new value of the counter.
+pub(super) fn increment_counter<T: Read + Write + Seek>(file: &mut T) -> Result<u64> {
+    let value = read_counter(file)? + 1;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(format!("{:020}", value).as_bytes())?;
+    Ok(value)
+}
+
+/// Reads the counter stored in the protected file. An empty file holds 0.
+///
+/// Arguments:
+/// - `file`: The protected file. It must be locked;
+pub(super) fn read_counter<T: Read + Seek>(file: &mut T) -> Result<u64> {
+    let mut buff = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut buff)?;
+    if buff.is_empty() {
+        Ok(0)
+    } else {
+        buff.parse()
+            .map_err(|_| Error::new(ErrorKind::InvalidData, "Invalid counter."))
+    }
+}
+
+/// Formats the result of an operation of the child process.
+fn child_result<T>(result: &Result<T>) -> String {
+    match result {
+        Ok(_) => String::from("ok"),
+        Err(e) => format!("{:?}", e.kind()),
+    }
+}
+
+/// Entry point of the child processes used by the multi-process tests. This
+/// test does nothing unless it is spawned by [`ChildProcess::spawn()`].
+///
+/// The actions `try_read` and `try_write` report the result of the attempt
+/// and exit. The actions `hold_read` and `hold_write` report the result of
+/// the blocking lock and keep it until a line is received from the parent.
+/// The action `increment` calls [`increment_counter()`]
+/// [`CHILD_INCREMENTS`] times, each one with its own write lock. The action
+/// `hold_plain_write` locks the given file itself with a plain exclusive lock
+/// of `fd-lock`, just like the previous versions of this library, and keeps
+/// it until a line is received from the parent.
+#[test]
+fn test_child_process() {
+    let file = match std::env::var_os(CHILD_FILE_ENV) {
+        Some(file) => file,
+        None => return,
+    };
+    let action = std::env::var(CHILD_ACTION_ENV).unwrap();
+    let wait_parent = || {
+        let mut line = String::new();
+        std::io::stdin().read_line(&mut line).unwrap();
+    };
+    if action == "hold_plain_write" {
+        let file = OpenOptions::new().read(true).write(true).open(&file);
+        let mut lock = fd_lock::RwLock::new(file.unwrap());
+        let guard = lock.write();
+        println!("{}{}", CHILD_RESULT_PREFIX, child_result(&guard));
+        wait_parent();
+        return;
+    }
+    let mut shared = SharedFile::new(Path::new(&file)).unwrap();
+    match action.as_str() {
+        "try_read" => println!(
+            "{}{}",
+            CHILD_RESULT_PREFIX,
+            child_result(&shared.try_read())
+        ),
+        "try_write" => println!(
+            "{}{}",
+            CHILD_RESULT_PREFIX,
+            child_result(&shared.try_write())
+        ),
+        "hold_read" => {
+            let lock = shared.read();
+            println!("{}{}", CHILD_RESULT_PREFIX, child_result(&lock));
+            wait_parent();
+        }
+        "hold_write" => {
+            let lock = shared.write();
+            println!("{}{}", CHILD_RESULT_PREFIX, child_result(&lock));
+            wait_parent();
+        }
+        "increment" => {
+            for _ in 0..CHILD_INCREMENTS {
+                increment_counter(&mut shared.write().unwrap()).unwrap();
+            }
+            println!("{}ok", CHILD_RESULT_PREFIX);
+        }
+        _ => panic!("Unknown action {}.", action),
+    }
+}
+
+/// A child process that runs [`test_child_process()`].
+pub(super) struct ChildProcess {
+    child: Child,
+    stdout: BufReader<ChildStdout>,
+}
+
+impl ChildProcess {
+    /// Spawns a new child process.
+    ///
+    /// Arguments:
+    /// - `file`: The protected file;
+    /// - `action`: The action performed by the child;
+    pub(super) fn spawn(file: &Path, action: &str) -> Self {
+        let module = module_path!().split_once("::").unwrap().1;
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .arg("--exact")
+            .arg(format!("{}::test_child_process", module))
+            .arg("--nocapture")
+            .arg("--test-threads=1")
+            .env(CHILD_FILE_ENV, file)
+            .env(CHILD_ACTION_ENV, action)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        Self { child, stdout }
+    }
+
+    /// Waits for the next result reported by the child.
+    pub(super) fn result(&mut self) -> String {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            assert!(self.stdout.read_line(&mut line).unwrap() > 0);
+            // The test harness may write on the same line
+            if let Some((_, result)) = line.trim_end().split_once(CHILD_RESULT_PREFIX) {
+                return String::from(result);
+            }
+        }
+    }
+
+    /// Releases the child and waits for its termination.
+    pub(super) fn finish(mut self) {
+        if let Some(stdin) = self.child.stdin.as_mut() {
+            let _ = stdin.write_all(b"\n");
+        }
+        assert!(self.child.wait().unwrap().success());
+    }
+}
+
+/// Runs a child process that performs a single action and returns its result.
+///
+/// Arguments:
+/// - `file`: The protected file;
+/// - `action`: The action performed by the child;
+pub(super) fn run_child(file: &Path, action: &str) -> String {
+    let mut child = ChildProcess::spawn(file, action);
+    let result = child.result();
+    child.finish();
+    result
+}
 
 //=============================================================================
 // SharedFileLockNameBuilder
@@ -143,6 +324,76 @@ fn test_defaultsharedfilelocknamebuilder_namebuilder_create_lock_file_name() {
     assert_eq!(b.create_lock_file_name(name), OsStr::new(".z.lock~"));
 }
 
+//=============================================================================
+// Secrets
+//-----------------------------------------------------------------------------
+/// A reader that returns its data in small chunks and fails once it reaches
+/// its end if required.
+struct ChunkedReader<'a> {
+    data: &'a [u8],
+    fail: bool,
+}
+
+impl<'a> Read for ChunkedReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.data.is_empty() && self.fail {
+            return Err(Error::other("Reader failed."));
+        }
+        let n = buf.len().min(self.data.len()).min(3);
+        buf[..n].copy_from_slice(&self.data[..n]);
+        self.data = &self.data[n..];
+        Ok(n)
+    }
+}
+
+#[test]
+fn test_read_into_secret() {
+    let data = b"0123456789";
+
+    let mut secret = SecretBytes::new(10, false);
+    let mut reader = ChunkedReader { data, fail: true };
+    assert_eq!(read_into_secret(&mut reader, &mut secret).unwrap(), 10);
+    assert_eq!(secret.value(), data);
+
+    // Short reader
+    let mut secret = SecretBytes::new(16, false);
+    let mut reader = ChunkedReader { data, fail: false };
+    assert_eq!(read_into_secret(&mut reader, &mut secret).unwrap(), 10);
+    assert_eq!(&secret.buffer()[..10], data);
+
+    // The partial data is zeroized when the reader fails
+    let mut secret = SecretBytes::new(16, false);
+    secret.mut_buffer().copy_from_slice(&[0xFF; 16]);
+    let mut reader = ChunkedReader { data, fail: true };
+    let e = read_into_secret(&mut reader, &mut secret).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Other);
+    assert_eq!(&secret.buffer()[..10], &[0; 10]);
+    // Untouched bytes are preserved
+    assert_eq!(&secret.buffer()[10..], &[0xFF; 6]);
+}
+
+#[test]
+fn test_read_file_to_secret() {
+    let test_dir = TestDirUtils::new("test_read_file_to_secret").unwrap();
+    let test_file = test_dir.create_test_file("secret", b"0123456789").unwrap();
+    let mut file = OpenOptions::new().read(true).open(&test_file).unwrap();
+
+    file.seek(SeekFrom::Start(4)).unwrap();
+    let secret = read_file_to_secret(&mut file, false).unwrap();
+    assert_eq!(secret.value(), b"0123456789");
+    assert!(!secret.locked());
+    assert_eq!(file.stream_position().unwrap(), 10);
+
+    let secret = read_file_to_secret(&mut file, true).unwrap();
+    assert_eq!(secret.value(), b"0123456789");
+    assert_eq!(secret.locked(), SecretBytes::lock_supported());
+
+    let empty = test_dir.create_test_file("empty", b"").unwrap();
+    let mut file = OpenOptions::new().read(true).open(&empty).unwrap();
+    let secret = read_file_to_secret(&mut file, true).unwrap();
+    assert!(secret.is_empty());
+}
+
 //=============================================================================
 // SharedFileReadLockGuard
 //-----------------------------------------------------------------------------
@@ -152,8 +403,8 @@ fn test_sharedfilereadlockguard_impl() {
     let lock_file = test_dir.create_test_file("target.lock", b"1").unwrap();
     let target_file = test_dir.create_test_file("target", b"2").unwrap();
 
-    let lock = fd_lock::RwLock::new(File::open(&lock_file).unwrap());
-    let mut lock2 = fd_lock::RwLock::new(File::open(&lock_file).unwrap());
+    let lock = FileLock::open(Path::new(&lock_file)).unwrap();
+    let lock2 = FileLock::open(Path::new(&lock_file)).unwrap();
     let mut target = OpenOptions::new()
         .read(true)
         .write(true)
@@ -162,12 +413,12 @@ fn test_sharedfilereadlockguard_impl() {
     {
         let mut rlock = SharedFileReadLockGuard {
             file: &mut target,
-            _lock: lock.read().unwrap(),
+            lock: lock.lock(FileLockMode::Shared).unwrap(),
         };
         // Cannot write
-        assert!(lock2.try_write().is_err());
+        assert!(lock2.try_lock(FileLockMode::Exclusive).is_err());
         // But can read
-        drop(lock2.read().unwrap());
+        drop(lock2.lock(FileLockMode::Shared).unwrap());
 
         // Check if it is pointing to the correct file by reading it
         let mut buff = Vec::<u8>::new();
@@ -184,10 +435,78 @@ fn test_sharedfilereadlockguard_impl() {
         let size = rlock.file().metadata().unwrap().len() as u64;
         assert_eq!(size, buff_len);
     }
-    let l = lock2.try_write().unwrap();
+    let l = lock2.try_lock(FileLockMode::Exclusive).unwrap();
     drop(l);
 }
 
+#[test]
+fn test_sharedfilereadlockguard_impl_read_to_vec_len() {
+    let test_dir = TestDirUtils::new("test_sharedfilereadlockguard_impl_read_to_vec_len").unwrap();
+    let test_file = test_dir.create_test_file("protected", b"12345").unwrap();
+    let mut shared = SharedFile::new(Path::new(&test_file)).unwrap();
+
+    let mut read = shared.read().unwrap();
+    assert_eq!(read.len().unwrap(), 5);
+    assert!(!read.is_empty().unwrap());
+    // The current position is ignored
+    read.seek(SeekFrom::Start(3)).unwrap();
+    assert_eq!(read.read_to_vec().unwrap(), b"12345");
+    assert_eq!(read.stream_position().unwrap(), 5);
+    assert_eq!(read.read_to_vec().unwrap(), b"12345");
+    drop(read);
+
+    shared.write().unwrap().truncate(0).unwrap();
+    let mut read = shared.read().unwrap();
+    assert_eq!(read.len().unwrap(), 0);
+    assert!(read.is_empty().unwrap());
+    assert!(read.read_to_vec().unwrap().is_empty());
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_sharedfilereadlockguard_impl_try_upgrade() {
+    let test_dir = TestDirUtils::new("test_sharedfilereadlockguard_impl_try_upgrade").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let test_file_path = Path::new(&test_file);
+    let mut shared = SharedFile::new(test_file_path).unwrap();
+
+    // Another process holds a read lock
+    let mut reader = ChildProcess::spawn(test_file_path, "hold_read");
+    assert_eq!(reader.result(), "ok");
+    let read = shared.read().unwrap();
+    let (read, e) = read.try_upgrade().err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    reader.finish();
+
+    // The read lock is still held after the failure
+    assert_eq!(run_child(test_file_path, "try_write"), "WouldBlock");
+    assert_eq!(run_child(test_file_path, "try_read"), "ok");
+
+    let mut write = match read.try_upgrade() {
+        Ok(write) => write,
+        Err((_, e)) => panic!("Unable to upgrade: {:?}", e),
+    };
+    assert_eq!(run_child(test_file_path, "try_read"), "WouldBlock");
+    assert_eq!(run_child(test_file_path, "try_write"), "WouldBlock");
+    write.write_all(b"123").unwrap();
+    drop(write);
+    assert_eq!(run_child(test_file_path, "try_write"), "ok");
+}
+
+#[test]
+#[cfg(not(target_os = "linux"))]
+fn test_sharedfilereadlockguard_impl_try_upgrade() {
+    let test_dir = TestDirUtils::new("test_sharedfilereadlockguard_impl_try_upgrade").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let mut shared = SharedFile::new(Path::new(&test_file)).unwrap();
+
+    let read = shared.read().unwrap();
+    let (read, e) = read.try_upgrade().err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+    // The read lock is kept
+    assert!(read.file().metadata().is_ok());
+}
+
 //=============================================================================
 // SharedFileWriteLockGuard
 //-----------------------------------------------------------------------------
@@ -197,8 +516,8 @@ fn test_sharedfilewritelockguard_impl() {
     let lock_file = test_dir.create_test_file("target.lock", b"1").unwrap();
     let target_file = test_dir.create_test_file("target", b"2").unwrap();
 
-    let mut lock = fd_lock::RwLock::new(File::open(&lock_file).unwrap());
-    let mut lock2 = fd_lock::RwLock::new(File::open(&lock_file).unwrap());
+    let lock = FileLock::open(Path::new(&lock_file)).unwrap();
+    let lock2 = FileLock::open(Path::new(&lock_file)).unwrap();
     let mut target = OpenOptions::new()
         .read(true)
         .write(true)
@@ -207,11 +526,11 @@ fn test_sharedfilewritelockguard_impl() {
     {
         let mut rwlock = SharedFileWriteLockGuard {
             file: &mut target,
-            _lock: lock.write().unwrap(),
+            lock: lock.lock(FileLockMode::Exclusive).unwrap(),
         };
         // Cannot read nor write
-        assert!(lock2.try_write().is_err());
-        drop(lock2.try_read().is_err());
+        assert!(lock2.try_lock(FileLockMode::Exclusive).is_err());
+        assert!(lock2.try_lock(FileLockMode::Shared).is_err());
 
         // Check if it is pointing to the correct file by reading it
         let mut buff = Vec::<u8>::new();
@@ -245,10 +564,165 @@ fn test_sharedfilewritelockguard_impl() {
         rwlock.read_to_end(&mut buff).unwrap();
         assert_eq!(buff.as_slice(), &sample);
     }
-    let l = lock2.try_write().unwrap();
+    let l = lock2.try_lock(FileLockMode::Exclusive).unwrap();
     drop(l);
 }
 
+#[test]
+#[cfg(target_os = "linux")]
+fn test_sharedfilewritelockguard_impl_downgrade() {
+    let test_dir = TestDirUtils::new("test_sharedfilewritelockguard_impl_downgrade").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let test_file_path = Path::new(&test_file);
+    let mut shared = SharedFile::new(test_file_path).unwrap();
+
+    let mut write = shared.write().unwrap();
+    write.write_all(b"123").unwrap();
+    // The reader waits for the lock
+    let mut reader = ChildProcess::spawn(test_file_path, "hold_read");
+    std::thread::sleep(Duration::from_millis(100));
+    let mut read = match write.downgrade() {
+        Ok(read) => read,
+        Err((_, e)) => panic!("Unable to downgrade: {:?}", e),
+    };
+    assert_eq!(reader.result(), "ok");
+    // Writers are still blocked
+    assert_eq!(run_child(test_file_path, "try_write"), "WouldBlock");
+
+    read.seek(SeekFrom::Start(0)).unwrap();
+    let mut buff = Vec::<u8>::new();
+    read.read_to_end(&mut buff).unwrap();
+    assert_eq!(buff.as_slice(), b"123");
+    reader.finish();
+    drop(read);
+    assert_eq!(run_child(test_file_path, "try_write"), "ok");
+}
+
+#[test]
+#[cfg(not(target_os = "linux"))]
+fn test_sharedfilewritelockguard_impl_downgrade() {
+    let test_dir = TestDirUtils::new("test_sharedfilewritelockguard_impl_downgrade").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let mut shared = SharedFile::new(Path::new(&test_file)).unwrap();
+
+    let write = shared.write().unwrap();
+    let (mut write, e) = write.downgrade().err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+    // The write lock is kept
+    write.write_all(b"123").unwrap();
+}
+
+#[test]
+fn test_sharedfilewritelockguard_impl_read_write_secret() {
+    let test_dir =
+        TestDirUtils::new("test_sharedfilewritelockguard_impl_read_write_secret").unwrap();
+    let test_file = test_dir
+        .create_test_file("protected", b"previous key material")
+        .unwrap();
+    let mut shared = SharedFile::new(Path::new(&test_file)).unwrap();
+
+    let key = SecretBytes::with_value(b"secret key", true);
+    let mut write = shared.write().unwrap();
+    assert_eq!(
+        write.read_secret(false).unwrap().value(),
+        b"previous key material"
+    );
+    write.write_secret(&key).unwrap();
+    assert_eq!(write.read_secret(true).unwrap().value(), key.value());
+    drop(write);
+
+    let mut read = shared.read().unwrap();
+    let secret = read.read_secret(true).unwrap();
+    assert_eq!(secret.value(), b"secret key");
+    assert_eq!(secret.locked(), SecretBytes::lock_supported());
+    drop(read);
+    assert_eq!(std::fs::read(&test_file).unwrap(), b"secret key");
+}
+
+#[test]
+fn test_sharedfilewritelockguard_impl_helpers() {
+    let test_dir = TestDirUtils::new("test_sharedfilewritelockguard_impl_helpers").unwrap();
+    let test_file = test_dir
+        .create_test_file("protected", b"0123456789")
+        .unwrap();
+    let mut shared = SharedFile::new(Path::new(&test_file)).unwrap();
+
+    let mut write = shared.write().unwrap();
+    assert_eq!(write.len().unwrap(), 10);
+    assert!(!write.is_empty().unwrap());
+    write.seek(SeekFrom::Start(4)).unwrap();
+    assert_eq!(write.read_to_vec().unwrap(), b"0123456789");
+
+    // Shorter contents truncate the file
+    write.seek(SeekFrom::Start(7)).unwrap();
+    write.replace_with(b"abc").unwrap();
+    assert_eq!(write.stream_position().unwrap(), 3);
+    assert_eq!(write.read_to_vec().unwrap(), b"abc");
+    write.replace_with(b"abcdef").unwrap();
+    assert_eq!(write.read_to_vec().unwrap(), b"abcdef");
+
+    write.seek(SeekFrom::Start(1)).unwrap();
+    write.append(b"gh").unwrap();
+    assert_eq!(write.stream_position().unwrap(), 8);
+    write.append(b"i").unwrap();
+    assert_eq!(write.read_to_vec().unwrap(), b"abcdefghi");
+
+    write.seek(SeekFrom::Start(1)).unwrap();
+    write.truncate(4).unwrap();
+    assert_eq!(write.stream_position().unwrap(), 4);
+    assert_eq!(write.len().unwrap(), 4);
+    write.truncate(6).unwrap();
+    assert_eq!(write.read_to_vec().unwrap(), b"abcd\0\0");
+    write.sync(SyncMode::Data).unwrap();
+    write.sync(SyncMode::All).unwrap();
+    write.truncate(0).unwrap();
+    assert!(write.is_empty().unwrap());
+    write.append(b"xyz").unwrap();
+    drop(write);
+
+    assert_eq!(std::fs::read(&test_file).unwrap(), b"xyz");
+}
+
+//=============================================================================
+// SharedFileRegion
+//-----------------------------------------------------------------------------
+#[test]
+fn test_sharedfileregion_impl_seek() {
+    let mut region = SharedFileRegion::new(FileLockRange { offset: 10, len: 5 });
+    assert_eq!(region.range(), 10..15);
+    assert_eq!(region.seek(SeekFrom::Start(3)).unwrap(), 3);
+    assert_eq!(region.seek(SeekFrom::Current(-1)).unwrap(), 2);
+    assert_eq!(region.seek(SeekFrom::Current(10)).unwrap(), 12);
+    assert_eq!(region.seek(SeekFrom::End(-5)).unwrap(), 0);
+    assert_eq!(region.seek(SeekFrom::End(1)).unwrap(), 6);
+    for pos in &[
+        SeekFrom::End(-6),
+        SeekFrom::Current(-7),
+        SeekFrom::Start(u64::MAX),
+    ] {
+        let e = region.seek(*pos).err().unwrap();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+    assert_eq!(region.position, 6);
+}
+
+#[test]
+fn test_sharedfileregion_impl_prepare() {
+    let test_dir = TestDirUtils::new("test_sharedfileregion_impl_prepare").unwrap();
+    let test_file = test_dir.create_test_file("file", b"0123456789").unwrap();
+    let mut file = File::open(&test_file).unwrap();
+
+    let mut region = SharedFileRegion::new(FileLockRange { offset: 4, len: 4 });
+    assert_eq!(region.prepare(&mut file, 100).unwrap(), 4);
+    assert_eq!(file.stream_position().unwrap(), 4);
+    region.seek(SeekFrom::Start(3)).unwrap();
+    assert_eq!(region.prepare(&mut file, 100).unwrap(), 1);
+    assert_eq!(file.stream_position().unwrap(), 7);
+    assert_eq!(region.prepare(&mut file, 0).unwrap(), 0);
+    region.seek(SeekFrom::Start(5)).unwrap();
+    assert_eq!(region.prepare(&mut file, 100).unwrap(), 0);
+}
+
 //=============================================================================
 // SharedFile
 //-----------------------------------------------------------------------------
@@ -319,6 +793,198 @@ fn test_sharedfile_impl() {
     drop(write2);
 }
 
+#[test]
+fn test_sharedfile_impl_read_write_timeout() {
+    let test_dir = TestDirUtils::new("test_sharedfile_impl_read_write_timeout").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let test_file_path = Path::new(&test_file);
+
+    let mut shared1 = SharedFile::new(test_file_path).unwrap();
+    let mut shared2 = SharedFile::new(test_file_path).unwrap();
+
+    // Free locks are acquired immediately
+    drop(shared1.write_timeout(Duration::from_secs(1)).unwrap());
+    let read1 = shared1.read_timeout(Duration::from_secs(1)).unwrap();
+    drop(shared2.read_timeout(Duration::from_secs(1)).unwrap());
+
+    // Blocked by the read lock
+    let start = Instant::now();
+    let e = shared2
+        .write_timeout(Duration::from_millis(50))
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    drop(read1);
+
+    // Released while waiting
+    let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+    let t = std::thread::spawn(move || {
+        let mut write1 = shared1.write().unwrap();
+        write1.write_all(b"123").unwrap();
+        locked_tx.send(()).unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+    });
+    locked_rx.recv().unwrap();
+    let e = shared2.read_timeout(Duration::ZERO).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    let mut read2 = shared2.read_timeout(Duration::from_secs(10)).unwrap();
+    let mut buff = Vec::<u8>::new();
+    read2.read_to_end(&mut buff).unwrap();
+    assert_eq!(buff.as_slice(), b"123");
+    drop(read2);
+    t.join().unwrap();
+}
+
+/// Returns the sorted names of the entries of a directory.
+fn list_dir(dir: &Path) -> Vec<OsString> {
+    let mut names: Vec<OsString> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Reads the whole protected file using a read lock.
+fn read_shared_file(shared: &mut SharedFile) -> Vec<u8> {
+    let mut read = shared.read().unwrap();
+    read.seek(SeekFrom::Start(0)).unwrap();
+    let mut buff = Vec::<u8>::new();
+    read.read_to_end(&mut buff).unwrap();
+    buff
+}
+
+/// Creates a protected file with the contents "old" and returns two
+/// instances of `SharedFile` pointing to it.
+fn create_replace_test_files(test_dir: &TestDirUtils) -> (SharedFile, SharedFile) {
+    test_dir.reset().unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let test_file_path = Path::new(&test_file);
+    let mut shared1 = SharedFile::new(test_file_path).unwrap();
+    let shared2 = SharedFile::new(test_file_path).unwrap();
+    shared1.write().unwrap().write_all(b"old").unwrap();
+    (shared1, shared2)
+}
+
+#[test]
+fn test_sharedfile_impl_replace_contents() {
+    let test_dir = TestDirUtils::new("test_sharedfile_impl_replace_contents").unwrap();
+    let (mut shared1, mut shared2) = create_replace_test_files(&test_dir);
+    let files = list_dir(test_dir.test_dir());
+    assert_eq!(read_shared_file(&mut shared2), b"old");
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let test_file = test_dir.get_test_file_path("protected");
+        std::fs::set_permissions(&test_file, std::fs::Permissions::from_mode(0o600)).unwrap();
+    }
+
+    shared1
+        .replace_contents(|file| {
+            // The other instance cannot access the file while it is replaced
+            assert!(shared2.try_read().is_err());
+            file.write_all(b"new contents")
+        })
+        .unwrap();
+    assert_eq!(read_shared_file(&mut shared1), b"new contents");
+    // The other instance sees the new file
+    assert_eq!(read_shared_file(&mut shared2), b"new contents");
+    // No temporary file is left behind
+    assert_eq!(list_dir(test_dir.test_dir()), files);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let test_file = test_dir.get_test_file_path("protected");
+        let mode = std::fs::metadata(&test_file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+
+    // Both instances can still write to the new file
+    let mut write2 = shared2.write().unwrap();
+    write2.seek(SeekFrom::Start(0)).unwrap();
+    write2.write_all(b"NEW").unwrap();
+    drop(write2);
+    assert_eq!(read_shared_file(&mut shared1), b"NEW contents");
+}
+
+#[test]
+fn test_sharedfile_impl_replace_contents_external() {
+    let test_dir = TestDirUtils::new("test_sharedfile_impl_replace_contents_external").unwrap();
+    let (mut shared1, mut shared2) = create_replace_test_files(&test_dir);
+    assert_eq!(read_shared_file(&mut shared1), b"old");
+
+    // The file is replaced without replace_contents()
+    let write = shared2.write().unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let temp_file = test_dir.create_test_file("replacement", b"new").unwrap();
+    std::fs::rename(&temp_file, &test_file).unwrap();
+    drop(write);
+
+    // Both instances see the new file
+    assert_eq!(read_shared_file(&mut shared1), b"new");
+    assert_eq!(read_shared_file(&mut shared2), b"new");
+}
+
+#[test]
+fn test_sharedfile_impl_keeps_cursor() {
+    let test_dir = TestDirUtils::new("test_sharedfile_impl_keeps_cursor").unwrap();
+    let (mut shared1, _shared2) = create_replace_test_files(&test_dir);
+
+    let mut write = shared1.write().unwrap();
+    write.seek(SeekFrom::Start(2)).unwrap();
+    drop(write);
+    // The file was not replaced, so it is not reopened
+    let mut write = shared1.write().unwrap();
+    assert_eq!(write.stream_position().unwrap(), 2);
+    drop(write);
+    let mut read = shared1.read().unwrap();
+    assert_eq!(read.stream_position().unwrap(), 2);
+}
+
+#[test]
+fn test_sharedfile_impl_replace_contents_error() {
+    let test_dir = TestDirUtils::new("test_sharedfile_impl_replace_contents_error").unwrap();
+    let (mut shared1, mut shared2) = create_replace_test_files(&test_dir);
+    let files = list_dir(test_dir.test_dir());
+
+    // The writer fails after writing part of the new contents
+    let e = shared1
+        .replace_contents(|file| {
+            file.write_all(b"partial")?;
+            Err(Error::other("Simulated failure."))
+        })
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::Other);
+    assert_eq!(e.to_string(), "Simulated failure.");
+    assert_eq!(read_shared_file(&mut shared1), b"old");
+    assert_eq!(read_shared_file(&mut shared2), b"old");
+    assert_eq!(list_dir(test_dir.test_dir()), files);
+    drop(shared2.try_write().unwrap());
+}
+
+#[test]
+fn test_sharedfile_impl_replace_contents_panic() {
+    let test_dir = TestDirUtils::new("test_sharedfile_impl_replace_contents_panic").unwrap();
+    let (mut shared1, mut shared2) = create_replace_test_files(&test_dir);
+    let files = list_dir(test_dir.test_dir());
+
+    // The writer panics after writing part of the new contents
+    let ret = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        shared1.replace_contents(|file| {
+            file.write_all(b"partial").unwrap();
+            panic!("Simulated crash.");
+        })
+    }));
+    assert!(ret.is_err());
+    assert_eq!(read_shared_file(&mut shared2), b"old");
+    assert_eq!(list_dir(test_dir.test_dir()), files);
+    // The lock was released
+    drop(shared2.try_write().unwrap());
+    assert_eq!(read_shared_file(&mut shared1), b"old");
+}
+
 #[test]
 fn test_sharedfile_default_options() {
     let options = SharedFile::default_options();
@@ -331,6 +997,114 @@ fn test_sharedfile_default_options() {
     assert_eq!(format!("{:?}", options), format!("{:?}", exp_options));
 }
 
+#[test]
+#[cfg(target_os = "linux")]
+fn test_sharedfile_impl_lock_range() {
+    let test_dir = TestDirUtils::new("test_sharedfile_impl_lock_range").unwrap();
+    let test_file = test_dir
+        .create_test_file("protected", b"0123456789abcdefghij")
+        .unwrap();
+    let test_file_path = Path::new(&test_file);
+    let mut shared1 = SharedFile::new(test_file_path).unwrap();
+    let mut shared2 = SharedFile::new(test_file_path).unwrap();
+    let mut shared3 = SharedFile::new(test_file_path).unwrap();
+
+    // Reads are limited to the region
+    let mut read = shared1.lock_range_read(5, 5).unwrap();
+    assert_eq!(read.range(), 5..10);
+    let mut buff = Vec::new();
+    read.read_to_end(&mut buff).unwrap();
+    assert_eq!(buff.as_slice(), b"56789");
+    read.seek(SeekFrom::End(-2)).unwrap();
+    buff.clear();
+    read.read_to_end(&mut buff).unwrap();
+    assert_eq!(buff.as_slice(), b"89");
+
+    // Independent regions and whole file locks
+    let mut write = shared2.try_lock_range_write(0, 5).unwrap();
+    let e = shared3.try_lock_range_write(8, 4).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    let e = shared3.try_lock_range_read(4, 1).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WouldBlock);
+    drop(shared3.try_lock_range_read(10, 10).unwrap());
+    assert_eq!(
+        shared3.try_read().err().unwrap().kind(),
+        ErrorKind::WouldBlock
+    );
+
+    // Writes are limited to the region
+    write.write_all(b"ABC").unwrap();
+    let e = write.write_all(b"DEF").err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::WriteZero);
+    write.seek(SeekFrom::Start(1)).unwrap();
+    buff.clear();
+    write.read_to_end(&mut buff).unwrap();
+    assert_eq!(buff.as_slice(), b"BCDE");
+    drop(write);
+    drop(read);
+
+    // Regions beyond the end of the file
+    let mut tail = shared1.lock_range_write(20, 10).unwrap();
+    tail.write_all(b"klm").unwrap();
+    drop(tail);
+    let mut read = shared2.read().unwrap();
+    read.seek(SeekFrom::Start(0)).unwrap();
+    buff.clear();
+    read.read_to_end(&mut buff).unwrap();
+    assert_eq!(buff.as_slice(), b"ABCDE56789abcdefghijklm");
+    drop(read);
+
+    let e = shared1.lock_range_read(0, 0).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    let e = shared1.try_lock_range_write(u64::MAX, 1).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::InvalidInput);
+}
+
+#[test]
+#[cfg(not(target_os = "linux"))]
+fn test_sharedfile_impl_lock_range() {
+    let test_dir = TestDirUtils::new("test_sharedfile_impl_lock_range").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let mut shared = SharedFile::new(Path::new(&test_file)).unwrap();
+
+    let e = shared.lock_range_read(0, 5).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+    let e = shared.lock_range_write(0, 5).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+}
+
+#[test]
+fn test_sharedfile_impl_lock_owner() {
+    let test_dir = TestDirUtils::new("test_sharedfile_impl_lock_owner").unwrap();
+    let test_file = test_dir.get_test_file_path("protected");
+    let test_file_path = Path::new(&test_file);
+    let mut shared = SharedFile::new(test_file_path).unwrap();
+    let shared2 = SharedFile::new(test_file_path).unwrap();
+    assert_eq!(
+        shared.lock_file(),
+        Path::new(&test_dir.get_test_file_path(".protected.lock~"))
+    );
+
+    assert!(shared.lock_owner().unwrap().is_none());
+    let lock = shared.read().unwrap();
+    assert!(shared2.lock_owner().unwrap().is_none());
+    drop(lock);
+    let lock = shared.write().unwrap();
+    let owner = shared2.lock_owner().unwrap().unwrap();
+    assert_eq!(owner.pid, std::process::id());
+    drop(lock);
+    assert!(shared2.lock_owner().unwrap().is_none());
+
+    // Held by another process
+    let mut writer = ChildProcess::spawn(test_file_path, "hold_write");
+    assert_eq!(writer.result(), "ok");
+    let owner = shared.lock_owner().unwrap().unwrap();
+    assert_eq!(owner.pid, writer.child.id());
+    assert_eq!(lock_file_owner(shared.lock_file()).unwrap().unwrap(), owner);
+    writer.finish();
+    assert!(shared.lock_owner().unwrap().is_none());
+}
+
 //=============================================================================
 // SharedDirectory
 //-----------------------------------------------------------------------------
@@ -363,3 +1137,316 @@ fn test_shared_directory() {
     let lock1 = shared1.write().unwrap();
     drop(lock1);
 }
+
+#[test]
+fn test_shared_directory_read_write_timeout() {
+    let test_dir = TestDirUtils::new("test_shared_directory_read_write_timeout").unwrap();
+    test_dir.reset().unwrap();
+
+    let mut shared1 = SharedDirectory::new(test_dir.test_dir()).unwrap();
+    let mut shared2 = SharedDirectory::new(test_dir.test_dir()).unwrap();
+
+    let lock1 = shared1.write_timeout(Duration::from_secs(1)).unwrap();
+    let e = shared2
+        .read_timeout(Duration::from_millis(20))
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    let e = shared2
+        .write_timeout(Duration::from_millis(20))
+        .err()
+        .unwrap();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+    drop(lock1);
+
+    let lock2 = shared2.read_timeout(Duration::from_secs(1)).unwrap();
+    drop(shared1.read_timeout(Duration::from_secs(1)).unwrap());
+    assert_eq!(
+        shared1
+            .write_timeout(Duration::from_millis(20))
+            .err()
+            .unwrap()
+            .kind(),
+        ErrorKind::TimedOut
+    );
+    drop(lock2);
+    drop(shared1.write_timeout(Duration::from_secs(1)).unwrap());
+}
+
+#[test]
+fn test_shared_directory_lock_owner() {
+    let test_dir = TestDirUtils::new("test_shared_directory_lock_owner").unwrap();
+    test_dir.reset().unwrap();
+
+    let mut shared1 = SharedDirectory::new(test_dir.test_dir()).unwrap();
+    let shared2 = SharedDirectory::new(test_dir.test_dir()).unwrap();
+    assert_eq!(
+        shared1.lock_file(),
+        test_dir
+            .test_dir()
+            .join(SharedDirectory::DEFAULT_LOCK_FILE_NAME)
+    );
+
+    assert!(shared2.lock_owner().unwrap().is_none());
+    let lock1 = shared1.write().unwrap();
+    let owner = shared2.lock_owner().unwrap().unwrap();
+    assert_eq!(owner.pid, std::process::id());
+    drop(lock1);
+    assert!(shared2.lock_owner().unwrap().is_none());
+}
+
+#[test]
+fn test_shareddirectoryscope_impl_resolve() {
+    let test_dir = TestDirUtils::new("test_shareddirectoryscope_impl_resolve").unwrap();
+    test_dir.reset().unwrap();
+    let lock_file = test_dir
+        .test_dir()
+        .join(SharedDirectory::DEFAULT_LOCK_FILE_NAME);
+    let scope = SharedDirectoryScope::new(test_dir.test_dir(), &lock_file);
+
+    assert_eq!(
+        scope.resolve(Path::new("file")).unwrap(),
+        test_dir.test_dir().join("file")
+    );
+    assert_eq!(
+        scope.resolve(Path::new("sub/file")).unwrap(),
+        test_dir.test_dir().join("sub").join("file")
+    );
+    for name in &[
+        "",
+        "..",
+        "../file",
+        "sub/../../file",
+        "./file",
+        "/file",
+        SharedDirectory::DEFAULT_LOCK_FILE_NAME,
+    ] {
+        assert_eq!(
+            scope.resolve(Path::new(name)).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_shareddirectoryscope_impl_resolve_symlink() {
+    let test_dir = TestDirUtils::new("test_shareddirectoryscope_impl_resolve_symlink").unwrap();
+    test_dir.reset().unwrap();
+    let protected = test_dir.test_dir().join("protected");
+    std::fs::create_dir(&protected).unwrap();
+    std::fs::create_dir(protected.join("sub")).unwrap();
+    std::fs::write(test_dir.test_dir().join("outside"), b"secret").unwrap();
+    std::os::unix::fs::symlink(test_dir.test_dir(), protected.join("up")).unwrap();
+    std::os::unix::fs::symlink(
+        test_dir.test_dir().join("outside"),
+        protected.join("sub").join("link"),
+    )
+    .unwrap();
+    let lock_file = protected.join(SharedDirectory::DEFAULT_LOCK_FILE_NAME);
+    let scope = SharedDirectoryScope::new(&protected, &lock_file);
+
+    for name in &["up", "up/outside", "sub/link"] {
+        assert_eq!(
+            scope.resolve(Path::new(name)).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+    assert!(scope.resolve(Path::new("sub/file")).is_ok());
+}
+
+#[test]
+fn test_shareddirectoryreadlockguard_impl_list_open() {
+    let test_dir = TestDirUtils::new("test_shareddirectoryreadlockguard_impl_list_open").unwrap();
+    test_dir.reset().unwrap();
+    test_dir.create_test_file("b", b"content b").unwrap();
+    test_dir.create_test_file("a", b"content a").unwrap();
+
+    let mut shared = SharedDirectory::new(test_dir.test_dir()).unwrap();
+    let lock = shared.read().unwrap();
+    assert_eq!(lock.directory(), test_dir.test_dir());
+    assert_eq!(
+        lock.list().unwrap(),
+        vec![OsString::from("a"), OsString::from("b")]
+    );
+
+    let mut file = lock.open("a").unwrap();
+    let mut content = String::new();
+    file.read_to_string(&mut content).unwrap();
+    assert_eq!(content, "content a");
+    assert!(file.write_all(b"x").is_err());
+
+    assert_eq!(lock.open("c").unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(
+        lock.open(SharedDirectory::DEFAULT_LOCK_FILE_NAME)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidInput
+    );
+    assert_eq!(
+        lock.open("../a").unwrap_err().kind(),
+        ErrorKind::InvalidInput
+    );
+}
+
+#[test]
+fn test_shareddirectorywritelockguard_impl_file_operations() {
+    let test_dir =
+        TestDirUtils::new("test_shareddirectorywritelockguard_impl_file_operations").unwrap();
+    test_dir.reset().unwrap();
+    let lock_file = PathBuf::from(test_dir.get_test_file_path("dir.lock"));
+    let protected = test_dir.test_dir().join("protected");
+
+    let mut shared = SharedDirectory::with_lock_file_path(&protected, &lock_file, false).unwrap();
+    let lock = shared.write().unwrap();
+    assert!(lock.list().unwrap().is_empty());
+
+    let mut file = lock.create("a").unwrap();
+    file.write_all(b"content a").unwrap();
+    drop(file);
+    let mut file = lock.open("a").unwrap();
+    file.write_all(b"C").unwrap();
+    drop(file);
+    assert_eq!(std::fs::read(protected.join("a")).unwrap(), b"Content a");
+    assert_eq!(lock.list().unwrap(), vec![OsString::from("a")]);
+
+    lock.rename("a", "b").unwrap();
+    assert_eq!(lock.list().unwrap(), vec![OsString::from("b")]);
+    assert_eq!(lock.open("a").unwrap_err().kind(), ErrorKind::NotFound);
+
+    lock.remove("b").unwrap();
+    assert!(lock.list().unwrap().is_empty());
+    assert_eq!(lock.remove("b").unwrap_err().kind(), ErrorKind::NotFound);
+
+    // Nothing can escape the protected directory.
+    lock.create("a").unwrap();
+    for name in &["../dir.lock", "../x", "/x"] {
+        assert_eq!(
+            lock.create(name).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            lock.remove(name).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            lock.rename("a", name).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+    assert!(lock_file.is_file());
+    assert!(!test_dir.test_dir().join("x").exists());
+    assert_eq!(lock.list().unwrap(), vec![OsString::from("a")]);
+}
+
+#[test]
+fn test_shareddirectorywritelockguard_impl_lock_file() {
+    let test_dir = TestDirUtils::new("test_shareddirectorywritelockguard_impl_lock_file").unwrap();
+    test_dir.reset().unwrap();
+
+    let mut shared = SharedDirectory::new(test_dir.test_dir()).unwrap();
+    let lock = shared.write().unwrap();
+    lock.create("a").unwrap();
+    assert_eq!(
+        lock.rename(SharedDirectory::DEFAULT_LOCK_FILE_NAME, "b")
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidInput
+    );
+    assert_eq!(
+        lock.rename("a", SharedDirectory::DEFAULT_LOCK_FILE_NAME)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidInput
+    );
+    assert_eq!(
+        lock.remove(SharedDirectory::DEFAULT_LOCK_FILE_NAME)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidInput
+    );
+    assert_eq!(
+        lock.create(SharedDirectory::DEFAULT_LOCK_FILE_NAME)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidInput
+    );
+    assert!(test_dir
+        .test_dir()
+        .join(SharedDirectory::DEFAULT_LOCK_FILE_NAME)
+        .is_file());
+    assert_eq!(lock.list().unwrap(), vec![OsString::from("a")]);
+}
+
+//=============================================================================
+// Lock file maintenance
+//-----------------------------------------------------------------------------
+#[test]
+fn test_is_default_lock_file_name() {
+    assert!(is_default_lock_file_name(OsStr::new(".file.lock~")));
+    assert!(is_default_lock_file_name(OsStr::new(".a.lock~")));
+    assert!(is_default_lock_file_name(OsStr::new(
+        SharedDirectory::DEFAULT_LOCK_FILE_NAME
+    )));
+    assert!(!is_default_lock_file_name(OsStr::new(".lock~")));
+    assert!(!is_default_lock_file_name(OsStr::new("file.lock~")));
+    assert!(!is_default_lock_file_name(OsStr::new(".file.lock")));
+    assert!(!is_default_lock_file_name(OsStr::new("file")));
+}
+
+#[test]
+#[cfg(unix)]
+fn test_remove_orphaned_lock_files() {
+    let test_dir = TestDirUtils::new("test_remove_orphaned_lock_files").unwrap();
+    test_dir.reset().unwrap();
+    let dir = test_dir.test_dir();
+
+    let mut shared_a = SharedFile::new(&dir.join("a")).unwrap();
+    let mut shared_b = SharedFile::new(&dir.join("b")).unwrap();
+    let mut shared_dir = SharedDirectory::new(dir).unwrap();
+    let lock_file_a = shared_a.lock_file().to_path_buf();
+    let lock_file_b = shared_b.lock_file().to_path_buf();
+    let lock_file_dir = shared_dir.lock_file().to_path_buf();
+    for name in &[".c", "c.lock~", ".lock~"] {
+        test_dir.create_test_file(name, b"").unwrap();
+    }
+
+    let lock_a = shared_a.read().unwrap();
+    let mut removed = remove_orphaned_lock_files(dir).unwrap();
+    removed.sort();
+    assert_eq!(removed, vec![lock_file_b.clone(), lock_file_dir.clone()]);
+    assert!(lock_file_a.exists());
+    assert!(!lock_file_b.exists());
+    assert!(!lock_file_dir.exists());
+    for name in &[".c", "c.lock~", ".lock~"] {
+        assert!(dir.join(name).exists());
+    }
+    assert!(!remove_orphaned_lock_file(&lock_file_a).unwrap());
+    drop(lock_a);
+    assert!(remove_orphaned_lock_file(&lock_file_a).unwrap());
+    assert!(!remove_orphaned_lock_file(&lock_file_a).unwrap());
+
+    // The instances still work after the removal
+    let mut shared_b2 = SharedFile::new(&dir.join("b")).unwrap();
+    let lock_b = shared_b.write().unwrap();
+    assert!(shared_b2.try_read().is_err());
+    drop(lock_b);
+    drop(shared_b2.try_write().unwrap());
+    drop(shared_a.write().unwrap());
+    drop(shared_dir.write().unwrap());
+}
+
+#[test]
+#[cfg(not(unix))]
+fn test_remove_orphaned_lock_files() {
+    let test_dir = TestDirUtils::new("test_remove_orphaned_lock_files").unwrap();
+    test_dir.reset().unwrap();
+    let dir = test_dir.test_dir();
+
+    let shared = SharedFile::new(&dir.join("a")).unwrap();
+    let e = remove_orphaned_lock_files(dir).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+    let e = remove_orphaned_lock_file(shared.lock_file()).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::Unsupported);
+    assert!(shared.lock_file().exists());
+}